@@ -0,0 +1,154 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+// Compares the collision grid against checking every pair.
+// Run with "cargo bench --bench collision".
+
+#[allow(dead_code)]
+#[path = "../src/grid.rs"]
+mod grid;
+// Its tests are not run from here.
+#[allow(dead_code, unused_imports)]
+#[path = "../src/shape.rs"]
+mod shape;
+
+use bevy::math::{Affine2, Vec2};
+use grid::{Grid, Layers};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use shape::{Placed, Shape};
+use std::time::{Duration, Instant};
+
+const FIELD: Vec2 = Vec2::new(16.0 / 9.0, 1.0);
+const CELL_SIZE: f32 = 0.15;
+const ENEMIES: usize = 30;
+const PLAYER_BULLETS: usize = 100;
+const BULLET_COUNTS: [usize; 5] = [100, 1000, 2000, 5000, 10000];
+const FRAMES: u32 = 20;
+
+struct Body {
+    shape: Placed,
+    layers: Layers,
+}
+
+fn scene(rng: &mut StdRng, enemy_bullets: usize) -> Vec<Body> {
+    let mut bodies = Vec::new();
+    let mut add = |count: usize, shape: Shape, layers: Layers| {
+        for _ in 0..count {
+            let pos = Vec2::new(rng.gen_range(0.0..FIELD.x), rng.gen_range(0.0..FIELD.y));
+            let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+            let mut placed = Vec::new();
+            shape.place(Affine2::from_angle_translation(angle, pos), &mut placed);
+            bodies.extend(placed.into_iter().map(|shape| Body { shape, layers }));
+        }
+    };
+    add(
+        1,
+        Shape::Box {
+            size: Vec2::new(0.13, 0.07),
+        },
+        Layers::PLAYER,
+    );
+    add(ENEMIES, Shape::Circle { radius: 0.045 }, Layers::ENEMY);
+    add(
+        PLAYER_BULLETS,
+        Shape::Circle { radius: 0.01 },
+        Layers::PLAYER_BULLET,
+    );
+    add(
+        enemy_bullets,
+        Shape::Capsule {
+            radius: 0.005,
+            length: 0.01,
+        },
+        Layers::ENEMY_BULLET,
+    );
+    return bodies;
+}
+
+#[derive(Clone, Copy)]
+enum Scenario {
+    // The player against enemies and their bullets,
+    // and every player bullet against enemies and enemy bullets.
+    Player,
+    // On top of that, every enemy bullet looks for player bullets.
+    AllBullets,
+}
+
+fn queries(bodies: &[Body], scenario: Scenario) -> impl Iterator<Item = (usize, Layers)> + '_ {
+    return bodies.iter().enumerate().filter_map(move |(index, body)| {
+        match (body.layers, scenario) {
+            (Layers::PLAYER, _) => Some((index, Layers::ENEMY | Layers::ENEMY_BULLET)),
+            (Layers::PLAYER_BULLET, _) => Some((index, Layers::ENEMY | Layers::ENEMY_BULLET)),
+            (Layers::ENEMY_BULLET, Scenario::AllBullets) => Some((index, Layers::PLAYER_BULLET)),
+            _ => None,
+        }
+    });
+}
+
+fn brute_force(bodies: &[Body], scenario: Scenario) -> usize {
+    let mut hits = 0;
+    for (index, mask) in queries(bodies, scenario) {
+        let body = &bodies[index];
+        hits += bodies
+            .iter()
+            .filter(|other| other.layers.intersects(mask) && body.shape.overlaps(&other.shape))
+            .count();
+    }
+    return hits;
+}
+
+fn with_grid(grid: &mut Grid<usize>, bodies: &[Body], scenario: Scenario) -> usize {
+    grid.clear();
+    for (index, body) in bodies.iter().enumerate() {
+        grid.insert(index, body.shape, body.layers);
+    }
+
+    let mut hits = 0;
+    let mut found = Vec::new();
+    for (index, mask) in queries(bodies, scenario) {
+        found.clear();
+        grid.query(&bodies[index].shape, mask, &mut found);
+        hits += found.len();
+    }
+    return hits;
+}
+
+fn measure(mut frame: impl FnMut() -> usize) -> (Duration, usize) {
+    let start = Instant::now();
+    let mut hits = 0;
+    for _ in 0..FRAMES {
+        hits = frame();
+    }
+    return (start.elapsed() / FRAMES, hits);
+}
+
+fn main() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut grid = Grid::new(CELL_SIZE);
+
+    for (name, scenario) in [
+        ("player", Scenario::Player),
+        ("all bullets", Scenario::AllBullets),
+    ] {
+        println!(
+            "{:<12} {:>14} {:>14} {:>14} {:>8}",
+            "", "enemy bullets", "brute force", "grid", "speedup"
+        );
+        for count in BULLET_COUNTS {
+            let bodies = scene(&mut rng, count);
+            let (brute_time, brute_hits) = measure(|| brute_force(&bodies, scenario));
+            let (grid_time, grid_hits) = measure(|| with_grid(&mut grid, &bodies, scenario));
+            assert_eq!(brute_hits, grid_hits, "the grid missed or invented a hit");
+
+            println!(
+                "{:<12} {:>14} {:>14?} {:>14?} {:>7.1}x",
+                name,
+                count,
+                brute_time,
+                grid_time,
+                brute_time.as_secs_f64() / grid_time.as_secs_f64()
+            );
+        }
+    }
+}
@@ -0,0 +1,177 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+// Aiming math is kept free of ECS state and takes its RNG as a parameter,
+// so hit rates against a moving target can be measured headlessly.
+
+use bevy::math::Vec2;
+use rand::Rng;
+
+// Time after which a bullet fired now can meet a target that keeps its velocity.
+pub fn intercept_time(
+    shooter: Vec2,
+    target: Vec2,
+    target_velocity: Vec2,
+    bullet_speed: f32,
+) -> Option<f32> {
+    let offset = target - shooter;
+    let a = target_velocity.length_squared() - bullet_speed * bullet_speed;
+    let b = 2.0 * offset.dot(target_velocity);
+    let c = offset.length_squared();
+
+    if a.abs() < f32::EPSILON {
+        if b >= 0.0 {
+            return None;
+        }
+        return Some(-c / b);
+    }
+
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let root = discriminant.sqrt();
+    let t1 = (-b - root) / (2.0 * a);
+    let t2 = (-b + root) / (2.0 * a);
+    return [t1, t2]
+        .into_iter()
+        .filter(|t| *t >= 0.0)
+        .min_by(|a, b| a.total_cmp(b));
+}
+
+// Falls back to the current position when the target cannot be caught.
+pub fn predict(shooter: Vec2, target: Vec2, target_velocity: Vec2, bullet_speed: f32) -> Vec2 {
+    return match intercept_time(shooter, target, target_velocity, bullet_speed) {
+        Some(t) => target + target_velocity * t,
+        None => target,
+    };
+}
+
+// Points to both sides of the line of fire, blocking both escape directions.
+pub fn cage(shooter: Vec2, point: Vec2, width: f32) -> [Vec2; 2] {
+    let side = (point - shooter).perp().normalize_or_zero() * width;
+    return [point + side, point - side];
+}
+
+pub fn scatter(shooter: Vec2, point: Vec2, error: f32, rng: &mut impl Rng) -> Vec2 {
+    if error <= 0.0 {
+        return point;
+    }
+    let side = (point - shooter).perp().normalize_or_zero();
+    return point + side * rng.gen_range(-error..error);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::f32::consts::TAU;
+
+    const STEP: f32 = 1.0 / 60.0;
+    const BULLET_SPEED: f32 = 1.2;
+    const TARGET_SPEED: f32 = 0.4;
+    // About the size of the player's core.
+    const HIT_RADIUS: f32 = 0.02;
+
+    // Steps a bullet fired towards the point and a target that keeps its velocity.
+    fn hits(shooter: Vec2, point: Vec2, target: Vec2, velocity: Vec2) -> bool {
+        let dir = (point - shooter).normalize_or_zero();
+        let (mut bullet, mut target) = (shooter, target);
+        for _ in 0..(3.0 / STEP) as usize {
+            bullet += dir * BULLET_SPEED * STEP;
+            target += velocity * STEP;
+            if bullet.distance(target) < HIT_RADIUS {
+                return true;
+            }
+        }
+        return false;
+    }
+
+    // Targets all around the shooter, each one moving its own way.
+    fn targets() -> Vec<(Vec2, Vec2)> {
+        let mut targets = Vec::new();
+        for i in 0..12 {
+            for j in 0..8 {
+                let target = Vec2::from_angle(i as f32 * TAU / 12.0) * 0.8;
+                let velocity = Vec2::from_angle(j as f32 * TAU / 8.0 + 0.3) * TARGET_SPEED;
+                targets.push((target, velocity));
+            }
+        }
+        return targets;
+    }
+
+    fn hit_rate(aim: impl Fn(Vec2, Vec2) -> Vec2) -> f32 {
+        let targets = targets();
+        let hit = targets
+            .iter()
+            .filter(|(target, velocity)| {
+                hits(Vec2::ZERO, aim(*target, *velocity), *target, *velocity)
+            })
+            .count();
+        return hit as f32 / targets.len() as f32;
+    }
+
+    #[test]
+    fn intercept_time_meets_target() {
+        for (target, velocity) in targets() {
+            let t = intercept_time(Vec2::ZERO, target, velocity, BULLET_SPEED).unwrap();
+            let meeting = target + velocity * t;
+            assert!((meeting.length() - BULLET_SPEED * t).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn intercept_time_gives_up_on_faster_target() {
+        let target = Vec2::new(0.5, 0.0);
+        assert_eq!(intercept_time(Vec2::ZERO, target, Vec2::X * 2.0, 1.0), None);
+        assert_eq!(predict(Vec2::ZERO, target, Vec2::X * 2.0, 1.0), target);
+    }
+
+    #[test]
+    fn intercept_time_with_target_as_fast_as_bullet() {
+        let t = intercept_time(Vec2::ZERO, Vec2::new(0.5, 0.0), -Vec2::X, 1.0).unwrap();
+        assert!((t - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn predicted_shots_hit() {
+        let rate = hit_rate(|target, velocity| predict(Vec2::ZERO, target, velocity, BULLET_SPEED));
+        assert_eq!(rate, 1.0);
+    }
+
+    #[test]
+    fn direct_shots_miss_moving_targets() {
+        let rate = hit_rate(|target, _| target);
+        assert!(rate < 0.25, "hit rate {}", rate);
+    }
+
+    // The target was still when the shots were fired, and then dodged to either side.
+    #[test]
+    fn cage_catches_dodge() {
+        for (target, _) in targets() {
+            let side = target.perp().normalize() * TARGET_SPEED;
+            let width = predict(Vec2::ZERO, target, side, BULLET_SPEED).distance(target);
+            let cage = cage(Vec2::ZERO, target, width);
+            for velocity in [side, -side] {
+                assert!(!hits(Vec2::ZERO, target, target, velocity));
+                assert!(cage
+                    .iter()
+                    .any(|point| hits(Vec2::ZERO, *point, target, velocity)));
+            }
+        }
+    }
+
+    #[test]
+    fn scatter_stays_within_error() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let point = Vec2::new(0.8, 0.0);
+        assert_eq!(scatter(Vec2::ZERO, point, 0.0, &mut rng), point);
+        for _ in 0..100 {
+            let scattered = scatter(Vec2::ZERO, point, 0.1, &mut rng);
+            assert_eq!(scattered.x, point.x);
+            assert!(scattered.y.abs() < 0.1);
+        }
+    }
+}
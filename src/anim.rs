@@ -6,7 +6,7 @@ use bevy::{asset::LoadState, prelude::*};
 use crate::collision::Screen;
 use crate::state::GameState;
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct AnimationTimer {
     pub timer: Timer,
     pub frames_left: usize,
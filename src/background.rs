@@ -2,6 +2,8 @@
 // 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
 
 use crate::audio::AudioTrack;
+use crate::sim::SimClock;
+use crate::stage;
 use crate::{anim::AssetsLoading, state::GameState, AnimPlugin};
 use bevy::{audio::AudioSink, prelude::*};
 
@@ -9,11 +11,22 @@ pub struct BackgroundPlugin;
 
 impl Plugin for BackgroundPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system_to_stage(StartupStage::PreStartup, setup)
-            .add_system_set(SystemSet::on_enter(GameState::TitleFlyIn).with_system(stop_bg_music))
+        app.add_event::<MusicCue>()
+            .add_startup_system_to_stage(StartupStage::PreStartup, setup)
+            .add_system_set(
+                SystemSet::on_enter(GameState::TitleFlyIn)
+                    .with_system(stop_bg_music)
+                    .with_system(reset_bg_music.after(stop_bg_music)),
+            )
+            .add_system_set(
+                SystemSet::on_enter(GameState::ReplayRewind)
+                    .with_system(stop_bg_music)
+                    .with_system(reset_bg_music.after(stop_bg_music)),
+            )
             .add_system_set(
                 SystemSet::on_enter(GameState::PlayerSlideOut).with_system(start_bg_music),
             )
+            .add_system(process_music_cues.after(stage::run))
             .add_system(movement);
     }
 }
@@ -23,13 +36,19 @@ const HEIGHT: f32 = 512.0;
 const SIZE_Y: f32 = 1.0;
 const SIZE_X: f32 = SIZE_Y * WIDTH / HEIGHT;
 const SPEED: f32 = 0.03;
+const DEFAULT_MUSIC: &str = "background";
 
 pub struct BackgroundSheet(Handle<TextureAtlas>);
 
 #[derive(Deref, DerefMut)]
 pub struct BackgroundAudio(pub AudioTrack);
 
-#[derive(Component)]
+pub enum MusicCue {
+    Play(String),
+    Stop,
+}
+
+#[derive(Component, Clone)]
 pub struct Background {
     pub initial_x: f32,
 }
@@ -70,7 +89,7 @@ fn setup(
             .insert(Name::new(format!("Background{}", i)));
     }
 
-    let bg_audio = assets.load("background.ogg");
+    let bg_audio = assets.load(&format!("{}.ogg", DEFAULT_MUSIC));
     loading.push(bg_audio.clone_untyped());
     commands.insert_resource(BackgroundAudio(AudioTrack::new(bg_audio)));
 }
@@ -87,6 +106,31 @@ fn stop_bg_music(bg_audio: Res<BackgroundAudio>, audio_sinks: Res<Assets<AudioSi
     bg_audio.stop(&audio_sinks);
 }
 
+fn reset_bg_music(mut bg_audio: ResMut<BackgroundAudio>, assets: Res<AssetServer>) {
+    bg_audio.0 = AudioTrack::new(assets.load(&format!("{}.ogg", DEFAULT_MUSIC)));
+}
+
+fn process_music_cues(
+    mut cues: EventReader<MusicCue>,
+    mut bg_audio: ResMut<BackgroundAudio>,
+    assets: Res<AssetServer>,
+    (audio, audio_sinks): (Res<Audio>, Res<Assets<AudioSink>>),
+    clock: Res<SimClock>,
+) {
+    // Nothing is heard from these ticks, the music included.
+    if clock.catching_up && clock.quiet {
+        cues.clear();
+        return;
+    }
+    for cue in cues.iter() {
+        bg_audio.stop(&audio_sinks);
+        if let MusicCue::Play(name) = cue {
+            bg_audio.0 = AudioTrack::new(assets.load(&format!("{}.ogg", name)));
+            bg_audio.play(&audio, &audio_sinks);
+        }
+    }
+}
+
 fn movement(mut q: Query<(&mut Transform, &Background)>, time: Res<Time>) {
     for (mut bg_pos, bg) in &mut q {
         let mut new_x = bg_pos.translation.x - SPEED * time.delta_seconds();
@@ -0,0 +1,525 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+use crate::anim::AssetsLoading;
+use crate::collision::{Collider, CollisionLayers, Screen};
+use crate::data::RonLoader;
+use crate::difficulty::Levels;
+use crate::emitter::{Emitter, EmitterPattern, Emitters};
+use crate::enemy::EnemyGraphics;
+use crate::explosion::{Explosion, ExplosionAudio, ExplosionSheet};
+use crate::grid::Layers;
+use crate::health::{self, Damage, DamageKind, Died, Health, KeepOnDeath, Resistances};
+use crate::hit::HitFlash;
+use crate::pattern::{MovementPattern, PatternLibrary, Patterns, Side};
+use crate::pool::Pools;
+use crate::rank::RankEvent;
+use crate::score::{Points, Score};
+use crate::shape::Shape;
+use crate::sim::SimRng;
+use crate::stage::StageRunner;
+use crate::state::GameState;
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use bevy::sprite::Anchor;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+pub struct BossPlugin;
+
+impl Plugin for BossPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<BossLibrary>()
+            .add_asset_loader(RonLoader::<BossLibrary>::new(&["bosses.ron"]))
+            .add_startup_system_to_stage(StartupStage::PreStartup, load_assets)
+            .add_startup_system(setup)
+            .insert_resource(BossSchedule { next_score: None })
+            .add_system(update_phase.after(health::die))
+            .add_system(process_death.after(update_phase))
+            .add_system(update_hp_bar.after(update_phase))
+            .add_system_set(SystemSet::on_enter(GameState::TitleFlyOut).with_system(reset_schedule))
+            .add_system_set(SystemSet::on_update(GameState::Game).with_system(spawn_on_score));
+    }
+}
+
+const PART_SIZE: f32 = 0.15;
+const PART_SCORE: u32 = 5;
+const RAM_DAMAGE: f32 = 1.0;
+const BOSS_SCORE_INTERVAL: u32 = 150;
+
+const DEATH_EXPLOSIONS: u32 = 12;
+const DEATH_EXPLOSION_PERIOD: f32 = 0.15;
+
+const HP_BAR_WIDTH: f32 = 1.0;
+const HP_BAR_HEIGHT: f32 = 0.015;
+const HP_BAR_BORDER: f32 = 0.004;
+const HP_BAR_Y: f32 = 0.965;
+const HP_BAR_Z: f32 = 900.0;
+
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct BossPartDef {
+    offset: Vec2,
+    scale: f32,
+    hp: u32,
+    core: bool,
+    color: Color,
+    resistances: Resistances,
+    // A circle that fits the sprite if not set.
+    collider: Option<Shape>,
+}
+
+impl Default for BossPartDef {
+    fn default() -> Self {
+        return Self {
+            offset: Vec2::ZERO,
+            scale: 1.0,
+            hp: 10,
+            core: false,
+            color: Color::WHITE,
+            resistances: Resistances::default(),
+            collider: None,
+        };
+    }
+}
+
+impl BossPartDef {
+    fn size(&self) -> Vec2 {
+        return Vec2::splat(PART_SIZE * self.scale);
+    }
+
+    fn collider(&self) -> Shape {
+        return match &self.collider {
+            Some(collider) => collider.clone(),
+            None => Shape::Circle {
+                radius: PART_SIZE * self.scale * 0.3,
+            },
+        };
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BossAttack {
+    part: usize,
+    pattern: EmitterPattern,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BossPhase {
+    hp: f32,
+    attacks: Vec<BossAttack>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BossDef {
+    pattern: String,
+    score: u32,
+    parts: Vec<BossPartDef>,
+    phases: Vec<BossPhase>,
+}
+
+impl BossDef {
+    fn size(&self) -> Vec2 {
+        let half = self.parts.iter().fold(Vec2::ZERO, |half, part| {
+            half.max(part.offset.abs() + part.size() / 2.0)
+        });
+        return half * 2.0;
+    }
+
+    fn max_hp(&self) -> u32 {
+        return self.parts.iter().map(|part| part.hp).sum();
+    }
+}
+
+#[derive(Deserialize, TypeUuid)]
+#[serde(transparent)]
+#[uuid = "8c4e2f17-6a9b-4d3e-b5f0-1e7d9a3c5b42"]
+pub struct BossLibrary(BTreeMap<String, BossDef>);
+
+impl BossLibrary {
+    pub fn get(&self, name: &str) -> Option<&BossDef> {
+        let def = self.0.get(name);
+        if def.is_none() {
+            warn!("unknown boss: {}", name);
+        }
+        return def;
+    }
+}
+
+pub struct Bosses(pub Handle<BossLibrary>);
+
+#[derive(Clone)]
+pub struct BossSchedule {
+    next_score: Option<u32>,
+}
+
+#[derive(Component, Clone)]
+pub struct Boss {
+    def: BossDef,
+    parts: Vec<Entity>,
+    phase: Option<usize>,
+    hp_fraction: f32,
+    // The player who destroyed the last part, who gets the points for the whole boss.
+    killer: Option<usize>,
+}
+
+impl Boss {
+    // The name of the movement pattern, which is what tells the bosses apart.
+    pub fn pattern(&self) -> &str {
+        return &self.def.pattern;
+    }
+
+    pub fn parts(&self) -> &[Entity] {
+        return &self.parts;
+    }
+
+    pub fn phase(&self) -> Option<usize> {
+        return self.phase;
+    }
+
+    pub fn hp_fraction(&self) -> f32 {
+        return self.hp_fraction;
+    }
+}
+
+#[derive(Component, Clone)]
+pub struct BossPart {
+    core: bool,
+}
+
+#[derive(Component, Clone)]
+pub struct BossDeath {
+    timer: Timer,
+    explosions_left: u32,
+}
+
+#[derive(Component)]
+pub struct BossHpBar;
+
+#[derive(Component)]
+pub struct BossHpFill;
+
+fn load_assets(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    mut loading: ResMut<AssetsLoading>,
+) {
+    let handle = assets.load("enemies.bosses.ron");
+    loading.push(handle.clone_untyped());
+    commands.insert_resource(Bosses(handle));
+}
+
+fn setup(mut commands: Commands, win: Res<WindowDescriptor>) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgba(0.2, 0.2, 0.2, 0.8),
+                custom_size: Some(Vec2::new(
+                    HP_BAR_WIDTH + HP_BAR_BORDER * 2.0,
+                    HP_BAR_HEIGHT + HP_BAR_BORDER * 2.0,
+                )),
+                ..default()
+            },
+            transform: Transform::from_xyz(win.middle_x(), HP_BAR_Y, HP_BAR_Z),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        })
+        .insert(BossHpBar)
+        .insert(Name::new("BossHpBar"));
+
+    commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: Color::rgb(0.9, 0.15, 0.1),
+                custom_size: Some(Vec2::new(HP_BAR_WIDTH, HP_BAR_HEIGHT)),
+                anchor: Anchor::CenterLeft,
+                ..default()
+            },
+            transform: Transform::from_xyz(
+                win.middle_x() - HP_BAR_WIDTH / 2.0,
+                HP_BAR_Y,
+                HP_BAR_Z + 1.0,
+            ),
+            visibility: Visibility { is_visible: false },
+            ..default()
+        })
+        .insert(BossHpFill)
+        .insert(Name::new("BossHpFill"));
+}
+
+pub fn spawn(
+    commands: &mut Commands,
+    graphics: &EnemyGraphics,
+    def: &BossDef,
+    library: &PatternLibrary,
+    win: &WindowDescriptor,
+) -> Option<Entity> {
+    let pattern = library.pattern(&def.pattern)?;
+    let origin = Side::Right.entry_point(0.5, def.size(), win);
+    let movement = MovementPattern::new(pattern, Side::Right, origin);
+
+    let boss = commands
+        .spawn_bundle(SpatialBundle {
+            transform: Transform::from_translation(movement.position().extend(100.0)),
+            ..default()
+        })
+        .insert(movement)
+        .insert(Name::new("Boss"))
+        .id();
+
+    let mut parts = Vec::new();
+    for part_def in &def.parts {
+        let mut sprite = TextureAtlasSprite::new(0);
+        sprite.custom_size = Some(part_def.size());
+        sprite.flip_x = true;
+        sprite.color = part_def.color;
+
+        let z = if part_def.core { 1.0 } else { 0.0 };
+        let part = commands
+            .spawn_bundle(SpriteSheetBundle {
+                sprite,
+                texture_atlas: graphics.ship_atlas.clone(),
+                transform: Transform::from_translation(part_def.offset.extend(z)),
+                ..default()
+            })
+            .insert(BossPart {
+                core: part_def.core,
+            })
+            .insert(Health::new(part_def.hp as f32))
+            // Ramming a boss is deadly, but only for the player.
+            .insert(part_def.resistances.clone().with(DamageKind::Ram, 0.0))
+            .insert(Damage::new(RAM_DAMAGE, DamageKind::Ram))
+            .insert(Collider(part_def.collider()))
+            .insert(CollisionLayers::new(
+                Layers::ENEMY,
+                Layers::PLAYER | Layers::PLAYER_BULLET,
+            ))
+            .insert(HitFlash::new(part_def.color))
+            .insert(Name::new("BossPart"))
+            .id();
+        // The core stays until the whole boss explodes.
+        if part_def.core {
+            commands.entity(part).insert(KeepOnDeath);
+        } else {
+            commands.entity(part).insert(Points(PART_SCORE));
+        }
+        commands.entity(boss).add_child(part);
+        parts.push(part);
+    }
+
+    commands.entity(boss).insert(Boss {
+        def: def.clone(),
+        parts,
+        phase: None,
+        hp_fraction: 1.0,
+        killer: None,
+    });
+
+    return Some(boss);
+}
+
+fn reset_schedule(mut schedule: ResMut<BossSchedule>) {
+    schedule.next_score = None;
+}
+
+fn spawn_on_score(
+    mut commands: Commands,
+    (mut schedule, score, runner): (ResMut<BossSchedule>, Res<Score>, Res<StageRunner>),
+    bosses_q: Query<(), With<Boss>>,
+    (bosses, boss_libraries): (Res<Bosses>, Res<Assets<BossLibrary>>),
+    (patterns, pattern_libraries): (Res<Patterns>, Res<Assets<PatternLibrary>>),
+    (graphics, win): (Res<EnemyGraphics>, Res<WindowDescriptor>),
+    mut rng: ResMut<SimRng>,
+) {
+    if !runner.is_endless() || !bosses_q.is_empty() {
+        return;
+    }
+
+    let next_score = *schedule
+        .next_score
+        .get_or_insert(score.score + BOSS_SCORE_INTERVAL);
+    if score.score < next_score {
+        return;
+    }
+    schedule.next_score = Some(score.score + BOSS_SCORE_INTERVAL);
+
+    let library = boss_libraries.get(&bosses.0).unwrap();
+    let names: Vec<&String> = library.0.keys().collect();
+    if let Some(name) = names.choose(&mut *rng) {
+        let pattern_library = pattern_libraries.get(&patterns.0).unwrap();
+        spawn(
+            &mut commands,
+            &graphics,
+            &library.0[*name],
+            pattern_library,
+            &win,
+        );
+    }
+}
+
+fn update_phase(
+    mut commands: Commands,
+    mut boss_q: Query<(Entity, &mut Boss, &GlobalTransform), Without<BossDeath>>,
+    parts_q: Query<(&BossPart, &Health)>,
+    mut died: EventReader<Died>,
+    levels: Res<Levels>,
+    (explosion_sheet, explosion_audio, audio): (
+        Res<ExplosionSheet>,
+        Res<ExplosionAudio>,
+        Res<Audio>,
+    ),
+    mut pools: ResMut<Pools>,
+) {
+    let died: Vec<&Died> = died.iter().collect();
+    for (boss_entity, mut boss, transform) in &mut boss_q {
+        for died in &died {
+            if boss.parts.contains(&died.entity) {
+                boss.killer = died.player;
+            }
+        }
+
+        let mut hp = 0.0;
+        let mut core_destroyed = false;
+        for (part, health) in boss.parts.iter().filter_map(|e| parts_q.get(*e).ok()) {
+            hp += health.current;
+            core_destroyed |= part.core && health.is_dead();
+        }
+        boss.hp_fraction = hp / boss.def.max_hp().max(1) as f32;
+
+        if core_destroyed || hp <= 0.0 {
+            boss.hp_fraction = 0.0;
+            for part in &boss.parts {
+                if parts_q.contains(*part) {
+                    commands
+                        .entity(*part)
+                        .remove::<Collider>()
+                        .remove::<Emitters>();
+                }
+            }
+            commands.entity(boss_entity).insert(BossDeath {
+                timer: Timer::from_seconds(DEATH_EXPLOSION_PERIOD, true),
+                explosions_left: DEATH_EXPLOSIONS,
+            });
+            continue;
+        }
+
+        let fraction = boss.hp_fraction;
+        let phase = boss
+            .def
+            .phases
+            .iter()
+            .rposition(|phase| fraction <= phase.hp)
+            .unwrap_or(0);
+        if boss.phase.is_some_and(|current| phase <= current) {
+            continue;
+        }
+
+        if boss.phase.is_some() {
+            Explosion::spawn_scaled(
+                &mut commands,
+                &mut pools,
+                &explosion_sheet,
+                transform.translation() + Vec3::Z,
+                0.05,
+                1.5,
+            );
+            Explosion::play_sound(&explosion_audio, &audio);
+        }
+        boss.phase = Some(phase);
+
+        let difficulty = levels.emitters;
+        let attacks = boss.def.phases.get(phase).map(|phase| &phase.attacks);
+        for (index, part) in boss.parts.iter().enumerate() {
+            // Parts destroyed this frame are already queued for despawn.
+            if parts_q
+                .get(*part)
+                .map_or(true, |(_, health)| health.is_dead())
+            {
+                continue;
+            }
+            let emitters = attacks
+                .into_iter()
+                .flatten()
+                .filter(|attack| attack.part == index)
+                .map(|attack| Emitter::new(attack.pattern.clone(), 1.0, difficulty))
+                .collect();
+            commands.entity(*part).insert(Emitters(emitters));
+        }
+    }
+}
+
+fn process_death(
+    mut commands: Commands,
+    mut boss_q: Query<(Entity, &Boss, &mut BossDeath, &GlobalTransform)>,
+    time: Res<Time>,
+    mut score: ResMut<Score>,
+    (explosion_sheet, explosion_audio, audio): (
+        Res<ExplosionSheet>,
+        Res<ExplosionAudio>,
+        Res<Audio>,
+    ),
+    mut pools: ResMut<Pools>,
+    mut rank_events: EventWriter<RankEvent>,
+) {
+    let mut rng = rand::thread_rng();
+
+    for (entity, boss, mut death, transform) in &mut boss_q {
+        death.timer.tick(time.delta());
+        if !death.timer.just_finished() {
+            continue;
+        }
+
+        let center = transform.translation() + Vec3::Z;
+        if death.explosions_left > 0 {
+            death.explosions_left -= 1;
+            let size = boss.def.size();
+            let offset = Vec2::new(
+                rng.gen_range(-0.5..0.5) * size.x,
+                rng.gen_range(-0.5..0.5) * size.y,
+            );
+            Explosion::spawn_scaled(
+                &mut commands,
+                &mut pools,
+                &explosion_sheet,
+                center + offset.extend(0.0),
+                0.04,
+                rng.gen_range(0.5..1.0),
+            );
+            Explosion::play_sound(&explosion_audio, &audio);
+        } else {
+            Explosion::spawn_scaled(
+                &mut commands,
+                &mut pools,
+                &explosion_sheet,
+                center,
+                0.07,
+                3.0,
+            );
+            Explosion::play_sound(&explosion_audio, &audio);
+            match boss.killer {
+                Some(player) => score.add_for(player, boss.def.score),
+                None => score.add(boss.def.score),
+            }
+            rank_events.send(RankEvent::Kill);
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+fn update_hp_bar(
+    boss_q: Query<&Boss>,
+    mut bar_q: Query<&mut Visibility, (With<BossHpBar>, Without<BossHpFill>)>,
+    mut fill_q: Query<(&mut Visibility, &mut Sprite), With<BossHpFill>>,
+) {
+    let boss = boss_q.iter().next();
+    let (mut fill_visibility, mut fill) = fill_q.single_mut();
+    let mut bar_visibility = bar_q.single_mut();
+
+    bar_visibility.is_visible = boss.is_some();
+    fill_visibility.is_visible = boss.is_some();
+    if let Some(boss) = boss {
+        fill.custom_size = Some(Vec2::new(HP_BAR_WIDTH * boss.hp_fraction, HP_BAR_HEIGHT));
+    }
+}
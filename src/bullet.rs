@@ -4,16 +4,51 @@
 use std::time::Duration;
 
 use crate::anim::AssetsLoading;
-use crate::collision::DestroyOutsideScreen;
+use crate::collision::{self, Collider, DestroyOutsideScreen, EnemyBulletHitByBullet};
+use crate::explosion::{Explosion, ExplosionSheet};
+use crate::hit::SPARK_Z;
+use crate::player::PlayerBullet;
+use crate::pool::{PoolKind, Pooled, Pools, ReleaseExt};
+use crate::score::Score;
+use crate::shape::Shape;
 use crate::AnimPlugin;
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
 
 pub struct BulletPlugin;
 
 impl Plugin for BulletPlugin {
     fn build(&self, app: &mut App) {
         app.add_startup_system_to_stage(StartupStage::PreStartup, load_assets)
-            .add_system(movement);
+            .insert_resource(BulletCancel::Off)
+            .add_system(movement)
+            .add_system(cancel.after(collision::detect));
+    }
+}
+
+// Cancelled enemy bullets are worth a fraction of a kill.
+const CANCELS_PER_POINT: u32 = 5;
+
+// Whether player shots can destroy enemy bullets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BulletCancel {
+    Off,
+    On,
+}
+
+impl BulletCancel {
+    pub fn toggle(&mut self) {
+        *self = match self {
+            BulletCancel::Off => BulletCancel::On,
+            BulletCancel::On => BulletCancel::Off,
+        };
+    }
+
+    pub fn title(&self) -> &'static str {
+        return match self {
+            BulletCancel::Off => "OFF",
+            BulletCancel::On => "ON",
+        };
     }
 }
 
@@ -21,8 +56,9 @@ pub struct BulletResInfo {
     atlas: Handle<TextureAtlas>,
     audio: Handle<AudioSource>,
     sprite_size: Vec2,
-    collision_size: Vec2,
+    collider: Shape,
     audio_volume: f32,
+    pool: PoolKind,
 }
 
 impl BulletResInfo {
@@ -33,10 +69,7 @@ impl BulletResInfo {
         image_filename: &str,
         audio_filename: &str,
         atlas_size: Vec2,
-        sprite_size: Vec2,
-        collision_size: Vec2,
-        audio_volume: f32,
-    ) -> Self {
+    ) -> (Handle<TextureAtlas>, Handle<AudioSource>) {
         let atlas = AnimPlugin::load_atlas(
             image_filename,
             Vec2::new(atlas_size.x, atlas_size.y),
@@ -48,13 +81,7 @@ impl BulletResInfo {
         );
         let audio = assets.load(audio_filename);
         loading.push(audio.clone_untyped());
-        return Self {
-            atlas,
-            audio,
-            sprite_size,
-            collision_size,
-            audio_volume,
-        };
+        return (atlas, audio);
     }
 }
 
@@ -63,20 +90,37 @@ pub struct BulletRes {
     pub enemy: BulletResInfo,
 }
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct Bullet {
     speed: Vec3,
+    age: f32,
+    grazed: bool,
+}
+
+// Optional non-linear behavior. Zero values disable the corresponding effect.
+#[derive(Component, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(default)]
+pub struct BulletMotion {
+    pub acceleration: f32,
+    pub min_speed: f32,
+    pub max_speed: f32,
+    pub angular_velocity: f32,
+    pub turn_rate: f32,
+    pub lifetime: f32,
 }
 
+#[derive(Component, Clone)]
+pub struct HomingTarget(pub Option<Entity>);
+
 impl Bullet {
-    pub fn spawn(
+    pub fn spawn_silent(
         commands: &mut Commands,
+        pools: &mut Pools,
         res_info: &BulletResInfo,
         starting_point: Vec3,
         target_point: Vec3,
         speed: f32,
         color: Color,
-        audio: &Res<Audio>,
     ) -> Entity {
         let mut sprite = TextureAtlasSprite::new(0);
 
@@ -98,15 +142,37 @@ impl Bullet {
             ..default()
         };
 
-        let entity = commands
-            .spawn_bundle(sprite_bundle)
-            .insert(Bullet { speed: speed_vec })
-            .insert(HitBox(res_info.collision_size))
+        let entity = pools.acquire(commands, res_info.pool);
+        commands
+            .entity(entity)
+            .insert_bundle(sprite_bundle)
+            .insert(Pooled::new(res_info.pool))
+            .insert(Bullet {
+                speed: speed_vec,
+                age: 0.0,
+                grazed: false,
+            })
+            .insert(Collider(res_info.collider.clone()))
             .insert(DestroyOutsideScreen {
                 size: res_info.sprite_size,
-            })
-            .id();
+            });
+        return entity;
+    }
+
+    pub fn grazed(&self) -> bool {
+        return self.grazed;
+    }
 
+    // Only the first graze of each bullet counts.
+    pub fn graze(&mut self) -> bool {
+        if self.grazed {
+            return false;
+        }
+        self.grazed = true;
+        return true;
+    }
+
+    pub fn play_sound(res_info: &BulletResInfo, audio: &Res<Audio>) {
         audio.play_with_settings(
             res_info.audio.clone(),
             PlaybackSettings {
@@ -114,11 +180,10 @@ impl Bullet {
                 ..default()
             },
         );
-        return entity;
     }
 }
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct BulletTimer {
     timer: Timer,
     pub can_shoot: bool,
@@ -142,10 +207,18 @@ impl BulletTimer {
         return timer;
     }
 
+    pub fn set_period(&mut self, secs: f32) {
+        self.timer.set_duration(Duration::from_secs_f32(secs));
+    }
+
     pub fn shoot(&mut self) {
         self.can_shoot = false;
     }
 
+    pub fn elapsed(&self) -> f32 {
+        return self.timer.elapsed_secs();
+    }
+
     pub fn process(&mut self, delta: Duration) {
         if self.can_shoot {
             return;
@@ -158,9 +231,6 @@ impl BulletTimer {
     }
 }
 
-#[derive(Component, Deref, DerefMut)]
-pub struct HitBox(pub Vec2);
-
 fn load_assets(
     mut commands: Commands,
     assets: Res<AssetServer>,
@@ -169,37 +239,153 @@ fn load_assets(
 ) {
     let player_atlas_size = Vec2::new(141.0, 129.0);
     let player_sprite_size = player_atlas_size / 141.0 * 0.15;
-    let player = BulletResInfo::load(
+    let (atlas, audio) = BulletResInfo::load(
         &assets,
         &mut atlases,
         &mut loading,
         "player_bullet.png",
         "player_bullet.ogg",
         player_atlas_size,
-        player_sprite_size,
-        player_sprite_size * 0.1,
-        0.25,
     );
+    let player = BulletResInfo {
+        atlas,
+        audio,
+        sprite_size: player_sprite_size,
+        collider: Shape::Circle {
+            radius: player_sprite_size.y * 0.05,
+        },
+        audio_volume: 0.25,
+        pool: PoolKind::PlayerBullet,
+    };
 
     let enemy_atlas_size = Vec2::new(325.0, 238.0);
     let enemy_sprite_size = player_atlas_size / 325.0 * 0.25;
-    let enemy = BulletResInfo::load(
+    let (atlas, audio) = BulletResInfo::load(
         &assets,
         &mut atlases,
         &mut loading,
         "enemy_bullet.png",
         "enemy_bullet.ogg",
         enemy_atlas_size,
-        enemy_sprite_size,
-        enemy_sprite_size * 0.1,
-        0.75,
     );
+    let enemy = BulletResInfo {
+        atlas,
+        audio,
+        sprite_size: enemy_sprite_size,
+        // Enemy bullets are stretched along their heading.
+        collider: Shape::Capsule {
+            radius: enemy_sprite_size.y * 0.05,
+            length: enemy_sprite_size.x * 0.05,
+        },
+        audio_volume: 0.75,
+        pool: PoolKind::EnemyBullet,
+    };
 
     commands.insert_resource(BulletRes { player, enemy });
 }
 
-fn movement(mut q: Query<(&Bullet, &mut Transform)>, time: Res<Time>) {
-    for (bullet, mut transform) in &mut q {
-        transform.translation += bullet.speed * time.delta_seconds();
+// Bullets with whatever steers them.
+type Moving<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static mut Bullet,
+        &'static mut Transform,
+        Option<&'static BulletMotion>,
+        Option<&'static HomingTarget>,
+    ),
+>;
+
+fn movement(
+    mut commands: Commands,
+    mut q: Moving,
+    targets: Query<&GlobalTransform>,
+    time: Res<Time>,
+) {
+    let delta = time.delta_seconds();
+
+    for (entity, mut bullet, mut transform, motion, homing) in &mut q {
+        bullet.age += delta;
+
+        if let Some(motion) = motion {
+            if motion.lifetime > 0.0 && bullet.age >= motion.lifetime {
+                commands.entity(entity).release();
+                continue;
+            }
+
+            let max_speed = if motion.max_speed > 0.0 {
+                motion.max_speed
+            } else {
+                f32::INFINITY
+            };
+            let speed = (bullet.speed.length() + motion.acceleration * delta)
+                .clamp(motion.min_speed, max_speed.max(motion.min_speed));
+
+            // The heading is kept in the rotation so that it survives a full stop.
+            let mut dir = (transform.rotation * Vec3::X)
+                .truncate()
+                .normalize_or_zero();
+            dir = Vec2::from_angle(motion.angular_velocity * delta).rotate(dir);
+
+            let target = homing
+                .and_then(|homing| homing.0)
+                .filter(|_| motion.turn_rate > 0.0)
+                .and_then(|target| targets.get(target).ok());
+            if let Some(target) = target {
+                let wanted = target.translation().truncate() - transform.translation.truncate();
+                let max_turn = motion.turn_rate * delta;
+                let turn = dir.angle_between(wanted).clamp(-max_turn, max_turn);
+                if turn.is_finite() {
+                    dir = Vec2::from_angle(turn).rotate(dir);
+                }
+            }
+
+            bullet.speed = (dir * speed).extend(0.0);
+            transform.rotation = Quat::from_rotation_z(dir.y.atan2(dir.x));
+        }
+
+        transform.translation += bullet.speed * delta;
+    }
+}
+
+fn cancel(
+    mut commands: Commands,
+    mut events: EventReader<EnemyBulletHitByBullet>,
+    q: Query<&GlobalTransform, With<Bullet>>,
+    shooters_q: Query<&PlayerBullet>,
+    sheet: Res<ExplosionSheet>,
+    mut pools: ResMut<Pools>,
+    mut score: ResMut<Score>,
+) {
+    // Both bullets are spent on the first hit.
+    let mut spent = Vec::new();
+    for hit in events.iter() {
+        if spent.contains(&hit.enemy_bullet) || spent.contains(&hit.bullet) {
+            continue;
+        }
+        let transform = match q.get(hit.enemy_bullet) {
+            Ok(transform) => transform,
+            Err(_) => continue,
+        };
+        spent.push(hit.enemy_bullet);
+        spent.push(hit.bullet);
+
+        Explosion::spawn_spark(
+            &mut commands,
+            &mut pools,
+            &sheet,
+            transform.translation().truncate().extend(SPARK_Z),
+        );
+        commands.entity(hit.enemy_bullet).release();
+        commands.entity(hit.bullet).release();
+
+        score.cancels += 1;
+        if score.cancels.is_multiple_of(CANCELS_PER_POINT) {
+            match shooters_q.get(hit.bullet) {
+                Ok(shooter) => score.add_for(shooter.player, 1),
+                Err(_) => score.add(1),
+            }
+        }
     }
 }
@@ -0,0 +1,311 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+// A fingerprint of the state a run depends on, taken after every tick.
+// Two machines that play the same run get the same ones, so comparing them tells
+// when the runs went apart, and the snapshots they were taken from tell where.
+
+use crate::boss::Boss;
+use crate::bullet::{Bullet, BulletTimer};
+use crate::emitter::Emitters;
+use crate::enemy::Enemy;
+use crate::health::Health;
+use crate::player::{MissileLauncher, Player, PlayerBullet, Slots};
+use crate::save;
+use crate::score::Score;
+use crate::sim::{SimClock, SimRng};
+use crate::stage::StageRunner;
+use bevy::prelude::*;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+pub struct ChecksumPlugin;
+
+impl Plugin for ChecksumPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Checksums::default())
+            .add_system_to_stage(CoreStage::PostUpdate, take);
+    }
+}
+
+// Covers how far apart the two sides of netplay can get.
+const HISTORY: usize = 120;
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+// FNV-1a. Unlike the std hasher, it stays the same in every build of the game.
+fn hash(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    return hash;
+}
+
+#[derive(Clone, Copy)]
+enum Value {
+    Float(f32),
+    Int(u64),
+}
+
+impl Value {
+    fn bits(&self) -> u64 {
+        return match self {
+            Self::Float(value) => value.to_bits() as u64,
+            Self::Int(value) => *value,
+        };
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            Self::Float(value) => write!(f, "{:?}", value),
+            Self::Int(value) => write!(f, "{}", value),
+        };
+    }
+}
+
+struct Entry {
+    kind: &'static str,
+    // Tells the entities of the same kind apart in the text, as far as it can.
+    name: String,
+    fields: Vec<(&'static str, Value)>,
+}
+
+impl Entry {
+    fn new(kind: &'static str, fields: Vec<(&'static str, Value)>) -> Self {
+        return Self::named(kind, "", fields);
+    }
+
+    fn named(kind: &'static str, name: &str, fields: Vec<(&'static str, Value)>) -> Self {
+        return Self {
+            kind,
+            name: name.to_string(),
+            fields,
+        };
+    }
+
+    fn bits(&self) -> impl Iterator<Item = u64> + '_ {
+        return self.fields.iter().map(|(_, value)| value.bits());
+    }
+}
+
+struct Snapshot {
+    tick: u64,
+    checksum: u64,
+    entries: Vec<Entry>,
+}
+
+impl Snapshot {
+    fn new(tick: u64, mut entries: Vec<Entry>) -> Self {
+        // Queries list the entities in an order that depends on what happened before the run,
+        // e.g. on which pooled entities got reused.
+        entries.sort_by(|a, b| {
+            a.kind
+                .cmp(b.kind)
+                .then_with(|| a.name.cmp(&b.name))
+                .then_with(|| a.bits().cmp(b.bits()))
+        });
+        let mut checksum = FNV_OFFSET;
+        for entry in &entries {
+            checksum = hash(checksum, entry.kind.as_bytes());
+            checksum = hash(checksum, entry.name.as_bytes());
+            for bits in entry.bits() {
+                checksum = hash(checksum, &bits.to_le_bytes());
+            }
+        }
+        return Self {
+            tick,
+            checksum,
+            entries,
+        };
+    }
+
+    // One line per entity, so that the snapshots of two sides can be diffed.
+    fn text(&self) -> String {
+        let mut text = format!("tick {}\nchecksum {:016x}\n", self.tick, self.checksum);
+        for entry in &self.entries {
+            text.push_str(entry.kind);
+            if !entry.name.is_empty() {
+                write!(text, " {}", entry.name).unwrap();
+            }
+            for (name, value) in &entry.fields {
+                write!(text, " {}={}", name, value).unwrap();
+            }
+            text.push('\n');
+        }
+        return text;
+    }
+}
+
+// The last few ticks of the current run.
+#[derive(Default)]
+pub struct Checksums {
+    snapshots: VecDeque<Snapshot>,
+}
+
+impl Checksums {
+    pub fn get(&self, tick: u64) -> Option<u64> {
+        return self.snapshot(tick).map(|snapshot| snapshot.checksum);
+    }
+
+    // The checksums of the last `count` ticks before `until`, and the tick of the first one.
+    pub fn recent(&self, count: usize, until: u64) -> (u64, Vec<u64>) {
+        let end = self
+            .snapshots
+            .partition_point(|snapshot| snapshot.tick < until);
+        let skip = end.saturating_sub(count);
+        let start = self.snapshots.get(skip).map_or(0, |snapshot| snapshot.tick);
+        let checksums = self
+            .snapshots
+            .range(skip..end)
+            .map(|snapshot| snapshot.checksum)
+            .collect();
+        return (start, checksums);
+    }
+
+    pub fn oldest(&self) -> Option<u64> {
+        return self.snapshots.front().map(|snapshot| snapshot.tick);
+    }
+
+    // Forgets the ticks from the given one on, which are going to be simulated again.
+    pub fn truncate(&mut self, tick: u64) {
+        let end = self
+            .snapshots
+            .partition_point(|snapshot| snapshot.tick < tick);
+        self.snapshots.truncate(end);
+    }
+
+    // Saves the snapshot the checksum of the tick was taken from,
+    // named so that it can be told apart from the other side's one.
+    pub fn dump(&self, tick: u64, side: &str) -> Option<PathBuf> {
+        let snapshot = self.snapshot(tick)?;
+        return save::store_text(&format!("desync/{}-{}.txt", tick, side), &snapshot.text());
+    }
+
+    fn snapshot(&self, tick: u64) -> Option<&Snapshot> {
+        let oldest = self.oldest()?;
+        return self
+            .snapshots
+            .get(tick.checked_sub(oldest)? as usize)
+            .filter(|snapshot| snapshot.tick == tick);
+    }
+}
+
+pub fn take(
+    mut checksums: ResMut<Checksums>,
+    clock: Res<SimClock>,
+    (rng, score, slots, runner): (Res<SimRng>, Res<Score>, Res<Slots>, Res<StageRunner>),
+    players_q: Query<(&Player, &Transform, &BulletTimer, &MissileLauncher)>,
+    enemies_q: Query<(&Enemy, &Transform, &Health, Option<&Emitters>)>,
+    (bosses_q, parts_q): (Query<(&Boss, &Transform)>, Query<&Health>),
+    bullets_q: Query<(&Transform, Option<&PlayerBullet>), With<Bullet>>,
+) {
+    if clock.stalled {
+        return;
+    }
+    if let Some(last) = checksums.snapshots.back() {
+        // A new run, or one that goes on from a snapshot taken further on (see rollback.rs).
+        if last.tick + 1 != clock.tick {
+            checksums.snapshots.clear();
+        }
+    }
+
+    let mut entries = vec![
+        Entry::new("Rng", vec![("next", Value::Int(rng.fingerprint()))]),
+        Entry::new(
+            "Score",
+            vec![
+                ("score", Value::Int(score.score as u64)),
+                ("cancels", Value::Int(score.cancels as u64)),
+            ],
+        ),
+        Entry::new("Stage", vec![("wait", Value::Float(runner.wait()))]),
+    ];
+    for (index, slot) in slots.0.iter().enumerate() {
+        entries.push(Entry::new(
+            "Slot",
+            vec![
+                ("index", Value::Int(index as u64)),
+                ("joined", Value::Int(slot.joined as u64)),
+                ("lives", Value::Int(slot.lives as u64)),
+                ("respawn", Value::Float(slot.respawn_elapsed())),
+                ("score", Value::Int(score.players[index] as u64)),
+            ],
+        ));
+    }
+    for (player, transform, timer, launcher) in &players_q {
+        entries.push(Entry::new(
+            "Player",
+            vec![
+                ("index", Value::Int(player.index() as u64)),
+                ("x", Value::Float(transform.translation.x)),
+                ("y", Value::Float(transform.translation.y)),
+                ("heat", Value::Float(player.heat())),
+                ("timer", Value::Float(timer.elapsed())),
+                ("missile", Value::Float(launcher.elapsed())),
+            ],
+        ));
+    }
+    for (enemy, transform, health, emitters) in &enemies_q {
+        let mut fields = vec![
+            ("x", Value::Float(transform.translation.x)),
+            ("y", Value::Float(transform.translation.y)),
+            ("hp", Value::Float(health.current)),
+        ];
+        for emitter in emitters.iter().flat_map(|emitters| &emitters.0) {
+            fields.push(("emitter", Value::Float(emitter.elapsed())));
+        }
+        entries.push(Entry::named("Enemy", &enemy.archetype, fields));
+    }
+    for (boss, transform) in &bosses_q {
+        // No phase is 0, the first one is 1.
+        let phase = boss.phase().map_or(0, |phase| phase as u64 + 1);
+        entries.push(Entry::named(
+            "Boss",
+            boss.pattern(),
+            vec![
+                ("x", Value::Float(transform.translation.x)),
+                ("y", Value::Float(transform.translation.y)),
+                ("phase", Value::Int(phase)),
+                ("hp", Value::Float(boss.hp_fraction())),
+            ],
+        ));
+        for (index, part) in boss.parts().iter().enumerate() {
+            if let Ok(health) = parts_q.get(*part) {
+                entries.push(Entry::named(
+                    "BossPart",
+                    boss.pattern(),
+                    vec![
+                        ("index", Value::Int(index as u64)),
+                        ("hp", Value::Float(health.current)),
+                    ],
+                ));
+            }
+        }
+    }
+    for (transform, shooter) in &bullets_q {
+        let mut fields = vec![
+            ("x", Value::Float(transform.translation.x)),
+            ("y", Value::Float(transform.translation.y)),
+        ];
+        let kind = match shooter {
+            Some(shooter) => {
+                fields.push(("player", Value::Int(shooter.player as u64)));
+                "PlayerBullet"
+            }
+            None => "EnemyBullet",
+        };
+        entries.push(Entry::new(kind, fields));
+    }
+
+    if checksums.snapshots.len() == HISTORY {
+        checksums.snapshots.pop_front();
+    }
+    checksums
+        .snapshots
+        .push_back(Snapshot::new(clock.tick, entries));
+}
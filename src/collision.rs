@@ -1,7 +1,16 @@
 // SPDX-License-Identifier: GPL-3.0-only
 // 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
 
+use crate::grid::{Grid, Layers};
+use crate::pool::ReleaseExt;
+use crate::shape::{Placed, Shape};
+use bevy::math::Affine2;
 use bevy::prelude::*;
+use bevy::utils::HashMap;
+use std::ops::Range;
+
+// Roughly the size of an enemy ship.
+const GRID_CELL_SIZE: f32 = 0.15;
 
 pub trait Screen {
     fn max_x(&self) -> f32;
@@ -60,16 +69,226 @@ impl Screen for WindowDescriptor {
     }
 }
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct DestroyOutsideScreen {
     pub size: Vec2,
 }
 
+// What an entity is for the purpose of collisions,
+// and what other entities it can collide with.
+// The entity's own collider and the colliders of its children
+// that have no layers of their own make up its body.
+#[derive(Component, Clone, Copy)]
+pub struct CollisionLayers {
+    pub member: Layers,
+    pub mask: Layers,
+}
+
+impl CollisionLayers {
+    pub fn new(member: Layers, mask: Layers) -> Self {
+        return Self { member, mask };
+    }
+}
+
+#[derive(Component, Clone)]
+pub struct Collider(pub Shape);
+
+struct Body {
+    shapes: Range<usize>,
+    mask: Layers,
+}
+
+// Every body as of the start of the frame.
+pub struct CollisionGrid {
+    grid: Grid<Entity>,
+    bodies: HashMap<Entity, Body>,
+    shapes: Vec<Placed>,
+}
+
+impl CollisionGrid {
+    fn new() -> Self {
+        return Self {
+            grid: Grid::new(GRID_CELL_SIZE),
+            bodies: HashMap::new(),
+            shapes: Vec::new(),
+        };
+    }
+
+    // Bodies on the given layers that the entity touches, as far as its mask allows.
+    pub fn hits(&self, entity: Entity, layers: Layers) -> Vec<Entity> {
+        let mut found = Vec::new();
+        if let Some(body) = self.bodies.get(&entity) {
+            for shape in &self.shapes[body.shapes.clone()] {
+                self.grid.query(shape, body.mask & layers, &mut found);
+            }
+        }
+        found.retain(|other| *other != entity);
+        return found;
+    }
+
+    // Whether the body's mask lets it be touched by the given layers.
+    pub fn accepts(&self, entity: Entity, layers: Layers) -> bool {
+        return self
+            .bodies
+            .get(&entity)
+            .is_some_and(|body| body.mask.intersects(layers));
+    }
+}
+
+// Collisions are only reported here.
+// What they do to the game is up to the systems reading these events.
+
+pub struct PlayerHitByBullet {
+    pub player: Entity,
+    pub bullet: Entity,
+}
+
+// The enemy may be a boss part.
+pub struct PlayerRammedEnemy {
+    pub player: Entity,
+    pub enemy: Entity,
+}
+
+pub struct EnemyHitByBullet {
+    pub enemy: Entity,
+    pub bullet: Entity,
+}
+
+pub struct EnemyBulletHitByBullet {
+    pub enemy_bullet: Entity,
+    pub bullet: Entity,
+}
+
+// The bullet is near the player, but not necessarily touching the ship.
+pub struct PlayerGrazedBullet {
+    pub zone: Entity,
+    pub bullet: Entity,
+}
+
 pub struct CollisionPlugin;
 
 impl Plugin for CollisionPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(destroy_outside_screen);
+        app.add_event::<PlayerHitByBullet>()
+            .add_event::<PlayerRammedEnemy>()
+            .add_event::<EnemyHitByBullet>()
+            .add_event::<EnemyBulletHitByBullet>()
+            .add_event::<PlayerGrazedBullet>()
+            .insert_resource(CollisionGrid::new())
+            .add_system(update_grid)
+            .add_system(detect.after(update_grid))
+            .add_system(destroy_outside_screen);
+    }
+}
+
+fn affine2(transform: &GlobalTransform) -> Affine2 {
+    let affine = transform.affine();
+    return Affine2::from_cols(
+        affine.transform_vector3(Vec3::X).truncate(),
+        affine.transform_vector3(Vec3::Y).truncate(),
+        transform.translation().truncate(),
+    );
+}
+
+// Whatever collides, with the shape of its own and of its parts.
+type Bodies<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static CollisionLayers,
+        &'static GlobalTransform,
+        Option<&'static Collider>,
+        Option<&'static Children>,
+    ),
+>;
+
+pub fn update_grid(
+    mut grid: ResMut<CollisionGrid>,
+    bodies_q: Bodies,
+    parts_q: Query<(&GlobalTransform, &Collider), Without<CollisionLayers>>,
+) {
+    let grid = &mut *grid;
+    grid.grid.clear();
+    grid.bodies.clear();
+    grid.shapes.clear();
+
+    for (entity, layers, transform, collider, children) in &bodies_q {
+        let start = grid.shapes.len();
+        if let Some(collider) = collider {
+            collider.0.place(affine2(transform), &mut grid.shapes);
+        }
+        for child in children.into_iter().flatten() {
+            if let Ok((transform, collider)) = parts_q.get(*child) {
+                collider.0.place(affine2(transform), &mut grid.shapes);
+            }
+        }
+
+        let shapes = start..grid.shapes.len();
+        for shape in &grid.shapes[shapes.clone()] {
+            grid.grid.insert(entity, *shape, layers.member);
+        }
+        grid.bodies.insert(
+            entity,
+            Body {
+                shapes,
+                mask: layers.mask,
+            },
+        );
+    }
+}
+
+pub fn detect(
+    grid: Res<CollisionGrid>,
+    bodies_q: Query<(Entity, &CollisionLayers)>,
+    mut player_hit_by_bullet: EventWriter<PlayerHitByBullet>,
+    mut player_rammed_enemy: EventWriter<PlayerRammedEnemy>,
+    mut enemy_hit_by_bullet: EventWriter<EnemyHitByBullet>,
+    mut enemy_bullet_hit_by_bullet: EventWriter<EnemyBulletHitByBullet>,
+    mut player_grazed_bullet: EventWriter<PlayerGrazedBullet>,
+) {
+    for (entity, layers) in &bodies_q {
+        if layers.member == Layers::PLAYER {
+            for enemy in grid.hits(entity, Layers::ENEMY) {
+                player_rammed_enemy.send(PlayerRammedEnemy {
+                    player: entity,
+                    enemy,
+                });
+            }
+            for bullet in grid.hits(entity, Layers::ENEMY_BULLET) {
+                player_hit_by_bullet.send(PlayerHitByBullet {
+                    player: entity,
+                    bullet,
+                });
+            }
+        } else if layers.member == Layers::PLAYER_BULLET {
+            // A bullet may overlap several enemies at once, but must only hit one.
+            if let Some(enemy) = grid.hits(entity, Layers::ENEMY).first() {
+                enemy_hit_by_bullet.send(EnemyHitByBullet {
+                    enemy: *enemy,
+                    bullet: entity,
+                });
+                continue;
+            }
+            // Enemy bullets decide for themselves whether they can be shot down.
+            let enemy_bullet = grid
+                .hits(entity, Layers::ENEMY_BULLET)
+                .into_iter()
+                .find(|other| grid.accepts(*other, Layers::PLAYER_BULLET));
+            if let Some(enemy_bullet) = enemy_bullet {
+                enemy_bullet_hit_by_bullet.send(EnemyBulletHitByBullet {
+                    enemy_bullet,
+                    bullet: entity,
+                });
+            }
+        } else if layers.member == Layers::GRAZE {
+            for bullet in grid.hits(entity, Layers::ENEMY_BULLET) {
+                player_grazed_bullet.send(PlayerGrazedBullet {
+                    zone: entity,
+                    bullet,
+                });
+            }
+        }
     }
 }
 
@@ -85,7 +304,7 @@ fn destroy_outside_screen(
             || transform.translation.y > bounds.top
             || transform.translation.y < bounds.bottom
         {
-            commands.entity(entity).despawn_recursive();
+            commands.entity(entity).release();
         }
     }
 }
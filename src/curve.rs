@@ -0,0 +1,169 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+use serde::Deserialize;
+
+// What a curve is sampled by.
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub enum Driver {
+    Score,
+    Time,
+    Rank,
+}
+
+impl Driver {
+    pub fn title(&self) -> &'static str {
+        return match self {
+            Driver::Score => "SCORE",
+            Driver::Time => "TIME",
+            Driver::Rank => "RANK",
+        };
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Progress {
+    pub score: f32,
+    pub time: f32,
+    pub rank: f32,
+}
+
+impl Progress {
+    pub fn get(&self, driver: Driver) -> f32 {
+        return match driver {
+            Driver::Score => self.score,
+            Driver::Time => self.time,
+            Driver::Rank => self.rank,
+        };
+    }
+}
+
+// Shapes the segment that ends at a key.
+#[derive(Deserialize, Clone, Copy, Default, Debug)]
+pub enum Ease {
+    #[default]
+    Linear,
+    Step,
+    In,
+    Out,
+    InOut,
+}
+
+impl Ease {
+    fn apply(&self, t: f32) -> f32 {
+        return match self {
+            Ease::Linear => t,
+            Ease::Step => {
+                if t < 1.0 {
+                    0.0
+                } else {
+                    1.0
+                }
+            }
+            Ease::In => t * t,
+            Ease::Out => 1.0 - (1.0 - t) * (1.0 - t),
+            Ease::InOut => t * t * (3.0 - 2.0 * t),
+        };
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct Key {
+    pub at: f32,
+    pub value: f32,
+    #[serde(default)]
+    pub ease: Ease,
+}
+
+// What happens past the last key.
+#[derive(Deserialize, Clone, Copy, Default, Debug)]
+pub enum After {
+    #[default]
+    Hold,
+    // Keeps the slope between the last two keys.
+    Extend,
+    // Starts over from the first key, adding the escalation on every pass.
+    Loop {
+        escalation: f32,
+    },
+}
+
+// Keys must be sorted by "at".
+// Before the first key the curve holds the first value.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Curve {
+    pub driver: Driver,
+    pub keys: Vec<Key>,
+    #[serde(default)]
+    pub after: After,
+    #[serde(default)]
+    pub min: Option<f32>,
+    #[serde(default)]
+    pub max: Option<f32>,
+}
+
+impl Curve {
+    pub fn eval(&self, progress: &Progress) -> f32 {
+        return self.sample(progress.get(self.driver));
+    }
+
+    pub fn sample(&self, x: f32) -> f32 {
+        let (first, last) = match (self.keys.first(), self.keys.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return 0.0,
+        };
+
+        let value = if x <= last.at {
+            self.sample_keys(x)
+        } else {
+            match self.after {
+                After::Hold => last.value,
+                After::Extend => match self.keys.len() {
+                    0 | 1 => last.value,
+                    n => {
+                        let prev = self.keys[n - 2];
+                        let span = last.at - prev.at;
+                        if span > 0.0 {
+                            last.value + (x - last.at) * (last.value - prev.value) / span
+                        } else {
+                            last.value
+                        }
+                    }
+                },
+                After::Loop { escalation } => {
+                    let period = last.at - first.at;
+                    if period > 0.0 {
+                        let passes = ((x - first.at) / period).floor();
+                        let local = first.at + (x - first.at) - passes * period;
+                        self.sample_keys(local) + escalation * passes
+                    } else {
+                        last.value
+                    }
+                }
+            }
+        };
+
+        let value = self.min.map_or(value, |min| value.max(min));
+        return self.max.map_or(value, |max| value.min(max));
+    }
+
+    fn sample_keys(&self, x: f32) -> f32 {
+        let next_index = self.keys.iter().position(|key| key.at > x);
+        return match next_index {
+            None => self.keys.last().map_or(0.0, |key| key.value),
+            Some(0) => self.keys[0].value,
+            Some(index) => {
+                let from = self.keys[index - 1];
+                let to = self.keys[index];
+                let t = (x - from.at) / (to.at - from.at);
+                from.value + (to.value - from.value) * to.ease.apply(t)
+            }
+        };
+    }
+
+    // The range of the keys, with room to show what comes after them.
+    pub fn plot_range(&self) -> f32 {
+        let last = self.keys.last().map_or(0.0, |key| key.at);
+        return if last > 0.0 { last * 2.0 } else { 1.0 };
+    }
+}
@@ -1,7 +1,12 @@
 // SPDX-License-Identifier: GPL-3.0-only
 // 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
 
+use crate::anim::{AnimPlugin, MainFont};
+use crate::difficulty::{Curves, DifficultyCurves, RunProgress};
+use crate::pool::{PoolKind, Pooled, Pools};
+use crate::rank::Rank;
 use bevy::prelude::*;
+use bevy::sprite::Anchor;
 
 #[cfg(feature = "inspector")]
 use {
@@ -12,12 +17,274 @@ use {
 pub struct DebugPlugin;
 
 impl Plugin for DebugPlugin {
-    fn build(
-        &self,
-        #[cfg_attr(not(feature = "inspector"), allow(unused_variables))] app: &mut App,
-    ) {
+    fn build(&self, app: &mut App) {
         #[cfg(feature = "inspector")]
         app.add_plugin(WorldInspectorPlugin::new())
             .register_inspectable::<Player>();
+
+        app.add_system(toggle_curve_plot)
+            .add_system(update_curve_plot)
+            .add_system(update_rank_label)
+            .add_system(toggle_pool_stats)
+            .add_system(update_pool_stats);
+    }
+}
+
+const PLOT_KEY: KeyCode = KeyCode::F3;
+const PLOT_Z: f32 = 950.0;
+const PLOT_ORIGIN: Vec2 = Vec2::new(0.05, 0.05);
+const PLOT_SIZE: Vec2 = Vec2::new(0.5, 0.16);
+const PLOT_GAP: f32 = 0.05;
+const PLOT_ROWS: usize = 3;
+const PLOT_POINTS: usize = 100;
+const PLOT_DOT: f32 = 0.004;
+const PLOT_MARKER: f32 = 0.012;
+const PLOT_BACKGROUND: Color = Color::rgba(0.0, 0.0, 0.0, 0.7);
+const PLOT_LINE: Color = Color::rgb(0.3, 0.9, 0.3);
+const PLOT_MARKER_COLOR: Color = Color::rgb(1.0, 0.9, 0.2);
+
+const POOL_STATS_KEY: KeyCode = KeyCode::F4;
+const POOL_STATS_ORIGIN: Vec2 = Vec2::new(1.2, 0.95);
+const POOL_STATS_LINE: f32 = 0.03;
+
+#[derive(Component)]
+pub struct CurvePlot;
+
+#[derive(Component)]
+pub struct CurvePlotMarker {
+    index: usize,
+    origin: Vec2,
+    range: f32,
+    low: f32,
+    high: f32,
+}
+
+impl CurvePlotMarker {
+    fn to_plot(&self, x: f32, y: f32) -> Vec2 {
+        let x = (x / self.range).clamp(0.0, 1.0);
+        let y = ((y - self.low) / (self.high - self.low)).clamp(0.0, 1.0);
+        return self.origin + Vec2::new(x, y) * PLOT_SIZE;
+    }
+}
+
+#[derive(Component)]
+pub struct CurvePlotLabel(usize);
+
+#[derive(Component)]
+pub struct RankLabel;
+
+#[derive(Component)]
+pub struct PoolStats;
+
+// None is the line with the total number of entities.
+#[derive(Component)]
+pub struct PoolStatsLabel(Option<PoolKind>);
+
+fn dot_bundle(pos: Vec2, z: f32, size: f32, color: Color) -> SpriteBundle {
+    return SpriteBundle {
+        sprite: Sprite {
+            color,
+            custom_size: Some(Vec2::splat(size)),
+            ..default()
+        },
+        transform: Transform::from_translation(pos.extend(z)),
+        ..default()
+    };
+}
+
+// F3 shows every difficulty curve with the current run marked on it,
+// along with the hidden rank.
+fn toggle_curve_plot(
+    mut commands: Commands,
+    plot_q: Query<Entity, With<CurvePlot>>,
+    kbd: Res<Input<KeyCode>>,
+    curves: Res<Curves>,
+    curve_assets: Res<Assets<DifficultyCurves>>,
+    font: Res<MainFont>,
+) {
+    if !kbd.just_pressed(PLOT_KEY) {
+        return;
+    }
+
+    if let Ok(plot) = plot_q.get_single() {
+        commands.entity(plot).despawn_recursive();
+        return;
+    }
+
+    let curves = match curve_assets.get(&curves.0) {
+        Some(curves) => curves,
+        None => return,
+    };
+
+    commands
+        .spawn_bundle(SpatialBundle::from_transform(Transform::from_xyz(
+            0.0, 0.0, PLOT_Z,
+        )))
+        .insert(CurvePlot)
+        .insert(Name::new("CurvePlot"))
+        .with_children(|parent| {
+            for (index, (_, curve)) in curves.all().into_iter().enumerate() {
+                let cell = Vec2::new((index / PLOT_ROWS) as f32, (index % PLOT_ROWS) as f32);
+                let origin = PLOT_ORIGIN + cell * (PLOT_SIZE + PLOT_GAP);
+                let range = curve.plot_range();
+                let points: Vec<(f32, f32)> = (0..=PLOT_POINTS)
+                    .map(|i| {
+                        let x = range * i as f32 / PLOT_POINTS as f32;
+                        (x, curve.sample(x))
+                    })
+                    .collect();
+                let low = points.iter().map(|p| p.1).fold(f32::INFINITY, f32::min);
+                let high = points.iter().map(|p| p.1).fold(f32::NEG_INFINITY, f32::max);
+                let (low, high) = if (high - low).abs() < f32::EPSILON {
+                    (low - 0.5, high + 0.5)
+                } else {
+                    (low, high)
+                };
+                let marker = CurvePlotMarker {
+                    index,
+                    origin,
+                    range,
+                    low,
+                    high,
+                };
+
+                parent.spawn_bundle(SpriteBundle {
+                    sprite: Sprite {
+                        color: PLOT_BACKGROUND,
+                        custom_size: Some(PLOT_SIZE),
+                        anchor: Anchor::BottomLeft,
+                        ..default()
+                    },
+                    transform: Transform::from_translation(origin.extend(0.0)),
+                    ..default()
+                });
+
+                for (x, y) in points {
+                    parent.spawn_bundle(dot_bundle(marker.to_plot(x, y), 0.1, PLOT_DOT, PLOT_LINE));
+                }
+
+                parent
+                    .spawn_bundle(dot_bundle(origin, 0.2, PLOT_MARKER, PLOT_MARKER_COLOR))
+                    .insert(marker);
+
+                let mut label = AnimPlugin::text_bundle(
+                    &font.0,
+                    "",
+                    20.0,
+                    (origin + Vec2::new(0.01, PLOT_SIZE.y - 0.015)).extend(0.3),
+                );
+                label.text.alignment.horizontal = HorizontalAlign::Left;
+                label.visibility.is_visible = true;
+                parent.spawn_bundle(label).insert(CurvePlotLabel(index));
+            }
+
+            let top = PLOT_ORIGIN.y + (PLOT_SIZE.y + PLOT_GAP) * PLOT_ROWS as f32;
+            let mut label =
+                AnimPlugin::text_bundle(&font.0, "", 25.0, Vec3::new(PLOT_ORIGIN.x, top, 0.3));
+            label.text.alignment.horizontal = HorizontalAlign::Left;
+            label.visibility.is_visible = true;
+            parent.spawn_bundle(label).insert(RankLabel);
+        });
+}
+
+fn update_curve_plot(
+    mut marker_q: Query<(&mut Transform, &CurvePlotMarker)>,
+    mut label_q: Query<(&mut Text, &CurvePlotLabel)>,
+    run: Res<RunProgress>,
+    curves: Res<Curves>,
+    curve_assets: Res<Assets<DifficultyCurves>>,
+) {
+    let curves = match curve_assets.get(&curves.0) {
+        Some(curves) => curves.all(),
+        None => return,
+    };
+
+    for (mut transform, marker) in &mut marker_q {
+        let curve = curves[marker.index].1;
+        let x = run.progress.get(curve.driver);
+        let pos = marker.to_plot(x, curve.sample(x.min(marker.range)));
+        transform.translation = pos.extend(transform.translation.z);
+    }
+
+    for (mut text, label) in &mut label_q {
+        let (name, curve) = curves[label.0];
+        let x = run.progress.get(curve.driver);
+        text.sections[0].value = format!(
+            "{}: {:.2} AT {} {:.2}",
+            name,
+            curve.eval(&run.progress),
+            curve.driver.title(),
+            x
+        );
+    }
+}
+
+fn update_rank_label(mut q: Query<&mut Text, With<RankLabel>>, rank: Res<Rank>) {
+    for mut text in &mut q {
+        text.sections[0].value = format!(
+            "RANK: {:.2} ACCURACY: {:.0}%",
+            rank.value(),
+            rank.accuracy() * 100.0
+        );
+    }
+}
+
+// F4 shows how many entities exist and how well the pools keep up.
+fn toggle_pool_stats(
+    mut commands: Commands,
+    stats_q: Query<Entity, With<PoolStats>>,
+    kbd: Res<Input<KeyCode>>,
+    font: Res<MainFont>,
+) {
+    if !kbd.just_pressed(POOL_STATS_KEY) {
+        return;
+    }
+
+    if let Ok(stats) = stats_q.get_single() {
+        commands.entity(stats).despawn_recursive();
+        return;
+    }
+
+    commands
+        .spawn_bundle(SpatialBundle::from_transform(Transform::from_xyz(
+            0.0, 0.0, PLOT_Z,
+        )))
+        .insert(PoolStats)
+        .insert(Name::new("PoolStats"))
+        .with_children(|parent| {
+            let kinds = [None].into_iter().chain(PoolKind::ALL.map(Some));
+            for (index, kind) in kinds.enumerate() {
+                let pos = POOL_STATS_ORIGIN - Vec2::Y * POOL_STATS_LINE * index as f32;
+                let mut label = AnimPlugin::text_bundle(&font.0, "", 20.0, pos.extend(0.3));
+                label.text.alignment.horizontal = HorizontalAlign::Left;
+                label.visibility.is_visible = true;
+                parent.spawn_bundle(label).insert(PoolStatsLabel(kind));
+            }
+        });
+}
+
+fn update_pool_stats(
+    mut label_q: Query<(&mut Text, &PoolStatsLabel)>,
+    entities_q: Query<()>,
+    pooled_q: Query<&Pooled>,
+    pools: Res<Pools>,
+) {
+    for (mut text, label) in &mut label_q {
+        let kind = match label.0 {
+            Some(kind) => kind,
+            None => {
+                text.sections[0].value = format!("ENTITIES: {}", entities_q.iter().count());
+                continue;
+            }
+        };
+        let pool = pools.get(kind);
+        let total = pooled_q.iter().filter(|pooled| pooled.kind == kind).count();
+        text.sections[0].value = format!(
+            "{}: {} ACTIVE {} FREE, {:.0}% REUSED",
+            kind.title(),
+            total - pool.free(),
+            pool.free(),
+            pool.hit_rate() * 100.0
+        );
     }
 }
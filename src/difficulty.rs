@@ -0,0 +1,202 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+use crate::anim::AssetsLoading;
+use crate::curve::{Curve, Progress};
+use crate::data::RonLoader;
+use crate::rank::Rank;
+use crate::score::Score;
+use crate::state::GameState;
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use serde::{Deserialize, Serialize};
+
+pub struct DifficultyPlugin;
+
+impl Plugin for DifficultyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<DifficultyCurves>()
+            .add_asset_loader(RonLoader::<DifficultyCurves>::new(&["curves.ron"]))
+            .add_startup_system_to_stage(StartupStage::PreStartup, load_assets)
+            .insert_resource(Difficulty::Normal)
+            .insert_resource(RunProgress::default())
+            .insert_resource(Levels::default())
+            .add_system_set(SystemSet::on_enter(GameState::TitleFlyOut).with_system(reset_progress))
+            .add_system_set(SystemSet::on_update(GameState::Game).with_system(tick_progress))
+            .add_system(update_levels);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+    Insane,
+}
+
+// Multipliers are relative to Normal.
+pub struct Preset {
+    pub spawn_interval: f32,
+    pub enemy_speed: f32,
+    pub bullet_period: f32,
+    pub bullet_speed: f32,
+    pub min_distance_to_shoot: f32,
+    pub continue_cost: u32,
+}
+
+impl Difficulty {
+    const ALL: [Difficulty; 4] = [
+        Difficulty::Easy,
+        Difficulty::Normal,
+        Difficulty::Hard,
+        Difficulty::Insane,
+    ];
+
+    pub fn next(&mut self) {
+        let index = Self::ALL.iter().position(|d| d == self).unwrap();
+        *self = Self::ALL[(index + 1) % Self::ALL.len()];
+    }
+
+    pub fn prev(&mut self) {
+        let index = Self::ALL.iter().position(|d| d == self).unwrap();
+        *self = Self::ALL[(index + Self::ALL.len() - 1) % Self::ALL.len()];
+    }
+
+    pub fn title(&self) -> &'static str {
+        return match self {
+            Difficulty::Easy => "EASY",
+            Difficulty::Normal => "NORMAL",
+            Difficulty::Hard => "HARD",
+            Difficulty::Insane => "INSANE",
+        };
+    }
+
+    pub fn preset(&self) -> &'static Preset {
+        return match self {
+            Difficulty::Easy => &Preset {
+                spawn_interval: 1.4,
+                enemy_speed: 0.8,
+                bullet_period: 1.5,
+                bullet_speed: 0.75,
+                min_distance_to_shoot: 0.7,
+                continue_cost: 25,
+            },
+            Difficulty::Normal => &Preset {
+                spawn_interval: 1.0,
+                enemy_speed: 1.0,
+                bullet_period: 1.0,
+                bullet_speed: 1.0,
+                min_distance_to_shoot: 0.5,
+                continue_cost: 50,
+            },
+            Difficulty::Hard => &Preset {
+                spawn_interval: 0.8,
+                enemy_speed: 1.15,
+                bullet_period: 0.75,
+                bullet_speed: 1.2,
+                min_distance_to_shoot: 0.35,
+                continue_cost: 75,
+            },
+            Difficulty::Insane => &Preset {
+                spawn_interval: 0.6,
+                enemy_speed: 1.3,
+                bullet_period: 0.5,
+                bullet_speed: 1.4,
+                min_distance_to_shoot: 0.2,
+                continue_cost: 100,
+            },
+        };
+    }
+}
+
+// How the game gets harder as the run goes on.
+#[derive(Deserialize, TypeUuid)]
+#[uuid = "3f9d6b2a-8e41-4c7a-a5d3-6b0e2f8c1d97"]
+pub struct DifficultyCurves {
+    pub emitters: Curve,
+    pub spawn_interval: Curve,
+    pub pattern_chance: Curve,
+    pub enemy_speed: Curve,
+    pub bullet_speed: Curve,
+    pub aggression: Curve,
+}
+
+impl DifficultyCurves {
+    pub fn all(&self) -> [(&'static str, &Curve); 6] {
+        return [
+            ("EMITTERS", &self.emitters),
+            ("SPAWN INTERVAL", &self.spawn_interval),
+            ("PATTERN CHANCE", &self.pattern_chance),
+            ("ENEMY SPEED", &self.enemy_speed),
+            ("BULLET SPEED", &self.bullet_speed),
+            ("AGGRESSION", &self.aggression),
+        ];
+    }
+}
+
+pub struct Curves(pub Handle<DifficultyCurves>);
+
+#[derive(Default, Clone)]
+pub struct RunProgress {
+    time: f32,
+    pub progress: Progress,
+}
+
+// Current values of the difficulty curves.
+#[derive(Default, Clone)]
+pub struct Levels {
+    pub emitters: f32,
+    pub spawn_interval: f32,
+    pub pattern_chance: f32,
+    pub enemy_speed: f32,
+    pub bullet_speed: f32,
+    pub aggression: f32,
+}
+
+fn load_assets(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    mut loading: ResMut<AssetsLoading>,
+) {
+    let handle = assets.load("difficulty.curves.ron");
+    loading.push(handle.clone_untyped());
+    commands.insert_resource(Curves(handle));
+}
+
+fn reset_progress(mut run: ResMut<RunProgress>) {
+    *run = RunProgress::default();
+}
+
+fn tick_progress(mut run: ResMut<RunProgress>, time: Res<Time>) {
+    run.time += time.delta_seconds();
+}
+
+fn update_levels(
+    mut run: ResMut<RunProgress>,
+    mut levels: ResMut<Levels>,
+    score: Res<Score>,
+    rank: Res<Rank>,
+    curves: Res<Curves>,
+    curve_assets: Res<Assets<DifficultyCurves>>,
+) {
+    run.progress = Progress {
+        score: score.score as f32,
+        time: run.time,
+        rank: rank.value(),
+    };
+
+    let curves = match curve_assets.get(&curves.0) {
+        Some(curves) => curves,
+        None => return,
+    };
+    let progress = &run.progress;
+    *levels = Levels {
+        emitters: curves.emitters.eval(progress),
+        spawn_interval: curves.spawn_interval.eval(progress),
+        pattern_chance: curves.pattern_chance.eval(progress),
+        enemy_speed: curves.enemy_speed.eval(progress),
+        bullet_speed: curves.bullet_speed.eval(progress),
+        aggression: curves.aggression.eval(progress),
+    };
+}
@@ -0,0 +1,329 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+use crate::aim;
+use crate::bullet::{Bullet, BulletCancel, BulletMotion, BulletRes, BulletTimer, HomingTarget};
+use crate::collision::CollisionLayers;
+use crate::difficulty::{Difficulty, Levels};
+use crate::enemy::EnemyBullet;
+use crate::grid::Layers;
+use crate::health::{Damage, DamageKind};
+use crate::player::Player;
+use crate::pool::Pools;
+use crate::sim::SimRng;
+use crate::state::GameState;
+use bevy::prelude::*;
+use rand::Rng;
+use serde::Deserialize;
+use std::f32::consts::TAU;
+
+pub struct EmitterPlugin;
+
+impl Plugin for EmitterPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_update(GameState::Game).with_system(fire));
+    }
+}
+
+const BULLET_Z: f32 = 0.3;
+const BULLET_DAMAGE: Damage = Damage::new(1.0, DamageKind::Kinetic);
+
+// How much the patterns grow at difficulty level 1.
+const EXTRA_BULLETS: f32 = 0.5;
+const EXTRA_SPEED: f32 = 0.3;
+const PERIOD_REDUCTION: f32 = 0.5;
+const ACCURACY_GAIN: f32 = 0.5;
+
+// The difficulty curve can go past 1, but the period cannot shrink forever.
+const MIN_PERIOD_FACTOR: f32 = 0.2;
+
+// At this accuracy the aim error equals the pattern's jitter.
+pub const DEFAULT_ACCURACY: f32 = 0.5;
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum Shape {
+    Single,
+    Fan { count: u32, spread: f32 },
+    Ring { count: u32 },
+    Spiral { arms: u32, step: f32 },
+}
+
+impl Shape {
+    fn directions(&self, base: Vec2, spin: f32, difficulty: f32) -> Vec<Vec2> {
+        let scale = |count: u32| count + (count as f32 * EXTRA_BULLETS * difficulty).round() as u32;
+
+        let angles: Vec<f32> = match self {
+            Shape::Single => vec![0.0],
+            Shape::Fan { count, spread } => {
+                let count = scale(*count);
+                if count < 2 {
+                    vec![0.0]
+                } else {
+                    (0..count)
+                        .map(|i| spread * (i as f32 / (count - 1) as f32 - 0.5))
+                        .collect()
+                }
+            }
+            Shape::Ring { count } => {
+                let count = scale(*count).max(1);
+                (0..count).map(|i| TAU * i as f32 / count as f32).collect()
+            }
+            Shape::Spiral { arms, .. } => {
+                let arms = scale(*arms).max(1);
+                (0..arms)
+                    .map(|i| spin + TAU * i as f32 / arms as f32)
+                    .collect()
+            }
+        };
+
+        return angles
+            .into_iter()
+            .map(|angle| Vec2::from_angle(angle).rotate(base))
+            .collect();
+    }
+}
+
+// Directions are relative to the emitter's forward vector,
+// or to the player if the pattern is aimed.
+// The default is a single loosely aimed shot.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct EmitterPattern {
+    shape: Shape,
+    aimed: bool,
+    angle: f32,
+    jitter: f32,
+    speed: f32,
+    speed_step: f32,
+    period: f32,
+    volleys: u32,
+    volley_interval: f32,
+    delay: f32,
+    lead: bool,
+    cage: f32,
+    motion: Option<BulletMotion>,
+    // Whether player shots can destroy these bullets when bullet cancelling is on.
+    cancellable: bool,
+}
+
+impl Default for EmitterPattern {
+    fn default() -> Self {
+        return Self {
+            shape: Shape::Single,
+            aimed: true,
+            angle: 0.0,
+            jitter: 0.25,
+            speed: 1.0,
+            speed_step: 0.0,
+            period: 3.0,
+            volleys: 1,
+            volley_interval: 0.1,
+            delay: 0.0,
+            lead: false,
+            cage: 0.0,
+            motion: None,
+            cancellable: true,
+        };
+    }
+}
+
+impl EmitterPattern {
+    fn period(&self, rate: f32, difficulty: f32) -> f32 {
+        let factor = (1.0 - PERIOD_REDUCTION * difficulty).max(MIN_PERIOD_FACTOR);
+        return self.period * factor / rate;
+    }
+}
+
+#[derive(Clone)]
+pub struct Emitter {
+    pattern: EmitterPattern,
+    rate: f32,
+    accuracy: f32,
+    timer: BulletTimer,
+    volleys_left: u32,
+    next_volley: f32,
+    spin: f32,
+}
+
+impl Emitter {
+    pub fn new(pattern: EmitterPattern, rate: f32, difficulty: f32) -> Self {
+        let delay = pattern.delay;
+        return Self::with_delay(pattern, rate, difficulty, delay);
+    }
+
+    // Keeps a group of identical enemies from firing in unison.
+    pub fn with_random_delay(
+        pattern: EmitterPattern,
+        rate: f32,
+        difficulty: f32,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let period = pattern.period(rate, difficulty);
+        let delay = pattern.delay + rng.gen_range(0.0..period);
+        return Self::with_delay(pattern, rate, difficulty, delay);
+    }
+
+    pub fn with_accuracy(mut self, accuracy: f32) -> Self {
+        self.accuracy = accuracy.clamp(0.0, 1.0);
+        return self;
+    }
+
+    fn with_delay(pattern: EmitterPattern, rate: f32, difficulty: f32, delay: f32) -> Self {
+        let period = pattern.period(rate, difficulty);
+        return Self {
+            timer: BulletTimer::new_delayed(period.max(delay), delay),
+            pattern,
+            rate,
+            accuracy: DEFAULT_ACCURACY,
+            volleys_left: 0,
+            next_volley: 0.0,
+            spin: 0.0,
+        };
+    }
+
+    pub fn elapsed(&self) -> f32 {
+        return self.timer.elapsed();
+    }
+}
+
+#[derive(Component, Clone)]
+pub struct Emitters(pub Vec<Emitter>);
+
+fn fire(
+    mut commands: Commands,
+    mut q: Query<(&GlobalTransform, &mut Emitters)>,
+    player_q: Query<(Entity, &Transform, &Player)>,
+    (time, levels, preset, cancel): (Res<Time>, Res<Levels>, Res<Difficulty>, Res<BulletCancel>),
+    (bullet_res, mut pools, audio): (Res<BulletRes>, ResMut<Pools>, Res<Audio>),
+    mut rng: ResMut<SimRng>,
+) {
+    let players: Vec<(Entity, Vec2, Vec2)> = player_q
+        .iter()
+        .map(|(player, transform, state)| {
+            (player, transform.translation.truncate(), state.velocity())
+        })
+        .collect();
+    if players.is_empty() {
+        return;
+    }
+    let difficulty = levels.emitters;
+    let preset = preset.preset();
+    let mut fired = false;
+
+    for (transform, mut emitters) in &mut q {
+        let pos = transform.translation().truncate();
+        // Each enemy goes after the closest player.
+        let (player, player_pos, player_velocity) = players
+            .iter()
+            .copied()
+            .min_by(|a, b| pos.distance(a.1).total_cmp(&pos.distance(b.1)))
+            .unwrap();
+        let forward = transform
+            .affine()
+            .transform_vector3(Vec3::NEG_X)
+            .truncate()
+            .normalize_or_zero();
+
+        for emitter in &mut emitters.0 {
+            emitter.timer.process(time.delta());
+
+            if emitter.timer.can_shoot && emitter.volleys_left == 0 {
+                let in_range = pos.distance(player_pos) > preset.min_distance_to_shoot;
+                let in_front = !emitter.pattern.aimed || pos.x > player_pos.x;
+                if !in_range || !in_front {
+                    continue;
+                }
+
+                emitter.timer.shoot();
+                let period = emitter.pattern.period(emitter.rate, difficulty);
+                emitter
+                    .timer
+                    .set_period(period * preset.bullet_period / levels.aggression);
+                emitter.volleys_left = emitter.pattern.volleys;
+                emitter.next_volley = 0.0;
+            }
+
+            if emitter.volleys_left == 0 {
+                continue;
+            }
+
+            emitter.next_volley -= time.delta_seconds();
+            while emitter.volleys_left > 0 && emitter.next_volley <= 0.0 {
+                let pattern = &emitter.pattern;
+                let volley_index = pattern.volleys - emitter.volleys_left;
+                let speed = (pattern.speed + pattern.speed_step * volley_index as f32)
+                    * (1.0 + EXTRA_SPEED * difficulty)
+                    * preset.bullet_speed
+                    * levels.bullet_speed;
+
+                let bases = if pattern.aimed {
+                    let accuracy = (emitter.accuracy
+                        + (1.0 - emitter.accuracy) * ACCURACY_GAIN * difficulty)
+                        .min(1.0);
+                    let error = pattern.jitter * 2.0 * (1.0 - accuracy);
+                    let point = if pattern.lead {
+                        aim::predict(pos, player_pos, player_velocity, speed)
+                    } else {
+                        player_pos
+                    };
+                    let mut points = vec![point];
+                    if pattern.cage > 0.0 {
+                        points.extend(aim::cage(pos, point, pattern.cage));
+                    }
+                    points
+                        .into_iter()
+                        .map(|point| {
+                            (aim::scatter(pos, point, error, &mut *rng) - pos).normalize_or_zero()
+                        })
+                        .collect()
+                } else {
+                    vec![forward]
+                };
+
+                let mask = if pattern.cancellable && *cancel == BulletCancel::On {
+                    Layers::PLAYER | Layers::PLAYER_BULLET
+                } else {
+                    Layers::PLAYER
+                };
+                let start = pos.extend(BULLET_Z);
+                for base in bases {
+                    let base = Vec2::from_angle(pattern.angle).rotate(base);
+                    for dir in pattern.shape.directions(base, emitter.spin, difficulty) {
+                        let bullet = Bullet::spawn_silent(
+                            &mut commands,
+                            &mut pools,
+                            &bullet_res.enemy,
+                            start,
+                            start + dir.extend(0.0),
+                            speed,
+                            Color::WHITE,
+                        );
+                        commands
+                            .entity(bullet)
+                            .insert(EnemyBullet)
+                            .insert(BULLET_DAMAGE)
+                            .insert(CollisionLayers::new(Layers::ENEMY_BULLET, mask))
+                            .insert(Name::new("EnemyBullet"));
+                        if let Some(motion) = pattern.motion {
+                            commands
+                                .entity(bullet)
+                                .insert(motion)
+                                .insert(HomingTarget(Some(player)));
+                        }
+                    }
+                }
+                fired = true;
+
+                if let Shape::Spiral { step, .. } = pattern.shape {
+                    emitter.spin = (emitter.spin + step) % TAU;
+                }
+                emitter.volleys_left -= 1;
+                emitter.next_volley += pattern.volley_interval;
+            }
+        }
+    }
+
+    if fired {
+        Bullet::play_sound(&bullet_res.enemy, &audio);
+    }
+}
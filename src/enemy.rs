@@ -2,66 +2,143 @@
 // 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
 
 use crate::anim::{AnimationTimer, AssetsLoading};
-use crate::bullet::{Bullet, BulletRes, BulletTimer, HitBox};
-use crate::collision::{DestroyOutsideScreen, Screen};
-use crate::explosion::{Explosion, ExplosionAudio, ExplosionSheet};
-use crate::player::{Player, PlayerBullet};
-use crate::score::Score;
+use crate::boss::Boss;
+use crate::collision::{Collider, CollisionLayers, DestroyOutsideScreen};
+use crate::data::RonLoader;
+use crate::difficulty::{Difficulty, Levels};
+use crate::emitter::{Emitter, EmitterPattern, Emitters, DEFAULT_ACCURACY};
+use crate::grid::Layers;
+use crate::health::{Damage, DamageKind, Health, Resistances};
+use crate::hit::HitFlash;
+use crate::pattern::{MovementPattern, Pattern, PatternLibrary, Patterns, Side};
+use crate::player::Player;
+use crate::score::Points;
+use crate::shape::Shape;
+use crate::sim::SimRng;
+use crate::stage::StageRunner;
 use crate::{AnimPlugin, GameState};
 use bevy::prelude::*;
-use bevy::sprite::collide_aabb::collide;
+use bevy::reflect::TypeUuid;
+use rand::seq::SliceRandom;
 use rand::Rng;
+use serde::Deserialize;
+use std::collections::BTreeMap;
 use std::time::Duration;
 
 pub struct EnemyPlugin;
 
 impl Plugin for EnemyPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_system_to_stage(StartupStage::PreStartup, load_assets)
+        app.add_asset::<EnemyArchetypes>()
+            .add_asset_loader(RonLoader::<EnemyArchetypes>::new(&["archetypes.ron"]))
+            .add_startup_system_to_stage(StartupStage::PreStartup, load_assets)
             .add_startup_system(setup)
-            .add_system(movement)
-            .add_system(bullet_hit)
-            .add_system_set(
-                SystemSet::on_update(GameState::Game)
-                    .with_system(process_spawn)
-                    .with_system(attack),
-            );
+            .add_system_set(SystemSet::on_update(GameState::Game).with_system(process_spawn));
     }
 }
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct Enemy {
-    speed: Vec3,
+    pub archetype: String,
 }
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct EnemySpawn {
     timer: Timer,
 }
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct EnemyBullet;
 
 #[derive(Component)]
 pub struct EnemiesContainer;
 
 pub struct EnemyGraphics {
-    ship_atlas: Handle<TextureAtlas>,
+    pub ship_atlas: Handle<TextureAtlas>,
     exhaust_atlas: Handle<TextureAtlas>,
 }
 
+#[derive(Deserialize, Clone)]
+#[serde(default)]
+pub struct EnemyArchetype {
+    // The key it is listed under.
+    #[serde(skip)]
+    name: String,
+    scale: f32,
+    color: Color,
+    fire_rate: f32,
+    hp: u32,
+    // Seconds without damage after each hit.
+    invulnerability: f32,
+    accuracy: f32,
+    emitters: Vec<EmitterPattern>,
+    resistances: Resistances,
+    // In the ship's own space, before the scale.
+    collider: Shape,
+}
+
+impl Default for EnemyArchetype {
+    fn default() -> Self {
+        return Self {
+            name: "default".to_string(),
+            scale: 1.0,
+            color: Color::WHITE,
+            fire_rate: 1.0,
+            hp: 1,
+            invulnerability: 0.0,
+            accuracy: DEFAULT_ACCURACY,
+            emitters: vec![EmitterPattern::default()],
+            resistances: Resistances::default(),
+            collider: Shape::Circle {
+                radius: SIZE_X * 0.3,
+            },
+        };
+    }
+}
+
+impl EnemyArchetype {
+    pub fn size(&self) -> Vec2 {
+        return Vec2::new(SIZE_X, SIZE_Y) * self.scale;
+    }
+}
+
+#[derive(Deserialize, TypeUuid)]
+#[serde(transparent)]
+#[uuid = "3f1d5a0e-2c4b-4e8f-8a7d-5b9e6c1f2d30"]
+pub struct EnemyArchetypes(BTreeMap<String, EnemyArchetype>);
+
+impl EnemyArchetypes {
+    pub fn get(&self, name: &str) -> EnemyArchetype {
+        return match self.0.get(name) {
+            Some(archetype) => EnemyArchetype {
+                name: name.to_string(),
+                ..archetype.clone()
+            },
+            None => {
+                warn!("unknown enemy archetype: {}", name);
+                EnemyArchetype::default()
+            }
+        };
+    }
+}
+
+pub struct Archetypes(pub Handle<EnemyArchetypes>);
+
 const WIDTH: f32 = 150.0;
 const HEIGHT: f32 = 150.0;
 const SIZE_X: f32 = 0.15;
 const SIZE_Y: f32 = SIZE_X * HEIGHT / WIDTH;
-const MIN_DISTANCE_TO_SHOOT: f32 = 0.5;
-const BULLET_SPEED: f32 = 1.0;
 
 const EXHAUST_WIDTH: f32 = 75.0;
 const EXHAUST_HEIGHT: f32 = 64.0;
 const EXHAUST_SIZE_X: f32 = 0.05;
 const EXHAUST_SIZE_Y: f32 = EXHAUST_SIZE_X * EXHAUST_HEIGHT / EXHAUST_WIDTH;
 
+// Enough to take down the player, who has a single hit point.
+const RAM_DAMAGE: f32 = 1.0;
+
+const RANDOM_SIDES: [Side; 3] = [Side::Right, Side::Top, Side::Bottom];
+
 fn setup(mut commands: Commands) {
     commands
         .spawn()
@@ -79,26 +156,42 @@ fn setup(mut commands: Commands) {
 }
 
 fn process_spawn(
-    commands: Commands,
+    mut commands: Commands,
     mut q: Query<&mut EnemySpawn>,
-    score: Res<Score>,
     player_q: Query<&Transform, With<Player>>,
-    time: Res<Time>,
-    graphics: Res<EnemyGraphics>,
-    win: Res<WindowDescriptor>,
+    bosses: Query<(), With<Boss>>,
+    (runner, time, levels, difficulty): (Res<StageRunner>, Res<Time>, Res<Levels>, Res<Difficulty>),
+    (patterns, pattern_libraries): (Res<Patterns>, Res<Assets<PatternLibrary>>),
+    (graphics, win, mut rng): (Res<EnemyGraphics>, Res<WindowDescriptor>, ResMut<SimRng>),
 ) {
+    if !runner.is_endless() || !bosses.is_empty() {
+        return;
+    }
+
     let mut spawn_el = q.single_mut();
     spawn_el.timer.tick(time.delta());
 
     if spawn_el.timer.just_finished() {
-        let mut rng = rand::thread_rng();
-        let y = rng.gen_range(0.1..1.0);
-        if let Ok(player) = player_q.get_single() {
+        let rng = &mut *rng;
+        let players: Vec<&Transform> = player_q.iter().collect();
+        if let Some(player) = players.choose(rng) {
             let player_pos = player.translation;
+            let library = pattern_libraries.get(&patterns.0).unwrap();
+
+            let pattern_chance = levels.pattern_chance.clamp(0.0, 1.0);
+            let movements = if rng.gen_bool(pattern_chance as f64) {
+                random_wave(library, &win, rng)
+            } else {
+                let y = rng.gen_range(0.1..1.0);
+                vec![aimed_line(&win, player_pos, levels.enemy_speed, y, rng)]
+            };
+
+            let archetype = EnemyArchetype::default();
+            for movement in movements {
+                spawn(&mut commands, &graphics, &archetype, movement, &levels, rng);
+            }
 
-            spawn(commands, graphics, win, player_pos, &score, y);
-
-            let timer_secs = score.interp(0.6, 0.3, 200);
+            let timer_secs = levels.spawn_interval * difficulty.preset().spawn_interval;
             spawn_el
                 .timer
                 .set_duration(Duration::from_secs_f32(timer_secs));
@@ -106,53 +199,112 @@ fn process_spawn(
     }
 }
 
-fn spawn(
-    mut commands: Commands,
-    graphics: Res<EnemyGraphics>,
-    win: Res<WindowDescriptor>,
+fn aimed_line(
+    win: &WindowDescriptor,
     player_pos: Vec3,
-    score: &Score,
+    speed: f32,
     y: f32,
-) {
-    let mut enemy_sprite = TextureAtlasSprite::new(0);
-    let mut rng = rand::thread_rng();
+    rng: &mut impl Rng,
+) -> MovementPattern {
+    let pos = Side::Right.entry_point(y, Vec2::new(SIZE_X, SIZE_Y), win);
+    let speed = (player_pos.truncate() - pos).normalize() * speed;
+
+    let angle = rng.gen_range(-0.3..0.3);
+    let speed = Vec2::from_angle(angle).rotate(speed);
 
-    let bounds = win.bounds_box_outside(Vec2::new(SIZE_X, SIZE_Y));
+    return MovementPattern::new(Pattern::Linear(speed), Side::Right, pos);
+}
 
-    let pos = Vec3::new(bounds.right, y, 100.0);
-    let speed = player_pos - pos;
-    let speed = Vec3::new(speed.x, speed.y, 0.0).normalize();
-    let speed = speed * score.interp(0.5, 2.0, 200);
+fn random_wave(
+    library: &PatternLibrary,
+    win: &WindowDescriptor,
+    rng: &mut impl Rng,
+) -> Vec<MovementPattern> {
+    let side = *RANDOM_SIDES.choose(rng).unwrap();
+    let pos = match side {
+        Side::Left | Side::Right => rng.gen_range(0.1..0.9),
+        Side::Top | Side::Bottom => rng.gen_range(0.3..0.9),
+    };
+    let origin = side.entry_point(pos, Vec2::new(SIZE_X, SIZE_Y), win);
+
+    if rng.gen_bool(0.5) {
+        if let Some(name) = library.formation_names().choose(rng) {
+            let formation = library.formation(name).unwrap();
+            return library.formation_members(formation, side, origin, win);
+        }
+    }
 
-    let angle = rng.gen_range(-0.3..0.3);
-    let rot = Quat::from_rotation_z(angle);
-    let speed = rot.mul_vec3(speed);
+    return match library.pattern_names().choose(rng) {
+        Some(name) => library
+            .pattern(name)
+            .map(|pattern| MovementPattern::new(pattern, side, origin))
+            .into_iter()
+            .collect(),
+        None => Vec::new(),
+    };
+}
+
+pub fn spawn(
+    commands: &mut Commands,
+    graphics: &EnemyGraphics,
+    archetype: &EnemyArchetype,
+    movement: MovementPattern,
+    levels: &Levels,
+    rng: &mut impl Rng,
+) -> Entity {
+    let mut enemy_sprite = TextureAtlasSprite::new(0);
 
-    let bullet_period = score.interp(3.0, 1.5, 200);
-    let bullet_delay = rng.gen_range(0.0..bullet_period);
+    let mut transform = Transform {
+        translation: movement.position().extend(100.0),
+        scale: Vec3::splat(archetype.scale),
+        ..default()
+    };
+    movement.side().orient(&mut transform);
 
     enemy_sprite.custom_size = Some(Vec2::new(SIZE_X, SIZE_Y));
     enemy_sprite.flip_x = true;
+    enemy_sprite.color = archetype.color;
     let enemy = commands
         .spawn_bundle(SpriteSheetBundle {
             sprite: enemy_sprite,
             texture_atlas: graphics.ship_atlas.clone(),
-            transform: Transform {
-                translation: pos,
-                ..default()
-            },
+            transform,
             ..default()
         })
-        .insert(Enemy { speed })
-        .insert(HitBox(Vec2::new(SIZE_X * 0.5, SIZE_Y * 0.5)))
+        .insert(Enemy {
+            archetype: archetype.name.clone(),
+        })
+        .insert(Health::new(archetype.hp as f32).with_invulnerability(archetype.invulnerability))
+        .insert(archetype.resistances.clone())
+        .insert(Damage::new(RAM_DAMAGE, DamageKind::Ram))
+        .insert(Points(1))
+        .insert(HitFlash::new(archetype.color))
+        .insert(movement)
+        .insert(Collider(archetype.collider.clone()))
+        .insert(CollisionLayers::new(
+            Layers::ENEMY,
+            Layers::PLAYER | Layers::PLAYER_BULLET,
+        ))
         .insert(DestroyOutsideScreen {
-            size: Vec2::new(SIZE_X, SIZE_Y),
+            size: archetype.size(),
         })
-        .insert(BulletTimer::new_delayed(bullet_period, bullet_delay))
         .insert(Name::new("Enemy"))
         .insert(GlobalTransform::default())
         .id();
 
+    if archetype.fire_rate > 0.0 && !archetype.emitters.is_empty() {
+        let difficulty = levels.emitters;
+        let emitters = archetype
+            .emitters
+            .iter()
+            .map(|pattern| {
+                Emitter::with_random_delay(pattern.clone(), archetype.fire_rate, difficulty, rng)
+                    .with_accuracy(archetype.accuracy)
+            })
+            .collect();
+        commands.entity(enemy).insert(Emitters(emitters));
+    }
+
     let mut exhaust_sprite = TextureAtlasSprite::new(0);
     exhaust_sprite.custom_size = Some(Vec2::new(EXHAUST_SIZE_X, EXHAUST_SIZE_Y));
     let exhaust = commands
@@ -170,6 +322,7 @@ fn spawn(
         .id();
 
     commands.entity(enemy).add_child(exhaust);
+    return enemy;
 }
 
 fn load_assets(
@@ -202,102 +355,8 @@ fn load_assets(
         ship_atlas,
         exhaust_atlas,
     });
-}
-
-fn bullet_hit(
-    mut commands: Commands,
-    enemy_query: Query<(Entity, &Transform, &HitBox), With<Enemy>>,
-    player_bullet_query: Query<(Entity, &Transform, &HitBox), With<PlayerBullet>>,
-    mut score: ResMut<Score>,
-    explosion_sheet: Res<ExplosionSheet>,
-    explosion_audio: Res<ExplosionAudio>,
-    audio: Res<Audio>,
-) {
-    for (enemy, enemy_pos, enemy_hitbox) in &enemy_query {
-        for (bullet, bullet_pos, bullet_hitbox) in &player_bullet_query {
-            if collide(
-                enemy_pos.translation,
-                enemy_hitbox.0,
-                bullet_pos.translation,
-                bullet_hitbox.0,
-            )
-            .is_some()
-            {
-                commands.entity(enemy).despawn_recursive();
-                commands.entity(bullet).despawn_recursive();
-
-                Explosion::spawn(
-                    &mut commands,
-                    &explosion_sheet,
-                    enemy_pos.translation,
-                    0.05,
-                    &explosion_audio,
-                    &audio,
-                );
-
-                score.inc();
-                break;
-            }
-        }
-    }
-}
 
-fn movement(mut q: Query<(&Enemy, &mut Transform)>, time: Res<Time>) {
-    for (enemy, mut transform) in &mut q {
-        transform.translation += enemy.speed * time.delta_seconds();
-    }
-}
-
-fn attack(
-    mut commands: Commands,
-    mut q: Query<(&Transform, &mut BulletTimer), With<Enemy>>,
-    player_q: Query<&Transform, With<Player>>,
-    time: Res<Time>,
-    bullet_res: Res<BulletRes>,
-    audio: Res<Audio>,
-) {
-    if let Ok(player_transform) = player_q.get_single() {
-        let player_pos = Vec3::new(
-            player_transform.translation.x,
-            player_transform.translation.y,
-            0.3,
-        );
-
-        let mut rng = rand::thread_rng();
-
-        for (enemy_transform, mut bullet_timer) in &mut q {
-            bullet_timer.process(time.delta());
-
-            if bullet_timer.can_shoot && enemy_transform.translation.x > player_pos.x {
-                let enemy_pos = Vec3::new(
-                    enemy_transform.translation.x,
-                    enemy_transform.translation.y,
-                    0.3,
-                );
-                let dist = enemy_pos.distance(player_pos);
-
-                let max_diff_y = 0.25;
-                let diff_y = rng.gen_range(-max_diff_y..max_diff_y);
-                let mut target_pos = player_pos.clone();
-                target_pos.y += diff_y;
-
-                if dist > MIN_DISTANCE_TO_SHOOT {
-                    let bullet = Bullet::spawn(
-                        &mut commands,
-                        &bullet_res.enemy,
-                        enemy_pos,
-                        target_pos,
-                        BULLET_SPEED,
-                        Color::WHITE,
-                        &audio,
-                    );
-                    commands
-                        .entity(bullet)
-                        .insert(EnemyBullet)
-                        .insert(Name::new("EnemyBullet"));
-                    bullet_timer.shoot();
-                }
-            }
-        }
-    }
+    let archetypes = assets.load("enemies.archetypes.ron");
+    loading.push(archetypes.clone_untyped());
+    commands.insert_resource(Archetypes(archetypes));
 }
@@ -4,6 +4,7 @@
 use std::f32::consts::PI;
 
 use crate::anim::{AnimationTimer, AssetsLoading};
+use crate::pool::{PoolKind, Pooled, Pools, ReleaseExt};
 use crate::AnimPlugin;
 use bevy::prelude::*;
 use rand::Rng;
@@ -17,25 +18,87 @@ impl Plugin for ExplosionPlugin {
     }
 }
 
-#[derive(Component)]
+#[derive(Component, Clone)]
 pub struct Explosion;
 
 impl Explosion {
     pub fn spawn(
         commands: &mut Commands,
+        pools: &mut Pools,
         sheet: &Res<ExplosionSheet>,
         pos: Vec3,
         frame_duration: f32,
         explosion_audio: &Res<ExplosionAudio>,
         audio: &Res<Audio>,
+    ) {
+        Self::spawn_scaled(commands, pools, sheet, pos, frame_duration, 1.0);
+        Self::play_sound(explosion_audio, audio);
+    }
+
+    pub fn spawn_scaled(
+        commands: &mut Commands,
+        pools: &mut Pools,
+        sheet: &Res<ExplosionSheet>,
+        pos: Vec3,
+        frame_duration: f32,
+        scale: f32,
+    ) {
+        Self::spawn_sprite(
+            commands,
+            pools,
+            PoolKind::Explosion,
+            sheet,
+            pos,
+            frame_duration,
+            scale,
+        );
+    }
+
+    pub fn play_sound(explosion_audio: &Res<ExplosionAudio>, audio: &Res<Audio>) {
+        audio.play_with_settings(
+            explosion_audio.0.clone(),
+            PlaybackSettings {
+                volume: 0.25,
+                ..default()
+            },
+        );
+    }
+
+    pub fn spawn_spark(
+        commands: &mut Commands,
+        pools: &mut Pools,
+        sheet: &Res<ExplosionSheet>,
+        pos: Vec3,
+    ) {
+        Self::spawn_sprite(
+            commands,
+            pools,
+            PoolKind::Spark,
+            sheet,
+            pos,
+            0.015,
+            SPARK_SCALE,
+        );
+    }
+
+    fn spawn_sprite(
+        commands: &mut Commands,
+        pools: &mut Pools,
+        kind: PoolKind,
+        sheet: &Res<ExplosionSheet>,
+        pos: Vec3,
+        frame_duration: f32,
+        scale: f32,
     ) {
         let mut sprite = TextureAtlasSprite::new(0);
 
         let angle = rand::thread_rng().gen_range(0.0..PI);
 
-        sprite.custom_size = Some(Vec2::new(SIZE_X, SIZE_Y));
+        sprite.custom_size = Some(Vec2::new(SIZE_X, SIZE_Y) * scale);
+        let entity = pools.acquire(commands, kind);
         commands
-            .spawn_bundle(SpriteSheetBundle {
+            .entity(entity)
+            .insert_bundle(SpriteSheetBundle {
                 sprite,
                 texture_atlas: sheet.0.clone(),
                 transform: Transform {
@@ -45,20 +108,13 @@ impl Explosion {
                 },
                 ..default()
             })
+            .insert(Pooled::new(kind))
             .insert(Explosion)
             .insert(AnimationTimer::finite(
                 frame_duration,
                 SHEET_COLUMNS * SHEET_ROWS,
             ))
             .insert(Name::new("Explosion"));
-
-        audio.play_with_settings(
-            explosion_audio.0.clone(),
-            PlaybackSettings {
-                volume: 0.25,
-                ..default()
-            },
-        );
     }
 }
 
@@ -72,6 +128,7 @@ const SIZE_X: f32 = 0.5;
 const SIZE_Y: f32 = 0.5;
 const SHEET_COLUMNS: usize = 4;
 const SHEET_ROWS: usize = 4;
+const SPARK_SCALE: f32 = 0.15;
 
 fn load_assets(
     mut commands: Commands,
@@ -98,7 +155,7 @@ fn load_assets(
 fn process(mut commands: Commands, q: Query<(Entity, &AnimationTimer), With<Explosion>>) {
     for (explosion, timer) in &q {
         if timer.timer.paused() {
-            commands.entity(explosion).despawn_recursive();
+            commands.entity(explosion).release();
         }
     }
 }
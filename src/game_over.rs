@@ -2,12 +2,17 @@
 // 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
 
 use crate::anim::MainFont;
+use crate::boss::Boss;
 use crate::bullet::Bullet;
 use crate::collision::Screen;
+use crate::difficulty::Difficulty;
 use crate::enemy::Enemy;
 use crate::explosion::Explosion;
+use crate::graze::Grazes;
 use crate::player::Player;
-use crate::score::{Score, ScoreText};
+use crate::pool::ReleaseExt;
+use crate::rank::RankEvent;
+use crate::score::Score;
 use crate::{AnimPlugin, GameState};
 use bevy::prelude::*;
 
@@ -19,6 +24,9 @@ impl Plugin for GameOverPlugin {
             .add_system_set(
                 SystemSet::on_enter(GameState::GameOver).with_system(show_game_over_text),
             )
+            .add_system_set(
+                SystemSet::on_exit(GameState::GameOver).with_system(hide_game_over_text),
+            )
             .add_system_set(
                 SystemSet::on_update(GameState::GameOver)
                     .with_system(wait_for_continue)
@@ -40,6 +48,30 @@ impl Plugin for GameOverPlugin {
     }
 }
 
+// Whatever can be left on the field when a run is over.
+pub type FieldQueries<'w, 's> = (
+    Query<'w, 's, Entity, With<Player>>,
+    Query<'w, 's, Entity, Or<(With<Enemy>, With<Boss>)>>,
+    Query<'w, 's, Entity, With<Bullet>>,
+    Query<'w, 's, Entity, With<Explosion>>,
+);
+
+pub fn clear_field(commands: &mut Commands, field: &FieldQueries) {
+    let (players, enemies, bullets, explosions) = field;
+    for e in players {
+        commands.entity(e).despawn_recursive();
+    }
+    for e in enemies {
+        commands.entity(e).despawn_recursive();
+    }
+    for e in bullets {
+        commands.entity(e).release();
+    }
+    for e in explosions {
+        commands.entity(e).release();
+    }
+}
+
 #[derive(Component)]
 pub struct NewGameTimer {
     timer: Timer,
@@ -62,19 +94,23 @@ const GG_TEXT_Z: f32 = 1.0;
 pub struct GameOverText;
 
 impl GameOverText {
-    fn gg_text(can_continue: bool) -> String {
+    fn gg_text(can_continue: bool, cost: u32, grazes: u32) -> String {
         if can_continue {
             return format!(
                 "-= GAME OVER =-\n\n\
+                GRAZE: {}\n\n\
                 PRESS \"ENTER\" TO SPEND {} POINTS AND CONTINUE\n\n\
                 PRESS \"Q\" TO EXIT",
-                Score::CONTINUE_COST
+                grazes, cost
             );
         }
-        return "-= GAME OVER =-\n\n\
+        return format!(
+            "-= GAME OVER =-\n\n\
+            GRAZE: {}\n\n\
             PRESS \"ENTER\" FOR QUICK RESTART\n\n\
-            PRESS \"Q\" TO EXIT"
-            .to_string();
+            PRESS \"Q\" TO EXIT",
+            grazes
+        );
     }
 }
 
@@ -86,7 +122,7 @@ fn setup(mut commands: Commands, font: Res<MainFont>, win: Res<WindowDescriptor>
 
     let mut gg_text = AnimPlugin::text_bundle(
         &font.0,
-        &GameOverText::gg_text(true),
+        &GameOverText::gg_text(true, 0, 0),
         GG_TEXT_SIZE,
         win.middle_with_z(GG_TEXT_Z),
     );
@@ -100,20 +136,23 @@ fn setup(mut commands: Commands, font: Res<MainFont>, win: Res<WindowDescriptor>
 fn show_game_over_text(
     mut q: Query<(&mut Visibility, &mut Text), With<GameOverText>>,
     score: Res<Score>,
+    grazes: Res<Grazes>,
+    difficulty: Res<Difficulty>,
 ) {
     let (mut visibility, mut text) = q.single_mut();
-    text.sections.first_mut().unwrap().value = GameOverText::gg_text(score.can_continue());
+    let cost = difficulty.preset().continue_cost;
+    text.sections.first_mut().unwrap().value =
+        GameOverText::gg_text(score.can_continue(cost), cost, grazes.0);
     visibility.is_visible = true;
 }
 
-fn wait_for_continue(
-    mut kbd: ResMut<Input<KeyCode>>,
-    mut state: ResMut<State<GameState>>,
-    mut q: Query<&mut Visibility, With<GameOverText>>,
-) {
+fn hide_game_over_text(mut q: Query<&mut Visibility, With<GameOverText>>) {
+    q.single_mut().is_visible = false;
+}
+
+fn wait_for_continue(mut kbd: ResMut<Input<KeyCode>>, mut state: ResMut<State<GameState>>) {
     if kbd.just_pressed(KeyCode::Return) {
         state.set(GameState::GameOverWaitingForEmptyField).unwrap();
-        q.single_mut().is_visible = false;
         kbd.clear();
     }
 }
@@ -122,30 +161,11 @@ fn wait_for_exit(
     mut commands: Commands,
     mut kbd: ResMut<Input<KeyCode>>,
     mut state: ResMut<State<GameState>>,
-    mut q: Query<&mut Visibility, With<GameOverText>>,
-    mut score_q: Query<&mut Visibility, (With<ScoreText>, Without<GameOverText>)>,
-    players: Query<Entity, With<Player>>,
-    enemies: Query<Entity, With<Enemy>>,
-    bullets: Query<Entity, With<Bullet>>,
-    explosions: Query<Entity, With<Explosion>>,
+    field: FieldQueries,
 ) {
     if kbd.just_pressed(KeyCode::Q) {
-        for e in &players {
-            commands.entity(e).despawn_recursive();
-        }
-        for e in &enemies {
-            commands.entity(e).despawn_recursive();
-        }
-        for e in &bullets {
-            commands.entity(e).despawn_recursive();
-        }
-        for e in &explosions {
-            commands.entity(e).despawn_recursive();
-        }
-
+        clear_field(&mut commands, &field);
         state.set(GameState::TitleFlyIn).unwrap();
-        q.single_mut().is_visible = false;
-        score_q.single_mut().is_visible = false;
         kbd.clear();
     }
 }
@@ -167,11 +187,14 @@ fn start_new_game_on_timer(
     mut timer_query: Query<&mut NewGameTimer>,
     time: Res<Time>,
     mut score: ResMut<Score>,
+    difficulty: Res<Difficulty>,
+    mut rank_events: EventWriter<RankEvent>,
 ) {
     let mut timer = timer_query.single_mut();
     timer.timer.tick(time.delta());
     if timer.timer.just_finished() {
-        score.buy_continue();
+        score.buy_continue(difficulty.preset().continue_cost);
+        rank_events.send(RankEvent::Continue);
         state.set(GameState::PlayerSlideOut).unwrap();
     }
 }
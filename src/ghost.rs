@@ -0,0 +1,233 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+// A see-through copy of a recorded run to race against:
+// the best run with the same settings, or the one given with "--ghost FILE".
+// The run is started with the same seed, and the ghost ships just follow the recorded positions,
+// so they cannot change anything on the field.
+
+use crate::bullet::BulletCancel;
+use crate::difficulty::Difficulty;
+use crate::netplay::Netplay;
+use crate::player::{self, PlayerGraphics, MAX_PLAYERS};
+use crate::replay::{Playback, Replay};
+use crate::sim::{self, NextSeed, Settings, SimClock};
+use crate::stage::GameMode;
+use crate::title;
+use crate::GameState;
+use bevy::prelude::*;
+use bevy::transform::TransformSystem;
+use std::path::Path;
+
+pub struct GhostPlugin;
+
+impl Plugin for GhostPlugin {
+    fn build(&self, app: &mut App) {
+        let mut run = GhostRun::default();
+        let mut args = std::env::args().skip_while(|arg| arg != "--ghost").skip(1);
+        if let Some(path) = args.next() {
+            match Replay::load_file(Path::new(&path)) {
+                Ok(replay) => run.file = Some((replay.settings, Track::new(&replay))),
+                Err(e) => error!("ghost: cannot load {}: {}", path, e),
+            }
+        }
+        let ghost = if run.file.is_some() {
+            Ghost::On
+        } else {
+            Ghost::Off
+        };
+
+        app.insert_resource(ghost)
+            .insert_resource(run)
+            .add_startup_system(setup)
+            .add_system_set(
+                SystemSet::on_update(GameState::Title).with_system(
+                    lock_settings
+                        .after(title::change_settings)
+                        .before(title::update_settings_text),
+                ),
+            )
+            .add_system_set(
+                SystemSet::on_enter(GameState::TitleFlyOut).with_system(start.before(sim::reseed)),
+            )
+            .add_system_set(SystemSet::on_enter(GameState::GameOver).with_system(finish))
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                follow.before(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ghost {
+    Off,
+    On,
+}
+
+impl Ghost {
+    pub fn toggle(&mut self) {
+        *self = match self {
+            Ghost::Off => Ghost::On,
+            Ghost::On => Ghost::Off,
+        };
+    }
+
+    pub fn title(&self) -> &'static str {
+        return match self {
+            Ghost::Off => "OFF",
+            Ghost::On => "ON",
+        };
+    }
+}
+
+#[derive(Component)]
+struct GhostShip(usize);
+
+#[derive(Clone)]
+struct Track {
+    seed: u64,
+    // Seconds since the start of the run, and where the ships were by then.
+    points: Vec<(f64, [Option<Vec2>; MAX_PLAYERS])>,
+}
+
+impl Track {
+    fn new(replay: &Replay) -> Self {
+        let points = replay
+            .tick_times()
+            .into_iter()
+            .zip(&replay.ticks)
+            .map(|(time, tick)| (time, tick.ships.map(|ship| ship.map(Vec2::from))))
+            .collect();
+        return Self {
+            seed: replay.seed,
+            points,
+        };
+    }
+
+    // The ticks of two runs do not have to last the same, so the positions are interpolated.
+    fn ships_at(&self, time: f64) -> [Option<Vec2>; MAX_PLAYERS] {
+        let next = self.points.partition_point(|(t, _)| *t <= time);
+        if next == 0 || next == self.points.len() {
+            return [None; MAX_PLAYERS];
+        }
+        let (prev_time, prev) = self.points[next - 1];
+        let (next_time, next) = self.points[next];
+        let k = ((time - prev_time) / (next_time - prev_time)) as f32;
+        let mut ships = prev;
+        for (ship, next) in ships.iter_mut().zip(next) {
+            if let (Some(ship), Some(next)) = (ship, next) {
+                *ship = ship.lerp(next, k);
+            }
+        }
+        return ships;
+    }
+}
+
+#[derive(Default)]
+pub struct GhostRun {
+    // Given on the command line, along with the settings it was recorded with.
+    file: Option<(Settings, Track)>,
+    // Of the current run.
+    track: Option<Track>,
+    elapsed: f64,
+}
+
+fn setup(mut commands: Commands, player_graphics: Res<PlayerGraphics>) {
+    for index in 0..MAX_PLAYERS {
+        commands
+            .spawn_bundle(player::ghost_bundle(&player_graphics, index))
+            .insert(GhostShip(index))
+            .insert(Name::new(format!("Ghost{}", index + 1)));
+    }
+}
+
+// The ghost from a file only makes sense with the settings it was recorded with.
+fn lock_settings(
+    run: Res<GhostRun>,
+    ghost: Res<Ghost>,
+    (mut mode, mut difficulty, mut cancel): (
+        ResMut<GameMode>,
+        ResMut<Difficulty>,
+        ResMut<BulletCancel>,
+    ),
+) {
+    let settings = match (*ghost, &run.file) {
+        (Ghost::On, Some((settings, _))) => settings,
+        _ => return,
+    };
+    if *mode != settings.mode {
+        *mode = settings.mode;
+    }
+    if *difficulty != settings.difficulty {
+        *difficulty = settings.difficulty;
+    }
+    if *cancel != settings.cancel {
+        *cancel = settings.cancel;
+    }
+}
+
+fn start(
+    mut run: ResMut<GhostRun>,
+    ghost: Res<Ghost>,
+    netplay: Option<Res<Netplay>>,
+    playback: Option<Res<Playback>>,
+    mut next_seed: ResMut<NextSeed>,
+    (mode, difficulty, cancel): (Res<GameMode>, Res<Difficulty>, Res<BulletCancel>),
+) {
+    run.elapsed = 0.0;
+    run.track = None;
+    // The host or the replay picks the seed then.
+    if *ghost == Ghost::Off || netplay.is_some() || playback.is_some() {
+        return;
+    }
+
+    run.track = match &run.file {
+        Some((_, track)) => Some(track.clone()),
+        None => {
+            let settings = Settings {
+                mode: *mode,
+                difficulty: *difficulty,
+                cancel: *cancel,
+            };
+            Replay::load_best(&settings).map(|replay| Track::new(&replay))
+        }
+    };
+    if let Some(track) = &run.track {
+        next_seed.0 = Some(track.seed);
+    }
+}
+
+fn finish(mut run: ResMut<GhostRun>, mut next_seed: ResMut<NextSeed>) {
+    if run.track.take().is_some() {
+        next_seed.0 = None;
+    }
+}
+
+fn follow(
+    mut run: ResMut<GhostRun>,
+    clock: Res<SimClock>,
+    time: Res<Time>,
+    mut q: Query<(&GhostShip, &mut Transform, &mut Visibility)>,
+) {
+    let run = &mut *run;
+    let ships = match &run.track {
+        Some(track) => {
+            if !clock.stalled {
+                run.elapsed += time.delta_seconds_f64();
+            }
+            track.ships_at(run.elapsed)
+        }
+        None => [None; MAX_PLAYERS],
+    };
+
+    for (ship, mut transform, mut visibility) in &mut q {
+        match ships[ship.0] {
+            Some(pos) => {
+                transform.translation.x = pos.x;
+                transform.translation.y = pos.y;
+                visibility.is_visible = true;
+            }
+            None => visibility.is_visible = false,
+        }
+    }
+}
@@ -0,0 +1,242 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+// Enemy bullets that fly close to the player without hitting are worth points.
+// A bullet counts once it is out of the zone again, or gone, and only if it did not hit.
+
+use crate::anim::{AnimPlugin, AssetsLoading, MainFont};
+use crate::bullet::Bullet;
+use crate::collision::{self, Collider, CollisionLayers, PlayerGrazedBullet, PlayerHitByBullet};
+use crate::explosion::{Explosion, ExplosionSheet};
+use crate::grid::Layers;
+use crate::health::{self, Health};
+use crate::hit::SPARK_Z;
+use crate::player::Player;
+use crate::pool::Pools;
+use crate::score::Score;
+use crate::shape::Shape;
+use crate::state::GameState;
+use bevy::prelude::*;
+
+pub struct GrazePlugin;
+
+impl Plugin for GrazePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system_to_stage(StartupStage::PreStartup, load_assets)
+            .add_startup_system(setup)
+            .insert_resource(GrazeConfig::default())
+            .insert_resource(Grazes::default())
+            .insert_resource(Grazing::default())
+            .add_system_set(SystemSet::on_enter(GameState::TitleFlyOut).with_system(reset))
+            .add_system_set(SystemSet::on_enter(GameState::Game).with_system(show_text))
+            .add_system_set(SystemSet::on_exit(GameState::Game).with_system(hide_text))
+            .add_system(attach_zone)
+            .add_system(graze.after(collision::detect).after(health::take_hits))
+            .add_system(update_text.after(graze));
+    }
+}
+
+pub struct GrazeConfig {
+    // Measured from the center of the player's ship.
+    pub radius: f32,
+    pub points: u32,
+    // How much of the weapon heat each graze takes away. Zero disables it.
+    pub heat_relief: f32,
+}
+
+impl Default for GrazeConfig {
+    fn default() -> Self {
+        return Self {
+            radius: 0.09,
+            points: 1,
+            heat_relief: 0.1,
+        };
+    }
+}
+
+// Grazes in the current run.
+#[derive(Default, Clone)]
+pub struct Grazes(pub u32);
+
+// The bullets that are in a zone now, in the order they got there.
+#[derive(Default, Clone)]
+pub struct Grazing(Vec<Near>);
+
+#[derive(Clone)]
+struct Near {
+    bullet: Entity,
+    zone: Entity,
+    // Where the bullet was last seen in the zone.
+    position: Vec3,
+}
+
+#[derive(Component, Clone)]
+pub struct GrazeZone;
+
+#[derive(Component)]
+pub struct GrazeText;
+
+pub struct GrazeAudio(Handle<AudioSource>);
+
+fn load_assets(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    mut loading: ResMut<AssetsLoading>,
+) {
+    let audio = assets.load("enemy_bullet.ogg");
+    loading.push(audio.clone_untyped());
+    commands.insert_resource(GrazeAudio(audio));
+}
+
+fn setup(mut commands: Commands, font: Res<MainFont>) {
+    let mut text = AnimPlugin::text_bundle(&font.0, "GRAZE: 0", 25.0, Vec3::new(0.01, 0.97, 0.1));
+    text.text.alignment.horizontal = HorizontalAlign::Left;
+    text.text.alignment.vertical = VerticalAlign::Top;
+    commands
+        .spawn_bundle(text)
+        .insert(GrazeText)
+        .insert(Name::new("Graze"));
+}
+
+fn reset(mut grazes: ResMut<Grazes>, mut grazing: ResMut<Grazing>) {
+    grazes.0 = 0;
+    grazing.0.clear();
+}
+
+fn show_text(mut q: Query<&mut Visibility, With<GrazeText>>) {
+    q.single_mut().is_visible = true;
+}
+
+fn hide_text(mut q: Query<&mut Visibility, With<GrazeText>>) {
+    q.single_mut().is_visible = false;
+}
+
+// The zone is a body of its own, so it does not become a part of the player's hitbox.
+fn attach_zone(
+    mut commands: Commands,
+    q: Query<(Entity, Option<&Children>), Added<Player>>,
+    zones_q: Query<(), With<GrazeZone>>,
+    config: Res<GrazeConfig>,
+) {
+    for (player, children) in &q {
+        // A ship put back by a rollback has its zone already.
+        if children.is_some_and(|children| children.iter().any(|c| zones_q.contains(*c))) {
+            continue;
+        }
+        let zone = commands
+            .spawn_bundle(TransformBundle::default())
+            .insert(Collider(Shape::Circle {
+                radius: config.radius,
+            }))
+            .insert(CollisionLayers::new(Layers::GRAZE, Layers::ENEMY_BULLET))
+            .insert(GrazeZone)
+            .insert(Name::new("GrazeZone"))
+            .id();
+        commands.entity(player).add_child(zone);
+    }
+}
+
+fn graze(
+    mut commands: Commands,
+    (mut events, mut hits): (
+        EventReader<PlayerGrazedBullet>,
+        EventReader<PlayerHitByBullet>,
+    ),
+    zones_q: Query<&Parent, With<GrazeZone>>,
+    mut players_q: Query<(&mut Player, &Health)>,
+    mut bullets_q: Query<(&mut Bullet, &GlobalTransform)>,
+    (config, mut grazing, mut grazes, mut score): (
+        Res<GrazeConfig>,
+        ResMut<Grazing>,
+        ResMut<Grazes>,
+        ResMut<Score>,
+    ),
+    (mut pools, sheet, graze_audio, audio): (
+        ResMut<Pools>,
+        Res<ExplosionSheet>,
+        Res<GrazeAudio>,
+        Res<Audio>,
+    ),
+) {
+    let hit: Vec<Entity> = hits.iter().map(|hit| hit.bullet).collect();
+    let inside: Vec<&PlayerGrazedBullet> = events.iter().collect();
+
+    let mut passed = Vec::new();
+    grazing.0.retain_mut(|near| {
+        if hit.contains(&near.bullet) {
+            return false;
+        }
+        match bullets_q.get(near.bullet) {
+            // A bullet that has not been grazed is a new one in the same pooled entity.
+            Ok((bullet, transform)) if bullet.grazed() => {
+                let still = inside
+                    .iter()
+                    .any(|event| event.bullet == near.bullet && event.zone == near.zone);
+                if still {
+                    near.position = transform.translation();
+                    return true;
+                }
+            }
+            _ => {}
+        }
+        passed.push((near.zone, near.position));
+        false
+    });
+
+    for event in inside {
+        if hit.contains(&event.bullet) {
+            continue;
+        }
+        let (mut bullet, transform) = match bullets_q.get_mut(event.bullet) {
+            Ok(bullet) => bullet,
+            Err(_) => continue,
+        };
+        if !bullet.graze() {
+            continue;
+        }
+        grazing.0.push(Near {
+            bullet: event.bullet,
+            zone: event.zone,
+            position: transform.translation(),
+        });
+    }
+
+    for (zone, position) in passed {
+        let (mut player, health) = match zones_q
+            .get(zone)
+            .and_then(|parent| players_q.get_mut(parent.get()))
+        {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        if health.is_dead() {
+            continue;
+        }
+
+        grazes.0 += 1;
+        score.add_for(player.index(), config.points);
+        player.relieve_heat(config.heat_relief);
+        Explosion::spawn_spark(
+            &mut commands,
+            &mut pools,
+            &sheet,
+            position.truncate().extend(SPARK_Z),
+        );
+        audio.play_with_settings(
+            graze_audio.0.clone(),
+            PlaybackSettings {
+                volume: 0.1,
+                speed: 3.0,
+                ..default()
+            },
+        );
+    }
+}
+
+fn update_text(mut q: Query<&mut Text, With<GrazeText>>, grazes: Res<Grazes>) {
+    if grazes.is_changed() {
+        for mut text in &mut q {
+            text.sections.first_mut().unwrap().value = format!("GRAZE: {}", grazes.0);
+        }
+    }
+}
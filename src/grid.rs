@@ -0,0 +1,153 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+// A uniform grid for finding overlapping shapes.
+// It does not depend on the ECS, so it can be benchmarked on its own.
+
+use crate::shape::Placed;
+use bevy::math::Vec2;
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::ops::{BitAnd, BitOr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Layers(u8);
+
+impl Layers {
+    pub const PLAYER: Self = Self(1 << 0);
+    pub const ENEMY: Self = Self(1 << 1);
+    pub const PLAYER_BULLET: Self = Self(1 << 2);
+    pub const ENEMY_BULLET: Self = Self(1 << 3);
+    pub const PICKUP: Self = Self(1 << 4);
+    pub const GRAZE: Self = Self(1 << 5);
+
+    pub fn intersects(&self, other: Layers) -> bool {
+        return self.0 & other.0 != 0;
+    }
+}
+
+impl BitOr for Layers {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        return Self(self.0 | rhs.0);
+    }
+}
+
+impl BitAnd for Layers {
+    type Output = Self;
+
+    fn bitand(self, rhs: Self) -> Self {
+        return Self(self.0 & rhs.0);
+    }
+}
+
+// Cell coordinates are small integers, so a multiply is enough to spread them
+// and is much cheaper than the default hasher.
+#[derive(Default)]
+struct CellHasher(u64);
+
+impl Hasher for CellHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write_u64(*byte as u64);
+        }
+    }
+
+    fn write_i32(&mut self, n: i32) {
+        self.write_u64(n as u32 as u64);
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = (self.0.rotate_left(5) ^ n).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+
+    fn finish(&self) -> u64 {
+        return self.0;
+    }
+}
+
+type Cells = HashMap<(i32, i32), Vec<usize>, BuildHasherDefault<CellHasher>>;
+
+struct Item<T> {
+    id: T,
+    shape: Placed,
+    min: Vec2,
+    max: Vec2,
+    layers: Layers,
+}
+
+pub struct Grid<T> {
+    cell_size: f32,
+    cells: Cells,
+    items: Vec<Item<T>>,
+}
+
+impl<T: Copy + PartialEq> Grid<T> {
+    pub fn new(cell_size: f32) -> Self {
+        return Self {
+            cell_size,
+            cells: Cells::default(),
+            items: Vec::new(),
+        };
+    }
+
+    // Keeps the allocations for the next rebuild.
+    pub fn clear(&mut self) {
+        for cell in self.cells.values_mut() {
+            cell.clear();
+        }
+        self.items.clear();
+    }
+
+    // An id may be inserted several times, once for every shape it consists of.
+    pub fn insert(&mut self, id: T, shape: Placed, layers: Layers) {
+        let index = self.items.len();
+        let (min, max) = shape.bounds();
+        self.items.push(Item {
+            id,
+            shape,
+            min,
+            max,
+            layers,
+        });
+
+        let (from, to) = (self.cell(min), self.cell(max));
+        for x in from.0..=to.0 {
+            for y in from.1..=to.1 {
+                self.cells.entry((x, y)).or_default().push(index);
+            }
+        }
+    }
+
+    // Everything on the given layers that overlaps the shape, each id once.
+    pub fn query(&self, shape: &Placed, mask: Layers, found: &mut Vec<T>) {
+        let (min, max) = shape.bounds();
+        let (from, to) = (self.cell(min), self.cell(max));
+
+        for x in from.0..=to.0 {
+            for y in from.1..=to.1 {
+                let cell = match self.cells.get(&(x, y)) {
+                    Some(cell) => cell,
+                    None => continue,
+                };
+                for item in cell.iter().map(|index| &self.items[*index]) {
+                    // Bounds that share several cells are only checked
+                    // in the one with the corner of their overlap.
+                    let candidate = item.layers.intersects(mask)
+                        && item.min.cmplt(max).all()
+                        && min.cmplt(item.max).all()
+                        && self.cell(item.min.max(min)) == (x, y);
+                    if candidate && !found.contains(&item.id) && item.shape.overlaps(shape) {
+                        found.push(item.id);
+                    }
+                }
+            }
+        }
+    }
+
+    fn cell(&self, pos: Vec2) -> (i32, i32) {
+        let cell = (pos / self.cell_size).floor();
+        return (cell.x as i32, cell.y as i32);
+    }
+}
@@ -0,0 +1,298 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+use crate::bullet::Bullet;
+use crate::collision::{self, EnemyHitByBullet, PlayerHitByBullet, PlayerRammedEnemy};
+use crate::explosion::{Explosion, ExplosionAudio, ExplosionSheet};
+use crate::player::{Player, PlayerBullet};
+use crate::pool::{Pools, ReleaseExt};
+use bevy::prelude::*;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+pub struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Damaged>()
+            .add_event::<Died>()
+            .add_system(take_hits.after(collision::detect))
+            .add_system(update)
+            .add_system(die.after(take_hits).after(update));
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DamageKind {
+    Kinetic,
+    Explosive,
+    Ram,
+    Burn,
+}
+
+#[derive(Component, Clone)]
+pub struct Health {
+    pub max: f32,
+    pub current: f32,
+    // Seconds of invulnerability after a hit that was survived.
+    invulnerability: f32,
+    invulnerable_for: f32,
+    burning: Option<Burning>,
+    // Set when the health runs out, until the death is handled.
+    killer: Option<Blame>,
+}
+
+// Who dealt the damage. It is worked out when the hit lands,
+// because a bullet is released on the hit while its burning goes on.
+#[derive(Clone, Copy)]
+struct Blame {
+    // The player who fired the bullet or rammed.
+    player: Option<usize>,
+}
+
+#[derive(Clone)]
+struct Burning {
+    blame: Blame,
+    per_second: f32,
+    left: f32,
+}
+
+impl Health {
+    pub fn new(max: f32) -> Self {
+        return Self {
+            max,
+            current: max,
+            invulnerability: 0.0,
+            invulnerable_for: 0.0,
+            burning: None,
+            killer: None,
+        };
+    }
+
+    pub fn with_invulnerability(mut self, secs: f32) -> Self {
+        self.invulnerability = secs;
+        return self;
+    }
+
+    pub fn is_dead(&self) -> bool {
+        return self.current <= 0.0;
+    }
+
+    pub fn is_invulnerable(&self) -> bool {
+        return self.invulnerable_for > 0.0;
+    }
+
+    pub fn make_invulnerable(&mut self, secs: f32) {
+        self.invulnerable_for = self.invulnerable_for.max(secs);
+    }
+
+    // Negative damage heals. Returns whether this was the killing blow.
+    fn take(&mut self, amount: f32, blame: Blame) -> bool {
+        if self.is_dead() {
+            return false;
+        }
+        self.current = (self.current - amount).clamp(0.0, self.max);
+        if self.is_dead() {
+            self.killer = Some(blame);
+            return true;
+        }
+        return false;
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct OverTime {
+    pub per_second: f32,
+    pub duration: f32,
+}
+
+// Dealt by projectiles when they hit and by anything that rams into something.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Damage {
+    pub amount: f32,
+    pub kind: DamageKind,
+    // Burning that continues after the hit.
+    pub over_time: Option<OverTime>,
+}
+
+impl Damage {
+    pub const fn new(amount: f32, kind: DamageKind) -> Self {
+        return Self {
+            amount,
+            kind,
+            over_time: None,
+        };
+    }
+}
+
+// Multipliers for the incoming damage of each kind. Missing kinds are not resisted.
+#[derive(Component, Deserialize, Clone, Default, Debug)]
+#[serde(transparent)]
+pub struct Resistances(BTreeMap<DamageKind, f32>);
+
+impl Resistances {
+    pub fn with(mut self, kind: DamageKind, factor: f32) -> Self {
+        self.0.insert(kind, factor);
+        return self;
+    }
+
+    fn factor(&self, kind: DamageKind) -> f32 {
+        return self.0.get(&kind).copied().unwrap_or(1.0);
+    }
+}
+
+// The entity is not despawned when it dies. Whoever owns it decides what happens next.
+#[derive(Component, Clone)]
+pub struct KeepOnDeath;
+
+// Any direct hit that did some damage, including the last one.
+pub struct Damaged {
+    pub entity: Entity,
+    pub source: Entity,
+    pub position: Vec3,
+}
+
+pub struct Died {
+    pub entity: Entity,
+    // The player to credit for the kill, if it was one.
+    pub player: Option<usize>,
+    pub position: Vec3,
+}
+
+pub fn take_hits(
+    mut commands: Commands,
+    (mut enemy_hits, mut player_hits, mut rams): (
+        EventReader<EnemyHitByBullet>,
+        EventReader<PlayerHitByBullet>,
+        EventReader<PlayerRammedEnemy>,
+    ),
+    mut targets_q: Query<(&mut Health, Option<&Resistances>)>,
+    sources_q: Query<(&Damage, &GlobalTransform, Option<&Bullet>)>,
+    shooters_q: Query<&PlayerBullet>,
+    players_q: Query<&Player>,
+    mut damaged: EventWriter<Damaged>,
+) {
+    let hits = enemy_hits
+        .iter()
+        .map(|hit| (hit.enemy, hit.bullet))
+        .chain(player_hits.iter().map(|hit| (hit.player, hit.bullet)))
+        // Both sides of a ram get hurt.
+        .chain(
+            rams.iter()
+                .flat_map(|ram| [(ram.player, ram.enemy), (ram.enemy, ram.player)]),
+        );
+
+    // A bullet is spent on the first target it damages.
+    let mut spent = Vec::new();
+    for (target, source) in hits {
+        if spent.contains(&source) {
+            continue;
+        }
+        let (mut health, resistances) = match targets_q.get_mut(target) {
+            Ok(target) => target,
+            Err(_) => continue,
+        };
+        let (damage, source_transform, bullet) = match sources_q.get(source) {
+            Ok(source) => source,
+            Err(_) => continue,
+        };
+        // Already destroyed by something else this frame.
+        if health.is_dead() || health.is_invulnerable() {
+            continue;
+        }
+
+        let factor = |kind| resistances.map_or(1.0, |resistances| resistances.factor(kind));
+        let amount = damage.amount * factor(damage.kind);
+        // Immune targets let it pass.
+        if amount == 0.0 {
+            continue;
+        }
+        let blame = Blame {
+            player: match (shooters_q.get(source), players_q.get(source)) {
+                (Ok(shooter), _) => Some(shooter.player),
+                (_, Ok(player)) => Some(player.index()),
+                _ => None,
+            },
+        };
+        health.take(amount, blame);
+        damaged.send(Damaged {
+            entity: target,
+            source,
+            position: source_transform.translation(),
+        });
+
+        if !health.is_dead() {
+            let secs = health.invulnerability;
+            health.make_invulnerable(secs);
+            if let Some(over_time) = damage.over_time {
+                health.burning = Some(Burning {
+                    blame,
+                    per_second: over_time.per_second * factor(DamageKind::Burn),
+                    left: over_time.duration,
+                });
+            }
+        }
+        if bullet.is_some() {
+            spent.push(source);
+            commands.entity(source).release();
+        }
+    }
+}
+
+fn update(mut q: Query<&mut Health>, time: Res<Time>) {
+    let delta = time.delta_seconds();
+    for mut health in &mut q {
+        if health.is_invulnerable() {
+            health.invulnerable_for -= delta;
+        }
+
+        let burning = match &mut health.burning {
+            Some(burning) => burning,
+            None => continue,
+        };
+        let secs = delta.min(burning.left);
+        burning.left -= secs;
+        let (blame, amount) = (burning.blame, burning.per_second * secs);
+        if burning.left <= 0.0 {
+            health.burning = None;
+        }
+        health.take(amount, blame);
+    }
+}
+
+pub fn die(
+    mut commands: Commands,
+    mut q: Query<(Entity, &mut Health, &GlobalTransform, Option<&KeepOnDeath>)>,
+    mut died: EventWriter<Died>,
+    explosion_sheet: Res<ExplosionSheet>,
+    explosion_audio: Res<ExplosionAudio>,
+    mut pools: ResMut<Pools>,
+    audio: Res<Audio>,
+) {
+    for (entity, mut health, transform, keep) in &mut q {
+        let killer = match health.killer {
+            Some(killer) => killer,
+            None => continue,
+        };
+        health.killer = None;
+
+        let event = Died {
+            entity,
+            player: killer.player,
+            position: transform.translation(),
+        };
+        Explosion::spawn(
+            &mut commands,
+            &mut pools,
+            &explosion_sheet,
+            event.position,
+            0.05,
+            &explosion_audio,
+            &audio,
+        );
+        if keep.is_none() {
+            commands.entity(entity).despawn_recursive();
+        }
+        died.send(event);
+    }
+}
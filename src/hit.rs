@@ -0,0 +1,102 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+use crate::anim::AssetsLoading;
+use crate::explosion::{Explosion, ExplosionSheet};
+use crate::health::{self, Damaged, Health};
+use crate::pool::Pools;
+use bevy::prelude::*;
+
+pub struct HitPlugin;
+
+impl Plugin for HitPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_startup_system_to_stage(StartupStage::PreStartup, load_assets)
+            .add_system(show_damage.after(health::die))
+            .add_system(flash.after(show_damage));
+    }
+}
+
+const FLASH_DURATION: f32 = 0.12;
+const FLASH_WHITE: Color = Color::rgb(6.0, 6.0, 6.0);
+const FLASH_RED: Color = Color::rgb(1.0, 0.2, 0.2);
+
+pub const SPARK_Z: f32 = 150.0;
+
+#[derive(Component, Clone)]
+pub struct HitFlash {
+    timer: Timer,
+    color: Color,
+}
+
+impl HitFlash {
+    pub fn new(color: Color) -> Self {
+        let mut timer = Timer::from_seconds(FLASH_DURATION, false);
+        timer.tick(timer.duration());
+        return Self { timer, color };
+    }
+
+    fn start(&mut self) {
+        self.timer.reset();
+    }
+}
+
+pub struct HitAudio(Handle<AudioSource>);
+
+fn load_assets(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    mut loading: ResMut<AssetsLoading>,
+) {
+    let audio = assets.load("hit.ogg");
+    loading.push(audio.clone_untyped());
+    commands.insert_resource(HitAudio(audio));
+}
+
+fn show_damage(
+    mut commands: Commands,
+    mut events: EventReader<Damaged>,
+    mut target_q: Query<(&Health, &mut HitFlash)>,
+    sheet: Res<ExplosionSheet>,
+    mut pools: ResMut<Pools>,
+    hit_audio: Res<HitAudio>,
+    audio: Res<Audio>,
+) {
+    for damaged in events.iter() {
+        // The last hit shows up as an explosion instead.
+        match target_q.get_mut(damaged.entity) {
+            Ok((health, mut flash)) if !health.is_dead() => flash.start(),
+            _ => continue,
+        }
+        Explosion::spawn_spark(
+            &mut commands,
+            &mut pools,
+            &sheet,
+            damaged.position.truncate().extend(SPARK_Z),
+        );
+        audio.play_with_settings(
+            hit_audio.0.clone(),
+            PlaybackSettings {
+                volume: 0.15,
+                ..default()
+            },
+        );
+    }
+}
+
+fn flash(mut q: Query<(&mut HitFlash, &mut TextureAtlasSprite)>, time: Res<Time>) {
+    for (mut flash, mut sprite) in &mut q {
+        if flash.timer.finished() {
+            continue;
+        }
+
+        flash.timer.tick(time.delta());
+        sprite.color = if flash.timer.finished() {
+            flash.color
+        } else if flash.timer.percent() < 0.5 {
+            FLASH_WHITE
+        } else {
+            FLASH_RED
+        };
+    }
+}
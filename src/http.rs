@@ -0,0 +1,153 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+// Just enough HTTP/1.1 for the leaderboard, on both ends:
+// plain HTTP only, one request per connection, and every body has a Content-Length.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+// A replay of a very long run is a few megabytes.
+const MAX_BODY: usize = 64 << 20;
+
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub body: String,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        return (200..300).contains(&self.status);
+    }
+
+    // The server will not take the request as it is, so there is no point in sending it again.
+    pub fn is_rejection(&self) -> bool {
+        return (400..500).contains(&self.status);
+    }
+}
+
+// E.g. "http://127.0.0.1:8080/" is "127.0.0.1:8080".
+pub fn host(url: &str) -> Result<String, String> {
+    let host = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("only http:// addresses are supported: {}", url))?
+        .trim_end_matches('/');
+    if host.is_empty() || host.contains('/') {
+        return Err(format!("bad address (need http://HOST:PORT): {}", url));
+    }
+    if host.contains(':') {
+        return Ok(host.to_string());
+    }
+    return Ok(format!("{}:80", host));
+}
+
+// Blocks until the whole response is there, or for a few seconds at most while nothing comes.
+pub fn send(host: &str, method: &str, path: &str, body: &str) -> io::Result<Response> {
+    let addr = host
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| invalid(format!("cannot resolve {}", host)))?;
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+
+    let head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/ron\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        method,
+        path,
+        host,
+        body.len()
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body.as_bytes())?;
+
+    let (line, body) = read_message(&mut BufReader::new(stream))?;
+    // E.g. "HTTP/1.1 200 OK".
+    let status = line
+        .split(' ')
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| invalid(format!("bad status line: {}", line)))?;
+    return Ok(Response { status, body });
+}
+
+pub fn read_request(stream: &TcpStream) -> io::Result<Request> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let (line, body) = read_message(&mut BufReader::new(stream))?;
+    // E.g. "GET /scores HTTP/1.1".
+    let mut parts = line.split(' ');
+    return match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => Ok(Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            body,
+        }),
+        _ => Err(invalid(format!("bad request line: {}", line))),
+    };
+}
+
+pub fn respond(mut stream: &TcpStream, status: u16, body: &str) -> io::Result<()> {
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let reason = match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        404 => "Not Found",
+        _ => "",
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/ron\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason,
+        body.len()
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body.as_bytes())?;
+    return Ok(());
+}
+
+// The first line and the body. The other headers do not matter here.
+fn read_message(reader: &mut impl BufRead) -> io::Result<(String, String)> {
+    let mut first = String::new();
+    reader.read_line(&mut first)?;
+
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid(format!("bad content length: {}", value)))?;
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(invalid(format!("the body is too large: {} bytes", length)));
+    }
+
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    let body = String::from_utf8(body).map_err(|_| invalid("the body is not UTF-8".to_string()))?;
+    return Ok((first.trim_end().to_string(), body));
+}
+
+fn invalid(message: String) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, message);
+}
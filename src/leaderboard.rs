@@ -0,0 +1,405 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+// An online leaderboard, for those who start the game with "--leaderboard http://HOST:PORT".
+// The name on it is the user name, unless given with "--leaderboard-name NAME".
+// Every run that is recorded to the end is submitted along with its replay,
+// so that the server can play it to check the score (see verify.rs),
+// and the title shows the top runs for the picked settings.
+//
+// The server is only talked to from a thread of its own, so the game never waits for it.
+// A run is saved before it is submitted, and only removed once the server got it,
+// so the runs that cannot be submitted now are submitted later, even after a restart.
+// To try it out without a real server, see leaderboard_server.rs.
+//
+// The bodies are in RON:
+// "POST /scores" takes a Submission and answers 201 with a Submitted,
+// or 400 if the submission is not accepted;
+// "GET /scores/KEY" answers with the top Entries for the settings (see Settings::key).
+
+use crate::anim::{AnimPlugin, MainFont};
+use crate::bullet::BulletCancel;
+use crate::collision::Screen;
+use crate::difficulty::Difficulty;
+use crate::http;
+use crate::replay::{Replay, RunRecorded};
+use crate::save;
+use crate::sim::Settings;
+use crate::stage::GameMode;
+use crate::title;
+use crate::GameState;
+use bevy::prelude::*;
+use bevy::utils::HashMap;
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub struct LeaderboardPlugin;
+
+impl Plugin for LeaderboardPlugin {
+    fn build(&self, app: &mut App) {
+        let config = match LeaderboardConfig::from_args(std::env::args().skip(1)) {
+            Ok(Some(config)) => config,
+            Ok(None) => return,
+            Err(e) => {
+                error!("leaderboard: {}", e);
+                return;
+            }
+        };
+
+        app.insert_resource(Leaderboard::start(config))
+            .add_startup_system(setup)
+            .add_system_set(SystemSet::on_enter(GameState::Title).with_system(show_top))
+            .add_system_set(
+                SystemSet::on_update(GameState::Title)
+                    .with_system(request_top.after(title::change_settings))
+                    .with_system(update_top_text.after(request_top)),
+            )
+            .add_system_set(SystemSet::on_exit(GameState::Title).with_system(hide_top))
+            .add_system_to_stage(CoreStage::Last, submit)
+            .add_system_to_stage(CoreStage::Last, receive);
+    }
+}
+
+pub const TOP_SIZE: usize = 10;
+pub const MAX_NAME: usize = 12;
+// The runs waiting to be submitted.
+const QUEUE_DIR: &str = "leaderboard";
+const RETRY_MIN: Duration = Duration::from_secs(5);
+const RETRY_MAX: Duration = Duration::from_secs(300);
+
+#[derive(Serialize, Deserialize)]
+pub struct Submission {
+    pub name: String,
+    pub score: u32,
+    pub seed: u64,
+    pub settings: Settings,
+    pub replay: Replay,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Submitted {
+    // Starting from 1.
+    pub rank: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Entry {
+    pub name: String,
+    pub score: u32,
+}
+
+struct LeaderboardConfig {
+    // E.g. "127.0.0.1:8080".
+    host: String,
+    name: String,
+}
+
+impl LeaderboardConfig {
+    fn from_args(mut args: impl Iterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut host = None;
+        let mut name = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--leaderboard" => host = Some(http::host(&value()?)?),
+                "--leaderboard-name" => name = Some(value()?),
+                _ => {}
+            }
+        }
+
+        let name = name
+            .or_else(|| std::env::var("USER").ok())
+            .or_else(|| std::env::var("USERNAME").ok())
+            .unwrap_or_default();
+        return Ok(host.map(|host| Self {
+            host,
+            name: clean_name(&name),
+        }));
+    }
+}
+
+// The font only has so many letters.
+pub fn clean_name(name: &str) -> String {
+    let name: String = name
+        .to_uppercase()
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(MAX_NAME)
+        .collect();
+    if name.is_empty() {
+        return "PLAYER".to_string();
+    }
+    return name;
+}
+
+enum Job {
+    Submit(Box<Submission>),
+    Fetch(String),
+}
+
+enum Outcome {
+    // None if the server cannot be reached.
+    Top(String, Option<Vec<Entry>>),
+    // How many runs are still waiting to be submitted.
+    Waiting(usize),
+}
+
+pub struct Leaderboard {
+    name: String,
+    jobs: Sender<Job>,
+    outcomes: Mutex<Receiver<Outcome>>,
+    // By the settings key. None if the server could not be reached.
+    tops: HashMap<String, Option<Vec<Entry>>>,
+    // The settings key of the top to show.
+    key: String,
+    // Whether the top for the key was asked for already.
+    requested: bool,
+    waiting: usize,
+}
+
+impl Leaderboard {
+    fn start(config: LeaderboardConfig) -> Self {
+        let (jobs, job_receiver) = mpsc::channel();
+        let (outcome_sender, outcomes) = mpsc::channel();
+        let host = config.host;
+        thread::spawn(move || work(&host, job_receiver, outcome_sender));
+        return Self {
+            name: config.name,
+            jobs,
+            outcomes: Mutex::new(outcomes),
+            tops: HashMap::new(),
+            key: String::new(),
+            requested: false,
+            waiting: 0,
+        };
+    }
+
+    fn text(&self) -> String {
+        let mut text = match self.tops.get(&self.key) {
+            None => "ONLINE TOP\n\nLOADING...".to_string(),
+            Some(None) => "ONLINE TOP\n\nOFFLINE".to_string(),
+            Some(Some(entries)) if entries.is_empty() => "ONLINE TOP\n\nNO RUNS YET".to_string(),
+            Some(Some(entries)) => {
+                let rows: Vec<String> = entries
+                    .iter()
+                    .enumerate()
+                    .map(|(index, entry)| {
+                        let marker = if entry.name == self.name { " <" } else { "" };
+                        format!("{}. {} {}{}", index + 1, entry.name, entry.score, marker)
+                    })
+                    .collect();
+                format!("ONLINE TOP\n\n{}", rows.join("\n"))
+            }
+        };
+        if self.waiting > 0 {
+            text += &format!("\n\nRUNS NOT SENT YET: {}", self.waiting);
+        }
+        return text;
+    }
+}
+
+// Runs on the thread that talks to the server.
+fn work(host: &str, jobs: Receiver<Job>, outcomes: Sender<Outcome>) {
+    let mut retry = RETRY_MIN;
+    // When to try submitting the waiting runs again. The ones left from before are tried first.
+    let mut next_try = Some(Instant::now());
+
+    loop {
+        let job = match next_try {
+            Some(time) => jobs.recv_timeout(time.saturating_duration_since(Instant::now())),
+            None => jobs.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match job {
+            Ok(Job::Submit(submission)) => {
+                enqueue(&submission);
+                retry = RETRY_MIN;
+                next_try = Some(Instant::now());
+            }
+            Ok(Job::Fetch(key)) => {
+                let top = fetch(host, &key);
+                // The server is back, so no need to wait for the next try.
+                if top.is_some() && next_try.is_some() {
+                    next_try = Some(Instant::now());
+                }
+                if outcomes.send(Outcome::Top(key, top)).is_err() {
+                    return;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        if !matches!(next_try, Some(time) if time <= Instant::now()) {
+            continue;
+        }
+        let waiting = flush(host);
+        if waiting == 0 {
+            retry = RETRY_MIN;
+            next_try = None;
+        } else {
+            next_try = Some(Instant::now() + retry);
+            retry = (retry * 2).min(RETRY_MAX);
+        }
+        if outcomes.send(Outcome::Waiting(waiting)).is_err() {
+            return;
+        }
+    }
+}
+
+fn enqueue(submission: &Submission) {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    match ron::to_string(submission) {
+        Ok(data) => {
+            save::store_text(&format!("{}/{}.ron", QUEUE_DIR, time), &data);
+        }
+        Err(e) => warn!("leaderboard: cannot serialize the run: {}", e),
+    }
+}
+
+// Submits the waiting runs, the oldest first, until the server cannot be reached.
+// Returns how many runs are still waiting.
+fn flush(host: &str) -> usize {
+    let names = save::list(QUEUE_DIR);
+    for (index, name) in names.iter().enumerate() {
+        let path = format!("{}/{}", QUEUE_DIR, name);
+        let data = match save::load_text(&path) {
+            Some(data) => data,
+            None => continue,
+        };
+        match http::send(host, "POST", "/scores", &data) {
+            Ok(response) if response.is_success() => {
+                match ron::from_str::<Submitted>(&response.body) {
+                    Ok(submitted) => info!("leaderboard: submitted, rank {}", submitted.rank),
+                    Err(_) => info!("leaderboard: submitted"),
+                }
+            }
+            Ok(response) if response.is_rejection() => {
+                warn!("leaderboard: the run was rejected: {}", response.body);
+            }
+            Ok(response) => {
+                warn!("leaderboard: cannot submit: status {}", response.status);
+                return names.len() - index;
+            }
+            Err(e) => {
+                warn!("leaderboard: cannot submit: {}", e);
+                return names.len() - index;
+            }
+        }
+        save::remove(&path);
+    }
+    return 0;
+}
+
+fn fetch(host: &str, key: &str) -> Option<Vec<Entry>> {
+    let response = match http::send(host, "GET", &format!("/scores/{}", key), "") {
+        Ok(response) if response.is_success() => response,
+        Ok(response) => {
+            warn!(
+                "leaderboard: cannot get the top: status {}",
+                response.status
+            );
+            return None;
+        }
+        Err(e) => {
+            warn!("leaderboard: cannot get the top: {}", e);
+            return None;
+        }
+    };
+    return match ron::from_str(&response.body) {
+        Ok(top) => Some(top),
+        Err(e) => {
+            warn!("leaderboard: cannot parse the top: {}", e);
+            None
+        }
+    };
+}
+
+#[derive(Component)]
+struct TopText;
+
+fn setup(mut commands: Commands, font: Res<MainFont>, win: Res<WindowDescriptor>) {
+    let mut text = AnimPlugin::text_bundle(&font.0, "", 30.0, Vec3::new(0.05, win.middle_y(), 0.1));
+    text.text.alignment.horizontal = HorizontalAlign::Left;
+    commands
+        .spawn_bundle(text)
+        .insert(TopText)
+        .insert(Name::new("LeaderboardTop"));
+}
+
+fn show_top(mut leaderboard: ResMut<Leaderboard>, mut q: Query<&mut Visibility, With<TopText>>) {
+    q.single_mut().is_visible = true;
+    // The top may have changed since it was shown last.
+    leaderboard.requested = false;
+}
+
+fn hide_top(mut q: Query<&mut Visibility, With<TopText>>) {
+    q.single_mut().is_visible = false;
+}
+
+fn request_top(
+    mut leaderboard: ResMut<Leaderboard>,
+    (mode, difficulty, cancel): (Res<GameMode>, Res<Difficulty>, Res<BulletCancel>),
+) {
+    let settings = Settings {
+        mode: *mode,
+        difficulty: *difficulty,
+        cancel: *cancel,
+    };
+    let key = settings.key();
+    if leaderboard.requested && leaderboard.key == key {
+        return;
+    }
+    leaderboard.key = key.clone();
+    leaderboard.requested = true;
+    leaderboard.jobs.send(Job::Fetch(key)).ok();
+}
+
+fn update_top_text(leaderboard: Res<Leaderboard>, mut q: Query<&mut Text, With<TopText>>) {
+    let mut text = q.single_mut();
+    let value = leaderboard.text();
+    if text.sections.first().unwrap().value != value {
+        text.sections.first_mut().unwrap().value = value;
+    }
+}
+
+fn submit(leaderboard: Res<Leaderboard>, mut recorded: EventReader<RunRecorded>) {
+    for RunRecorded(replay) in recorded.iter() {
+        let submission = Submission {
+            name: leaderboard.name.clone(),
+            score: replay.score,
+            seed: replay.seed,
+            settings: replay.settings,
+            replay: replay.clone(),
+        };
+        leaderboard
+            .jobs
+            .send(Job::Submit(Box::new(submission)))
+            .ok();
+    }
+}
+
+fn receive(mut leaderboard: ResMut<Leaderboard>) {
+    let leaderboard = &mut *leaderboard;
+    let outcomes = leaderboard.outcomes.get_mut().unwrap();
+    while let Ok(outcome) = outcomes.try_recv() {
+        match outcome {
+            Outcome::Top(key, top) => {
+                leaderboard.tops.insert(key, top);
+            }
+            Outcome::Waiting(waiting) => {
+                // Some runs got submitted, so the top may be different now.
+                if waiting < leaderboard.waiting {
+                    leaderboard.requested = false;
+                }
+                leaderboard.waiting = waiting;
+            }
+        }
+    }
+}
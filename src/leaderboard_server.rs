@@ -0,0 +1,138 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+// "--leaderboard-server PORT" runs a stand-in for the leaderboard server on 127.0.0.1
+// (see leaderboard.rs for the protocol). Then start the game with
+// "--leaderboard http://127.0.0.1:PORT". The runs are only kept for as long as it runs.
+// A submission has to agree with its replay, but the replay is not played,
+// which a real server would do with "--verify-replay".
+
+use crate::http::{self, Request};
+use crate::leaderboard::{Entry, Submission, Submitted, MAX_NAME, TOP_SIZE};
+use bevy::utils::HashMap;
+use std::net::TcpListener;
+
+// Returns the exit code.
+pub fn run(port: &str) -> i32 {
+    let port: u16 = match port.parse() {
+        Ok(port) => port,
+        Err(_) => {
+            eprintln!("leaderboard: bad port: {}", port);
+            return 2;
+        }
+    };
+    let listener = match TcpListener::bind(("127.0.0.1", port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("leaderboard: cannot listen on port {}: {}", port, e);
+            return 2;
+        }
+    };
+    if let Ok(addr) = listener.local_addr() {
+        println!("leaderboard: listening on http://{}", addr);
+    }
+
+    let mut board = Board::default();
+    // One at a time is plenty here.
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                eprintln!("leaderboard: {}", e);
+                continue;
+            }
+        };
+        let (status, body) = match http::read_request(&stream) {
+            Ok(request) => {
+                let (status, body) = board.handle(&request);
+                println!(
+                    "leaderboard: {} {} {}",
+                    request.method, request.path, status
+                );
+                (status, body)
+            }
+            Err(e) => (400, quoted(&e.to_string())),
+        };
+        if let Err(e) = http::respond(&stream, status, &body) {
+            eprintln!("leaderboard: {}", e);
+        }
+    }
+    return 0;
+}
+
+#[derive(Default)]
+struct Board {
+    // By the settings key, the best first.
+    entries: HashMap<String, Vec<Entry>>,
+}
+
+impl Board {
+    // The status and the body.
+    fn handle(&mut self, request: &Request) -> (u16, String) {
+        if let Some(key) = request.path.strip_prefix("/scores/") {
+            if request.method == "GET" {
+                let top: Vec<&Entry> = self
+                    .entries
+                    .get(key)
+                    .map(|entries| entries.iter().take(TOP_SIZE).collect())
+                    .unwrap_or_default();
+                return (200, ron::to_string(&top).unwrap());
+            }
+        }
+        if request.path == "/scores" && request.method == "POST" {
+            let submission: Submission = match ron::from_str(&request.body) {
+                Ok(submission) => submission,
+                Err(e) => return (400, quoted(&e.to_string())),
+            };
+            if let Err(e) = check(&submission) {
+                return (400, quoted(&e));
+            }
+            let rank = self.add(submission);
+            return (201, ron::to_string(&Submitted { rank }).unwrap());
+        }
+        return (404, quoted("no such thing"));
+    }
+
+    // Returns the rank.
+    fn add(&mut self, submission: Submission) -> usize {
+        let entries = self.entries.entry(submission.settings.key()).or_default();
+        // The earlier run stays ahead of a later one with the same score.
+        let index = entries.partition_point(|entry| entry.score >= submission.score);
+        entries.insert(
+            index,
+            Entry {
+                name: submission.name,
+                score: submission.score,
+            },
+        );
+        return index + 1;
+    }
+}
+
+fn check(submission: &Submission) -> Result<(), String> {
+    let replay = &submission.replay;
+    if submission.name.is_empty() || submission.name.len() > MAX_NAME {
+        return Err(format!("the name must have 1 to {} letters", MAX_NAME));
+    }
+    if replay.version != env!("CARGO_PKG_VERSION") {
+        return Err(format!(
+            "the run is from version {}, need {}",
+            replay.version,
+            env!("CARGO_PKG_VERSION")
+        ));
+    }
+    if replay.score != submission.score
+        || replay.seed != submission.seed
+        || replay.settings != submission.settings
+    {
+        return Err("the run does not match its replay".to_string());
+    }
+    if replay.ticks.is_empty() {
+        return Err("the replay is empty".to_string());
+    }
+    return Ok(());
+}
+
+fn quoted(message: &str) -> String {
+    return ron::to_string(message).unwrap();
+}
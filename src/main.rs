@@ -1,66 +1,150 @@
 // SPDX-License-Identifier: GPL-3.0-only
 // 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
 
+mod aim;
 mod anim;
 mod audio;
 mod background;
+mod boss;
 mod bullet;
+mod checksum;
 mod collision;
+mod curve;
+mod data;
 mod debug;
+mod difficulty;
+mod emitter;
 mod enemy;
 mod explosion;
 mod game_over;
+mod ghost;
+mod graze;
+mod grid;
+mod health;
+mod hit;
+mod http;
+mod leaderboard;
+mod leaderboard_server;
+mod net;
+mod netplay;
+mod pattern;
 mod player;
+mod pool;
+mod rank;
+mod replay;
+mod rollback;
+mod save;
 mod score;
+mod shape;
+mod sim;
+mod stage;
 mod state;
 mod title;
+mod verify;
+mod viewer;
 
 use crate::anim::AnimPlugin;
 use crate::background::BackgroundPlugin;
+use crate::boss::BossPlugin;
 use crate::bullet::BulletPlugin;
+use crate::checksum::ChecksumPlugin;
 use crate::collision::CollisionPlugin;
 use crate::debug::DebugPlugin;
+use crate::difficulty::DifficultyPlugin;
+use crate::emitter::EmitterPlugin;
 use crate::enemy::EnemyPlugin;
 use crate::explosion::ExplosionPlugin;
 use crate::game_over::GameOverPlugin;
+use crate::ghost::GhostPlugin;
+use crate::graze::GrazePlugin;
+use crate::health::HealthPlugin;
+use crate::hit::HitPlugin;
+use crate::leaderboard::LeaderboardPlugin;
+use crate::netplay::NetplayPlugin;
+use crate::pattern::PatternPlugin;
 use crate::player::PlayerPlugin;
+use crate::pool::PoolPlugin;
+use crate::rank::RankPlugin;
+use crate::replay::ReplayPlugin;
+use crate::rollback::RollbackPlugin;
 use crate::score::ScorePlugin;
+use crate::sim::SimPlugin;
+use crate::stage::StagePlugin;
 use crate::state::GameState;
 use bevy::prelude::*;
 use bevy::render::camera::ScalingMode;
 use bevy::window::PresentMode;
+use std::path::Path;
 use title::TitlePlugin;
+use viewer::ViewerPlugin;
 
 const HEIGHT: f32 = 1080.0;
 const WIDTH: f32 = 1920.0;
 const RESOLUTION: f32 = WIDTH / HEIGHT;
 
 fn main() {
-    App::new()
-        .add_plugins(DefaultPlugins)
-        .insert_resource(WindowDescriptor {
-            width: WIDTH,
-            height: HEIGHT,
-            present_mode: PresentMode::Fifo, // VSYNC
-            resizable: false,
-            title: "Fly and Shoot".to_string(),
-            ..default()
-        })
-        .insert_resource(ClearColor(Color::BLACK))
-        .add_state(GameState::Loading)
+    let mut args = std::env::args().skip_while(|arg| arg != "--verify-replay");
+    if let Some(path) = args.nth(1) {
+        std::process::exit(verify::run(Path::new(&path)));
+    }
+    let mut args = std::env::args().skip_while(|arg| arg != "--leaderboard-server");
+    if let Some(port) = args.nth(1) {
+        std::process::exit(leaderboard_server::run(&port));
+    }
+
+    let mut app = App::new();
+    app.add_plugins(DefaultPlugins)
+        .insert_resource(window())
+        .insert_resource(ClearColor(Color::BLACK));
+    add_game(&mut app);
+    app.add_plugin(ViewerPlugin)
+        .add_plugin(LeaderboardPlugin)
+        .add_plugin(DebugPlugin)
+        .run();
+}
+
+// The game logic depends on the window size too.
+fn window() -> WindowDescriptor {
+    return WindowDescriptor {
+        width: WIDTH,
+        height: HEIGHT,
+        present_mode: PresentMode::Fifo, // VSYNC
+        resizable: false,
+        title: "Fly and Shoot".to_string(),
+        ..default()
+    };
+}
+
+// Everything that plays the game. The rest is only for watching it.
+fn add_game(app: &mut App) {
+    app.add_state(GameState::Loading)
         .add_plugin(AnimPlugin)
+        .add_plugin(SimPlugin)
+        .add_plugin(NetplayPlugin)
+        .add_plugin(ChecksumPlugin)
+        .add_plugin(ReplayPlugin)
+        .add_plugin(RollbackPlugin)
+        .add_plugin(GhostPlugin)
+        .add_plugin(DifficultyPlugin)
+        .add_plugin(RankPlugin)
         .add_plugin(EnemyPlugin)
+        .add_plugin(PatternPlugin)
+        .add_plugin(StagePlugin)
+        .add_plugin(BossPlugin)
         .add_plugin(PlayerPlugin)
+        .add_plugin(PoolPlugin)
         .add_plugin(BulletPlugin)
+        .add_plugin(EmitterPlugin)
         .add_plugin(ExplosionPlugin)
+        .add_plugin(HitPlugin)
         .add_plugin(CollisionPlugin)
+        .add_plugin(HealthPlugin)
+        .add_plugin(GrazePlugin)
         .add_plugin(BackgroundPlugin)
         .add_plugin(ScorePlugin)
         .add_plugin(TitlePlugin)
         .add_plugin(GameOverPlugin)
-        .add_plugin(DebugPlugin)
-        .add_startup_system(spawn_camera)
-        .run();
+        .add_startup_system(spawn_camera);
 }
 
 fn spawn_camera(mut commands: Commands) {
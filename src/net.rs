@@ -0,0 +1,414 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+// Input exchange between two simulations that run in step.
+// Only the inputs are sent. When the other side's input for a tick is late,
+// it is predicted to stay the same, and once the real one arrives and turns out to be different,
+// the session reports the first tick that has to be simulated again.
+// Nothing here depends on the game. It is tested with the game on top in netplay.rs.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+pub const PROTOCOL_VERSION: u8 = 2;
+// The other side is considered gone after not hearing from it for this long.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_PACKET: usize = 1024;
+const MAX_INPUTS_PER_PACKET: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NetInput(pub u8);
+
+impl NetInput {
+    pub const UP: u8 = 1 << 0;
+    pub const DOWN: u8 = 1 << 1;
+    pub const LEFT: u8 = 1 << 2;
+    pub const RIGHT: u8 = 1 << 3;
+    pub const FIRE: u8 = 1 << 4;
+    pub const FOCUS: u8 = 1 << 5;
+    pub const MISSILE: u8 = 1 << 6;
+
+    pub fn has(&self, bit: u8) -> bool {
+        return self.0 & bit != 0;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    // Sent by the joining side until it is welcomed.
+    Hello {
+        version: u8,
+    },
+    // Everything both sides need to start the same run.
+    Welcome {
+        seed: u64,
+        settings: Vec<u8>,
+    },
+    // The sender's inputs from the `start` tick on,
+    // and how many of the receiver's inputs the sender has so far.
+    Inputs {
+        received: u32,
+        start: u32,
+        inputs: Vec<NetInput>,
+    },
+    // The sender's state checksums from the `start` tick on.
+    Checksums {
+        start: u32,
+        checksums: Vec<u64>,
+    },
+    Bye,
+}
+
+impl Message {
+    const HELLO: u8 = 1;
+    const WELCOME: u8 = 2;
+    const INPUTS: u8 = 3;
+    const BYE: u8 = 4;
+    const CHECKSUMS: u8 = 5;
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            Self::Hello { version } => {
+                bytes.push(Self::HELLO);
+                bytes.push(*version);
+            }
+            Self::Welcome { seed, settings } => {
+                bytes.push(Self::WELCOME);
+                bytes.extend_from_slice(&seed.to_le_bytes());
+                bytes.extend_from_slice(settings);
+            }
+            Self::Inputs {
+                received,
+                start,
+                inputs,
+            } => {
+                bytes.push(Self::INPUTS);
+                bytes.extend_from_slice(&received.to_le_bytes());
+                bytes.extend_from_slice(&start.to_le_bytes());
+                bytes.extend(inputs.iter().map(|input| input.0));
+            }
+            Self::Checksums { start, checksums } => {
+                bytes.push(Self::CHECKSUMS);
+                bytes.extend_from_slice(&start.to_le_bytes());
+                for checksum in checksums {
+                    bytes.extend_from_slice(&checksum.to_le_bytes());
+                }
+            }
+            Self::Bye => bytes.push(Self::BYE),
+        }
+        return bytes;
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let (&kind, rest) = bytes.split_first()?;
+        let u32_at = |offset: usize| -> Option<u32> {
+            Some(u32::from_le_bytes(
+                rest.get(offset..offset + 4)?.try_into().ok()?,
+            ))
+        };
+        return match kind {
+            Self::HELLO => Some(Self::Hello {
+                version: *rest.first()?,
+            }),
+            Self::WELCOME => Some(Self::Welcome {
+                seed: u64::from_le_bytes(rest.get(..8)?.try_into().ok()?),
+                settings: rest[8..].to_vec(),
+            }),
+            Self::INPUTS => Some(Self::Inputs {
+                received: u32_at(0)?,
+                start: u32_at(4)?,
+                inputs: rest[8..].iter().map(|byte| NetInput(*byte)).collect(),
+            }),
+            Self::CHECKSUMS => Some(Self::Checksums {
+                start: u32_at(0)?,
+                checksums: rest[4..]
+                    .chunks_exact(8)
+                    .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+                    .collect(),
+            }),
+            Self::BYE => Some(Self::Bye),
+            _ => None,
+        };
+    }
+}
+
+pub trait Link {
+    fn send(&mut self, bytes: &[u8]);
+    fn recv(&mut self) -> Option<Vec<u8>>;
+}
+
+pub struct UdpLink {
+    socket: UdpSocket,
+    // The host learns it from the first packet that comes in.
+    peer: Option<SocketAddr>,
+}
+
+impl UdpLink {
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        return Ok(Self { socket, peer: None });
+    }
+
+    pub fn connect(peer: SocketAddr) -> io::Result<Self> {
+        let local: SocketAddr = if peer.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let mut link = Self::bind(local)?;
+        link.peer = Some(peer);
+        return Ok(link);
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        return self.socket.local_addr();
+    }
+}
+
+impl Link for UdpLink {
+    fn send(&mut self, bytes: &[u8]) {
+        if let Some(peer) = self.peer {
+            // Lost packets are dealt with by sending everything again until it is acknowledged.
+            let _ = self.socket.send_to(bytes, peer);
+        }
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        let mut buf = [0; MAX_PACKET];
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                // Includes "would block" when there is nothing to read.
+                Err(_) => return None,
+            };
+            match self.peer {
+                Some(peer) if peer != from => continue,
+                Some(_) => {}
+                None => self.peer = Some(from),
+            }
+            return Some(buf[..len].to_vec());
+        }
+    }
+}
+
+// Delays and drops outgoing packets, to see how the game copes with a bad connection
+// without leaving the machine.
+pub struct LossyLink<L: Link> {
+    inner: L,
+    latency: Duration,
+    // From 0 to 1.
+    loss: f32,
+    queue: VecDeque<(Instant, Vec<u8>)>,
+    rng: StdRng,
+}
+
+impl<L: Link> LossyLink<L> {
+    pub fn new(inner: L, latency: Duration, loss: f32) -> Self {
+        return Self {
+            inner,
+            latency,
+            loss: loss.clamp(0.0, 1.0),
+            queue: VecDeque::new(),
+            rng: StdRng::from_entropy(),
+        };
+    }
+
+    fn flush(&mut self) {
+        let now = Instant::now();
+        while let Some((due, _)) = self.queue.front() {
+            if *due > now {
+                break;
+            }
+            let (_, bytes) = self.queue.pop_front().unwrap();
+            self.inner.send(&bytes);
+        }
+    }
+}
+
+impl<L: Link> Link for LossyLink<L> {
+    fn send(&mut self, bytes: &[u8]) {
+        if self.loss > 0.0 && self.rng.gen::<f32>() < self.loss {
+            self.flush();
+            return;
+        }
+        if self.latency.is_zero() {
+            self.inner.send(bytes);
+            return;
+        }
+        self.queue
+            .push_back((Instant::now() + self.latency, bytes.to_vec()));
+        self.flush();
+    }
+
+    fn recv(&mut self) -> Option<Vec<u8>> {
+        self.flush();
+        return self.inner.recv();
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy)]
+pub struct SessionStats {
+    pub predicted: u32,
+    pub mispredicted: u32,
+    pub rollbacks: u32,
+    // The most ticks simulated again at once.
+    pub max_rollback: u32,
+}
+
+pub struct Session<L: Link> {
+    link: L,
+    local: Vec<NetInput>,
+    // Confirmed inputs of the other side, without gaps.
+    remote: Vec<NetInput>,
+    // What was used for the other side's ticks that were not confirmed yet.
+    predictions: VecDeque<(u32, NetInput)>,
+    // How many of the local inputs the other side has.
+    peer_received: u32,
+    max_prediction: u32,
+    rollback: Option<u32>,
+    last_heard: Instant,
+    // Messages other than inputs, for whoever runs the session.
+    messages: Vec<Message>,
+    pub stats: SessionStats,
+}
+
+impl<L: Link> Session<L> {
+    // The local inputs are applied `delay` ticks after they are added,
+    // which gives them time to arrive before they are needed.
+    // The session gets ahead of the other side by `max_prediction` ticks at most.
+    pub fn new(link: L, delay: u32, max_prediction: u32) -> Self {
+        return Self {
+            link,
+            local: vec![NetInput::default(); delay as usize],
+            remote: Vec::new(),
+            predictions: VecDeque::new(),
+            peer_received: 0,
+            max_prediction,
+            rollback: None,
+            last_heard: Instant::now(),
+            messages: Vec::new(),
+            stats: SessionStats::default(),
+        };
+    }
+
+    pub fn add_local(&mut self, input: NetInput) {
+        self.local.push(input);
+    }
+
+    // Including the ones that are not applied yet.
+    pub fn local_ticks(&self) -> u32 {
+        return self.local.len() as u32;
+    }
+
+    pub fn local_input(&self, tick: u32) -> NetInput {
+        return self.local.get(tick as usize).copied().unwrap_or_default();
+    }
+
+    // The confirmed input, or a guess that is remembered to be checked later.
+    pub fn remote_input(&mut self, tick: u32) -> NetInput {
+        if let Some(input) = self.remote.get(tick as usize) {
+            return *input;
+        }
+        let input = self.remote.last().copied().unwrap_or_default();
+        match self.predictions.iter_mut().find(|(t, _)| *t == tick) {
+            Some(prediction) => prediction.1 = input,
+            None => {
+                self.predictions.push_back((tick, input));
+                self.stats.predicted += 1;
+            }
+        }
+        return input;
+    }
+
+    pub fn confirmed(&self) -> u32 {
+        return self.remote.len() as u32;
+    }
+
+    pub fn can_simulate(&self, tick: u32) -> bool {
+        return (tick as usize) < self.local.len() && tick < self.confirmed() + self.max_prediction;
+    }
+
+    pub fn send(&mut self) {
+        let start = self.peer_received as usize;
+        let end = self.local.len().min(start + MAX_INPUTS_PER_PACKET);
+        let message = Message::Inputs {
+            received: self.confirmed(),
+            start: start as u32,
+            inputs: self.local[start..end].to_vec(),
+        };
+        self.link.send(&message.encode());
+    }
+
+    pub fn send_message(&mut self, message: &Message) {
+        self.link.send(&message.encode());
+    }
+
+    pub fn poll(&mut self) {
+        while let Some(bytes) = self.link.recv() {
+            self.last_heard = Instant::now();
+            match Message::decode(&bytes) {
+                Some(Message::Inputs {
+                    received,
+                    start,
+                    inputs,
+                }) => self.receive_inputs(received, start, &inputs),
+                Some(message) => self.messages.push(message),
+                None => {}
+            }
+        }
+    }
+
+    fn receive_inputs(&mut self, received: u32, start: u32, inputs: &[NetInput]) {
+        self.peer_received = self
+            .peer_received
+            .max(received.min(self.local.len() as u32));
+
+        for (offset, input) in inputs.iter().enumerate() {
+            let tick = start + offset as u32;
+            if tick < self.confirmed() {
+                continue;
+            }
+            if tick > self.confirmed() {
+                // Something before it is still missing, and will be sent again.
+                break;
+            }
+            self.remote.push(*input);
+
+            while let Some((predicted_tick, prediction)) = self.predictions.front().copied() {
+                if predicted_tick > tick {
+                    break;
+                }
+                self.predictions.pop_front();
+                if predicted_tick == tick && prediction != *input {
+                    self.stats.mispredicted += 1;
+                    self.rollback = Some(self.rollback.map_or(tick, |rollback| rollback.min(tick)));
+                }
+            }
+        }
+    }
+
+    // The first tick that was simulated with a wrong guess, if any.
+    pub fn take_rollback(&mut self, current: u32) -> Option<u32> {
+        let rollback = self.rollback.take()?;
+        self.stats.rollbacks += 1;
+        self.stats.max_rollback = self
+            .stats
+            .max_rollback
+            .max(current.saturating_sub(rollback));
+        return Some(rollback);
+    }
+
+    pub fn take_messages(&mut self) -> Vec<Message> {
+        return std::mem::take(&mut self.messages);
+    }
+
+    pub fn is_lost(&self) -> bool {
+        return self.last_heard.elapsed() > TIMEOUT;
+    }
+}
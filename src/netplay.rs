@@ -0,0 +1,725 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+// Two players on different machines, each running the whole game.
+// Start one side with "--host PORT" and the other with "--join ADDR:PORT",
+// then press ENTER on both. Add "--net-latency MS" and "--net-loss PERCENT"
+// to try a bad connection, e.g. with both sides on 127.0.0.1.
+//
+// The host picks the seed and the settings, and from then on only the inputs are exchanged.
+// The local inputs are applied a few ticks later, which gives them time to arrive.
+// When the other player's input for a tick is not there yet, it is guessed to stay the same.
+// Should a guess turn out wrong, the run goes back to the last tick that was simulated
+// with the right inputs (see rollback.rs), and the ticks after it are simulated again
+// in the same frame, without their sounds. The game slows down when the inputs are late
+// by more than a few ticks, so for a slow connection raise "--net-delay TICKS"
+// to cover the one-way latency (a tick is about 17 ms).
+// Nothing is guessed outside of the game itself, or when the run may be over,
+// since going back cannot undo a change of the game state.
+//
+// Both sides also send the checksums of the ticks that cannot be simulated again.
+// Should the runs ever go apart anyway, each side saves what its state was
+// at the first tick that differs (see checksum.rs).
+
+use crate::anim::MainFont;
+use crate::bullet::BulletCancel;
+use crate::checksum::Checksums;
+use crate::collision::Screen;
+use crate::difficulty::Difficulty;
+use crate::net::{LossyLink, Message, NetInput, Session, UdpLink, PROTOCOL_VERSION};
+use crate::player::{self, Player, Slots, TickInputs, MAX_PLAYERS};
+use crate::rollback::Snapshots;
+use crate::sim::{NextSeed, Settings, SimClock};
+use crate::stage::GameMode;
+use crate::{AnimPlugin, GameState};
+use bevy::prelude::*;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+pub struct NetplayPlugin;
+
+impl Plugin for NetplayPlugin {
+    fn build(&self, app: &mut App) {
+        let config = match NetConfig::from_args(std::env::args().skip(1)) {
+            Ok(Some(config)) => config,
+            Ok(None) => return,
+            Err(e) => {
+                error!("netplay: {}", e);
+                return;
+            }
+        };
+
+        add_netplay(app, config);
+    }
+}
+
+fn add_netplay(app: &mut App, config: NetConfig) {
+    app.insert_resource(Netplay::new(config))
+        .add_startup_system(setup)
+        .add_system_set(SystemSet::on_enter(GameState::Lobby).with_system(enter_lobby))
+        .add_system_set(
+            SystemSet::on_update(GameState::Lobby)
+                .with_system(update_lobby_text)
+                .with_system(leave_lobby),
+        )
+        .add_system_set(SystemSet::on_exit(GameState::Lobby).with_system(hide_lobby_text))
+        .add_system_set(
+            SystemSet::on_enter(GameState::TitleFlyOut)
+                .with_system(join_both.after(player::reset_slots)),
+        )
+        .add_system_set(SystemSet::on_enter(GameState::GameOver).with_system(finish))
+        .add_system_set(
+            SystemSet::on_update(GameState::Game).with_system(
+                apply_inputs
+                    .after(player::read_input)
+                    .before(player::apply_input),
+            ),
+        )
+        .add_system(count_tick)
+        .add_system_to_stage(CoreStage::PostUpdate, record_input)
+        .add_system_to_stage(CoreStage::Last, exchange);
+}
+
+const DELAY: u32 = 2;
+const MAX_PREDICTION: u32 = 8;
+const HELLO_PERIOD: f32 = 0.25;
+// Each checksum is sent this many times, in case some packets are lost.
+const CHECKSUMS_PER_PACKET: usize = 8;
+
+type NetLink = LossyLink<UdpLink>;
+
+enum Role {
+    Host(u16),
+    Join(SocketAddr),
+}
+
+pub struct NetConfig {
+    role: Role,
+    // In ticks.
+    delay: u32,
+    latency: Duration,
+    // From 0 to 1.
+    loss: f32,
+}
+
+impl NetConfig {
+    fn from_args(mut args: impl Iterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut role = None;
+        let mut delay = DELAY;
+        let mut latency = Duration::ZERO;
+        let mut loss = 0.0;
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--host" => {
+                    let port = value()?;
+                    role = Some(Role::Host(
+                        port.parse().map_err(|_| format!("bad port: {}", port))?,
+                    ));
+                }
+                "--join" => {
+                    let addr = value()?;
+                    role =
+                        Some(Role::Join(addr.parse().map_err(|_| {
+                            format!("bad address (need IP:PORT): {}", addr)
+                        })?));
+                }
+                "--net-delay" => {
+                    let ticks = value()?;
+                    delay = ticks.parse().map_err(|_| format!("bad delay: {}", ticks))?;
+                }
+                "--net-latency" => {
+                    let ms = value()?;
+                    latency = Duration::from_millis(
+                        ms.parse().map_err(|_| format!("bad latency: {}", ms))?,
+                    );
+                }
+                "--net-loss" => {
+                    let percent = value()?;
+                    loss = percent
+                        .parse::<f32>()
+                        .map_err(|_| format!("bad loss: {}", percent))?
+                        / 100.0;
+                }
+                _ => {}
+            }
+        }
+
+        return Ok(role.map(|role| Self {
+            role,
+            delay,
+            latency,
+            loss,
+        }));
+    }
+
+    // Also tells what the lobby should say while waiting.
+    fn connect(&self) -> std::io::Result<(NetLink, String)> {
+        let (link, status) = match self.role {
+            Role::Host(port) => {
+                let link = UdpLink::bind(SocketAddr::from(([0, 0, 0, 0], port)))?;
+                let port = link.local_addr()?.port();
+                let status = format!("HOSTING ON PORT {}\nWAITING FOR PLAYER 2", port);
+                (link, status)
+            }
+            Role::Join(addr) => (UdpLink::connect(addr)?, format!("CONNECTING TO {}", addr)),
+        };
+        return Ok((LossyLink::new(link, self.latency, self.loss), status));
+    }
+
+    fn is_host(&self) -> bool {
+        return matches!(self.role, Role::Host(_));
+    }
+}
+
+pub struct Netplay {
+    config: NetConfig,
+    session: Option<Session<NetLink>>,
+    running: bool,
+    // The slot of the player on this machine.
+    local: usize,
+    // The next tick to simulate.
+    tick: u32,
+    simulated: bool,
+    // Sent again if the other side did not get it.
+    welcome: Option<Message>,
+    hello_timer: Timer,
+    status: String,
+    // The other side's checksums that were not compared yet, by tick.
+    remote_checksums: BTreeMap<u64, u64>,
+    desynced: bool,
+}
+
+impl Netplay {
+    pub fn new(config: NetConfig) -> Self {
+        return Self {
+            config,
+            session: None,
+            running: false,
+            local: 0,
+            tick: 0,
+            simulated: false,
+            welcome: None,
+            hello_timer: Timer::from_seconds(HELLO_PERIOD, true),
+            status: String::new(),
+            remote_checksums: BTreeMap::new(),
+            desynced: false,
+        };
+    }
+
+    fn start(&mut self, local: usize) {
+        self.running = true;
+        self.local = local;
+        self.tick = 0;
+        self.simulated = false;
+        self.remote_checksums.clear();
+        self.desynced = false;
+    }
+
+    fn stop(&mut self) {
+        if let (true, Some(session)) = (self.running, &self.session) {
+            let stats = session.stats;
+            info!(
+                "netplay: {} ticks, {} guessed, {} guessed wrong",
+                self.tick, stats.predicted, stats.mispredicted
+            );
+        }
+        self.session = None;
+        self.running = false;
+        self.welcome = None;
+    }
+
+    fn remote(&self) -> usize {
+        return 1 - self.local;
+    }
+
+    // The inputs of both players for the tick, by slot.
+    fn inputs(&mut self, tick: u32) -> [NetInput; MAX_PLAYERS] {
+        let session = self.session.as_mut().unwrap();
+        let mut inputs = [NetInput::default(); MAX_PLAYERS];
+        inputs[self.local] = session.local_input(tick);
+        inputs[1 - self.local] = session.remote_input(tick);
+        return inputs;
+    }
+
+    // Only the first tick that differs is reported, the ones after it would differ too.
+    // The ticks from `until` on may still be simulated again.
+    fn compare_checksums(&mut self, checksums: &Checksums, until: u64) {
+        let oldest = checksums.oldest().unwrap_or_default();
+        while let Some((&tick, &remote)) = self.remote_checksums.iter().next() {
+            if tick >= until {
+                break;
+            }
+            let local = match checksums.get(tick) {
+                Some(local) => local,
+                None if tick < oldest => {
+                    self.remote_checksums.remove(&tick);
+                    continue;
+                }
+                // Not simulated here yet.
+                None => break,
+            };
+            self.remote_checksums.remove(&tick);
+            if local == remote || self.desynced {
+                continue;
+            }
+
+            self.desynced = true;
+            warn!("netplay: out of sync since tick {}", tick);
+            let side = if self.config.is_host() {
+                "host"
+            } else {
+                "join"
+            };
+            if let Some(path) = checksums.dump(tick, side) {
+                warn!(
+                    "netplay: the state at that tick is saved to {}, diff it with the other side's",
+                    path.display()
+                );
+            }
+        }
+    }
+}
+
+#[derive(Component)]
+struct LobbyText;
+
+fn setup(mut commands: Commands, font: Res<MainFont>, win: Res<WindowDescriptor>) {
+    let mut text = AnimPlugin::text_bundle(
+        &font.0,
+        "",
+        50.0,
+        Vec3::new(win.middle_x(), win.middle_y() - 0.35, 0.1),
+    );
+    text.visibility.is_visible = false;
+    commands
+        .spawn_bundle(text)
+        .insert(LobbyText)
+        .insert(Name::new("LobbyText"));
+}
+
+fn enter_lobby(mut netplay: ResMut<Netplay>, mut q: Query<&mut Visibility, With<LobbyText>>) {
+    q.single_mut().is_visible = true;
+    netplay.stop();
+    netplay.status = match netplay.config.connect() {
+        Ok((link, status)) => {
+            netplay.session = Some(Session::new(link, netplay.config.delay, MAX_PREDICTION));
+            status
+        }
+        Err(e) => format!("CANNOT CONNECT: {}", e).to_uppercase(),
+    };
+}
+
+fn update_lobby_text(netplay: Res<Netplay>, mut q: Query<&mut Text, With<LobbyText>>) {
+    let mut text = q.single_mut();
+    let value = format!("{}\n\nESC - BACK", netplay.status);
+    if text.sections.first().unwrap().value != value {
+        text.sections.first_mut().unwrap().value = value;
+    }
+}
+
+fn hide_lobby_text(mut q: Query<&mut Visibility, With<LobbyText>>) {
+    q.single_mut().is_visible = false;
+}
+
+fn leave_lobby(
+    mut kbd: ResMut<Input<KeyCode>>,
+    mut netplay: ResMut<Netplay>,
+    mut state: ResMut<State<GameState>>,
+) {
+    if netplay.running {
+        state.set(GameState::TitleFlyOut).unwrap();
+        return;
+    }
+    if kbd.just_pressed(KeyCode::Escape) {
+        netplay.stop();
+        state.set(GameState::Title).unwrap();
+        kbd.clear();
+    }
+}
+
+// Both players are in from the start.
+fn join_both(netplay: Res<Netplay>, mut slots: ResMut<Slots>) {
+    if netplay.running {
+        for slot in &mut slots.0 {
+            slot.joined = true;
+        }
+    }
+}
+
+// Nothing runs in the frames that are skipped while waiting for the other side.
+fn count_tick(netplay: Option<ResMut<Netplay>>) {
+    if let Some(mut netplay) = netplay {
+        netplay.simulated = netplay.running;
+    }
+}
+
+// Replaces whatever was read from the local controls with the inputs of the tick.
+fn apply_inputs(netplay: Option<ResMut<Netplay>>, mut inputs: ResMut<TickInputs>) {
+    let mut netplay = match netplay {
+        Some(netplay) if netplay.running => netplay,
+        _ => return,
+    };
+
+    let tick = netplay.tick;
+    inputs.0 = netplay.inputs(tick);
+}
+
+fn record_input(
+    netplay: Option<ResMut<Netplay>>,
+    kbd: Res<Input<KeyCode>>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
+) {
+    let mut netplay = match netplay {
+        Some(netplay) if netplay.simulated => netplay,
+        _ => return,
+    };
+
+    netplay.simulated = false;
+    netplay.tick += 1;
+    let ahead = netplay.tick + netplay.config.delay;
+    let session = netplay.session.as_mut().unwrap();
+    // The ticks that are simulated again after a rollback have theirs already.
+    if session.local_ticks() < ahead {
+        let input = player::read_controls(0, &kbd, &buttons, &axes);
+        session.add_local(input.pack());
+    }
+}
+
+fn exchange(
+    mut commands: Commands,
+    netplay: Option<ResMut<Netplay>>,
+    (mut clock, mut next_seed, checksums): (ResMut<SimClock>, ResMut<NextSeed>, Res<Checksums>),
+    (mut mode, mut difficulty, mut cancel): (
+        ResMut<GameMode>,
+        ResMut<Difficulty>,
+        ResMut<BulletCancel>,
+    ),
+    (mut slots, state, mut snapshots): (ResMut<Slots>, Res<State<GameState>>, ResMut<Snapshots>),
+    q: Query<(Entity, &Player)>,
+    time: Res<Time>,
+) {
+    let mut netplay = match netplay {
+        Some(netplay) => netplay,
+        None => return,
+    };
+    let netplay = &mut *netplay;
+    let session = match &mut netplay.session {
+        Some(session) => session,
+        None => return,
+    };
+
+    if !netplay.running {
+        if !netplay.config.is_host() && netplay.hello_timer.tick(time.delta()).just_finished() {
+            session.send_message(&Message::Hello {
+                version: PROTOCOL_VERSION,
+            });
+        }
+        session.poll();
+        let mut local = None;
+        for message in session.take_messages() {
+            match message {
+                Message::Hello { version } if netplay.config.is_host() => {
+                    if version != PROTOCOL_VERSION {
+                        session.send_message(&Message::Bye);
+                        netplay.status = "PLAYER 2 HAS A DIFFERENT VERSION".to_string();
+                        continue;
+                    }
+                    let seed = rand::random();
+                    let settings = Settings {
+                        mode: *mode,
+                        difficulty: *difficulty,
+                        cancel: *cancel,
+                    };
+                    let welcome = Message::Welcome {
+                        seed,
+                        settings: ron::to_string(&settings).unwrap().into_bytes(),
+                    };
+                    session.send_message(&welcome);
+                    netplay.welcome = Some(welcome);
+                    next_seed.0 = Some(seed);
+                    local = Some(0);
+                }
+                Message::Welcome { seed, settings } if !netplay.config.is_host() => {
+                    let settings = String::from_utf8(settings)
+                        .ok()
+                        .and_then(|settings| ron::from_str::<Settings>(&settings).ok());
+                    let settings = match settings {
+                        Some(settings) => settings,
+                        None => {
+                            netplay.status = "THE HOST HAS A DIFFERENT VERSION".to_string();
+                            continue;
+                        }
+                    };
+                    *mode = settings.mode;
+                    *difficulty = settings.difficulty;
+                    *cancel = settings.cancel;
+                    next_seed.0 = Some(seed);
+                    local = Some(1);
+                }
+                Message::Bye if !netplay.config.is_host() => {
+                    netplay.status = "THE HOST HAS A DIFFERENT VERSION".to_string();
+                }
+                _ => {}
+            }
+            if local.is_some() {
+                break;
+            }
+        }
+        if let Some(local) = local {
+            // The first tick is simulated in the next frame on both sides.
+            netplay.start(local);
+            clock.fix(SimClock::STEP);
+            // Enough to go back to the oldest guess.
+            snapshots.start(1, Some(MAX_PREDICTION as usize + 2));
+        }
+        return;
+    }
+
+    // The ticks simulated again after a rollback are not sent or heard from the other side.
+    if !clock.catching_up {
+        // Ticks before it were simulated with inputs that are not going to change.
+        let confirmed = session.confirmed().min(netplay.tick) as u64;
+        session.send();
+        let (start, recent) = checksums.recent(CHECKSUMS_PER_PACKET, confirmed);
+        session.send_message(&Message::Checksums {
+            start: start as u32,
+            checksums: recent,
+        });
+        session.poll();
+
+        let mut left = session.is_lost();
+        for message in session.take_messages() {
+            match message {
+                Message::Hello { .. } => {
+                    if let Some(welcome) = &netplay.welcome {
+                        session.send_message(welcome);
+                    }
+                }
+                Message::Checksums { start, checksums } => {
+                    for (offset, checksum) in checksums.into_iter().enumerate() {
+                        let tick = start as u64 + offset as u64;
+                        netplay.remote_checksums.insert(tick, checksum);
+                    }
+                }
+                Message::Bye => left = true,
+                _ => {}
+            }
+        }
+        if left {
+            warn!("netplay: the other player has left");
+            leave(
+                &mut commands,
+                netplay,
+                &mut clock,
+                &mut next_seed,
+                &mut slots,
+                &mut snapshots,
+                &q,
+            );
+            return;
+        }
+
+        if let Some(rollback) = session.take_rollback(netplay.tick) {
+            // Guesses are only made in the game, which is saved after every tick.
+            snapshots.restore(rollback as u64 - 1);
+            clock.catch_up = netplay.tick - rollback;
+            clock.quiet = true;
+            netplay.tick = rollback;
+        }
+        netplay.compare_checksums(&checksums, confirmed);
+    }
+
+    // Whether the game goes on, and how, is only decided with the right inputs.
+    let session = netplay.session.as_ref().unwrap();
+    let needed = if *state.current() != GameState::Game {
+        netplay.tick + 1
+    } else if !slots.0.iter().any(|slot| slot.is_playing()) {
+        netplay.tick
+    } else {
+        0
+    };
+    clock.stalled = !session.can_simulate(netplay.tick) || session.confirmed() < needed;
+}
+
+// The other player is gone, so the game goes on locally.
+fn leave(
+    commands: &mut Commands,
+    netplay: &mut Netplay,
+    clock: &mut SimClock,
+    next_seed: &mut NextSeed,
+    slots: &mut Slots,
+    snapshots: &mut Snapshots,
+    q: &Query<(Entity, &Player)>,
+) {
+    let remote = netplay.remote();
+    netplay.stop();
+    snapshots.stop();
+    clock.unfix();
+    next_seed.0 = None;
+
+    slots.0[remote].leave();
+    for (entity, player) in q {
+        if player.index() == remote {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+fn finish(
+    mut netplay: ResMut<Netplay>,
+    mut clock: ResMut<SimClock>,
+    mut next_seed: ResMut<NextSeed>,
+    mut slots: ResMut<Slots>,
+    mut snapshots: ResMut<Snapshots>,
+) {
+    if !netplay.running {
+        return;
+    }
+    if let Some(session) = &mut netplay.session {
+        session.send_message(&Message::Bye);
+    }
+    let remote = netplay.remote();
+    netplay.stop();
+    snapshots.stop();
+    clock.unfix();
+    next_seed.0 = None;
+    slots.0[remote].leave();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+    use std::net::UdpSocket;
+    use std::time::Instant;
+
+    // Both sides play until this tick, the first part of it before the game itself starts.
+    const TICKS: u32 = 600;
+    const TIMEOUT: Duration = Duration::from_secs(300);
+    const LATENCY: Duration = Duration::from_millis(30);
+    const LOSS: f32 = 0.1;
+    // How often the held keys change, in frames.
+    const KEYS_PERIOD: u32 = 8;
+    const KEYS: [KeyCode; 6] = [
+        KeyCode::W,
+        KeyCode::S,
+        KeyCode::A,
+        KeyCode::D,
+        KeyCode::M,
+        KeyCode::LShift,
+    ];
+
+    struct Side {
+        app: App,
+        rng: StdRng,
+        frames: u32,
+        // The checksums of the ticks that cannot be simulated again.
+        checksums: BTreeMap<u64, u64>,
+    }
+
+    impl Side {
+        fn new(role: Role, seed: u64) -> Self {
+            let mut app = verify::headless_app();
+            let config = NetConfig {
+                role,
+                delay: DELAY,
+                latency: LATENCY,
+                loss: LOSS,
+            };
+            add_netplay(&mut app, config);
+            return Self {
+                app,
+                rng: StdRng::seed_from_u64(seed),
+                frames: 0,
+                checksums: BTreeMap::new(),
+            };
+        }
+
+        fn netplay(&self) -> &Netplay {
+            return self.app.world.resource::<Netplay>();
+        }
+
+        fn update(&mut self) {
+            let state = *self.app.world.resource::<State<GameState>>().current();
+            if state == GameState::TitleFlyIn {
+                let mut state = self.app.world.resource_mut::<State<GameState>>();
+                state.overwrite_set(GameState::Lobby).unwrap();
+            }
+            if state == GameState::Game {
+                self.frames += 1;
+                if self.frames.is_multiple_of(KEYS_PERIOD) {
+                    let held: Vec<bool> = KEYS.iter().map(|_| self.rng.gen_bool(0.5)).collect();
+                    let mut kbd = self.app.world.resource_mut::<Input<KeyCode>>();
+                    for (key, held) in KEYS.into_iter().zip(held) {
+                        if held {
+                            kbd.press(key);
+                        } else {
+                            kbd.release(key);
+                        }
+                    }
+                }
+            }
+
+            self.app.update();
+
+            let netplay = self.netplay();
+            let confirmed = match &netplay.session {
+                Some(session) if netplay.running => session.confirmed().min(netplay.tick),
+                _ => return,
+            };
+            let checksums = self.app.world.resource::<Checksums>();
+            let start = checksums.oldest().unwrap_or_default();
+            for tick in start..confirmed as u64 {
+                if let Some(checksum) = checksums.get(tick) {
+                    self.checksums.insert(tick, checksum);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn both_sides_play_the_same_run() {
+        let port = UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut host = Side::new(Role::Host(port), 1);
+        let mut join = Side::new(Role::Join(SocketAddr::from(([127, 0, 0, 1], port))), 2);
+
+        let started = Instant::now();
+        while host.netplay().tick < TICKS || join.netplay().tick < TICKS {
+            assert!(started.elapsed() < TIMEOUT, "the run does not go on");
+            host.update();
+            join.update();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        for side in [&host, &join] {
+            let netplay = side.netplay();
+            assert!(netplay.running, "the run has ended");
+            assert!(!netplay.desynced);
+            assert!(netplay.session.as_ref().unwrap().stats.rollbacks > 0);
+        }
+        let compared: Vec<u64> = host
+            .checksums
+            .keys()
+            .filter(|tick| join.checksums.contains_key(tick))
+            .copied()
+            .collect();
+        assert!(compared.len() > TICKS as usize / 2);
+        for tick in compared {
+            assert_eq!(
+                host.checksums[&tick], join.checksums[&tick],
+                "tick {}",
+                tick
+            );
+        }
+    }
+}
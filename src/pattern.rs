@@ -0,0 +1,417 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+use crate::anim::AssetsLoading;
+use crate::collision::Screen;
+use crate::data::RonLoader;
+use crate::difficulty::Difficulty;
+use crate::player::Player;
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::f32::consts::{FRAC_PI_2, TAU};
+
+pub struct PatternPlugin;
+
+impl Plugin for PatternPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<PatternLibrary>()
+            .add_asset_loader(RonLoader::<PatternLibrary>::new(&["patterns.ron"]))
+            .add_startup_system_to_stage(StartupStage::PreStartup, load_assets)
+            .add_system(movement);
+    }
+}
+
+const MAX_REF_DEPTH: usize = 16;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Side {
+    Top,
+    Bottom,
+    Left,
+    Right,
+}
+
+impl Side {
+    // Patterns are authored for enemies that enter from the right edge:
+    // negative X goes into the screen, Y is the vertical offset.
+    // Every other edge gets a rotated or mirrored copy of the same movement.
+    pub fn to_world(self, v: Vec2) -> Vec2 {
+        return match self {
+            Side::Right => v,
+            Side::Left => Vec2::new(-v.x, v.y),
+            Side::Top => Vec2::new(v.y, v.x),
+            Side::Bottom => Vec2::new(v.y, -v.x),
+        };
+    }
+
+    pub fn to_local(self, v: Vec2) -> Vec2 {
+        return match self {
+            Side::Right => v,
+            Side::Left => Vec2::new(-v.x, v.y),
+            Side::Top => Vec2::new(v.y, v.x),
+            Side::Bottom => Vec2::new(-v.y, v.x),
+        };
+    }
+
+    // "pos" is the Y coordinate for the left and right edges
+    // and a fraction of the screen width for the top and bottom edges.
+    pub fn entry_point(&self, pos: f32, size: Vec2, win: &WindowDescriptor) -> Vec2 {
+        let bounds = win.bounds_box_outside(size);
+        return match self {
+            Side::Right => Vec2::new(bounds.right, pos),
+            Side::Left => Vec2::new(bounds.left, pos),
+            Side::Top => Vec2::new(win.max_x() * pos, bounds.top),
+            Side::Bottom => Vec2::new(win.max_x() * pos, bounds.bottom),
+        };
+    }
+
+    // Turns a sprite that faces left so that it faces into the screen.
+    pub fn orient(&self, transform: &mut Transform) {
+        match self {
+            Side::Right => {}
+            Side::Left => transform.scale.x = -transform.scale.x,
+            Side::Top => transform.rotation = Quat::from_rotation_z(FRAC_PI_2),
+            Side::Bottom => transform.rotation = Quat::from_rotation_z(-FRAC_PI_2),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum Pattern {
+    Linear(Vec2),
+    Spline {
+        points: Vec<Vec2>,
+        duration: f32,
+    },
+    Bezier {
+        points: Vec<Vec2>,
+        duration: f32,
+    },
+    Sine {
+        amplitude: Vec2,
+        period: f32,
+        #[serde(default)]
+        phase: f32,
+    },
+    ZigZag {
+        amplitude: Vec2,
+        period: f32,
+    },
+    Dash {
+        speed: f32,
+        #[serde(default)]
+        direction: Option<Vec2>,
+    },
+    Sum(Vec<Pattern>),
+    Sequence(Vec<(f32, Pattern)>),
+    Ref(String),
+}
+
+impl Pattern {
+    fn displacement(&self, t: f32, aim: Vec2) -> Vec2 {
+        return match self {
+            Pattern::Linear(velocity) => *velocity * t,
+            Pattern::Spline { points, duration } => {
+                Self::along_curve(t, *duration, &Self::with_start(points), Self::spline)
+            }
+            Pattern::Bezier { points, duration } => {
+                Self::along_curve(t, *duration, &Self::with_start(points), Self::bezier)
+            }
+            Pattern::Sine {
+                amplitude,
+                period,
+                phase,
+            } => *amplitude * ((t / period * TAU + phase).sin() - phase.sin()),
+            Pattern::ZigZag { amplitude, period } => {
+                let triangle = 1.0 - 4.0 * ((t / period + 0.25).fract() - 0.5).abs();
+                *amplitude * triangle
+            }
+            Pattern::Dash { speed, direction } => {
+                direction.unwrap_or(aim).normalize_or_zero() * *speed * t
+            }
+            Pattern::Sum(patterns) => patterns
+                .iter()
+                .fold(Vec2::ZERO, |sum, p| sum + p.displacement(t, aim)),
+            Pattern::Sequence(segments) => {
+                let mut offset = Vec2::ZERO;
+                let mut t = t;
+                for (i, (duration, segment)) in segments.iter().enumerate() {
+                    if t <= *duration || i == segments.len() - 1 {
+                        return offset + segment.displacement(t, aim);
+                    }
+                    offset += segment.displacement(*duration, aim);
+                    t -= duration;
+                }
+                offset
+            }
+            Pattern::Ref(_) => Vec2::ZERO,
+        };
+    }
+
+    fn is_aiming(&self, t: f32) -> bool {
+        return match self {
+            Pattern::Dash { direction, .. } => direction.is_none(),
+            Pattern::Sum(patterns) => patterns.iter().any(|p| p.is_aiming(t)),
+            Pattern::Sequence(segments) => {
+                let mut t = t;
+                for (i, (duration, segment)) in segments.iter().enumerate() {
+                    if segment.is_aiming(t) {
+                        return true;
+                    }
+                    if t <= *duration || i == segments.len() - 1 {
+                        return false;
+                    }
+                    t -= duration;
+                }
+                false
+            }
+            _ => false,
+        };
+    }
+
+    fn resolve(&self, library: &PatternLibrary, depth: usize) -> Option<Pattern> {
+        if depth > MAX_REF_DEPTH {
+            warn!("pattern references are nested too deep");
+            return None;
+        }
+
+        let pattern = match self {
+            Pattern::Ref(name) => match library.patterns.get(name) {
+                Some(pattern) => pattern.resolve(library, depth + 1)?,
+                None => {
+                    warn!("unknown pattern: {}", name);
+                    return None;
+                }
+            },
+            Pattern::Sum(patterns) => Pattern::Sum(
+                patterns
+                    .iter()
+                    .map(|p| p.resolve(library, depth + 1))
+                    .collect::<Option<_>>()?,
+            ),
+            Pattern::Sequence(segments) => Pattern::Sequence(
+                segments
+                    .iter()
+                    .map(|(duration, p)| Some((*duration, p.resolve(library, depth + 1)?)))
+                    .collect::<Option<_>>()?,
+            ),
+            _ => self.clone(),
+        };
+        return Some(pattern);
+    }
+
+    fn with_start(points: &[Vec2]) -> Vec<Vec2> {
+        let mut result = Vec::with_capacity(points.len() + 1);
+        result.push(Vec2::ZERO);
+        result.extend_from_slice(points);
+        return result;
+    }
+
+    // Past its duration a curve keeps going along its final direction,
+    // so that enemies leave the screen instead of hanging at the last point.
+    fn along_curve(t: f32, duration: f32, points: &[Vec2], f: fn(&[Vec2], f32) -> Vec2) -> Vec2 {
+        if duration <= 0.0 || points.len() < 2 {
+            return f(points, 1.0);
+        }
+        if t <= duration {
+            return f(points, t / duration);
+        }
+
+        let last_step = points[points.len() - 1] - points[points.len() - 2];
+        let end_velocity = last_step * (points.len() - 1) as f32 / duration;
+        return f(points, 1.0) + end_velocity * (t - duration);
+    }
+
+    fn bezier(points: &[Vec2], u: f32) -> Vec2 {
+        let mut points = points.to_vec();
+        while points.len() > 1 {
+            for i in 0..points.len() - 1 {
+                points[i] = points[i].lerp(points[i + 1], u);
+            }
+            points.pop();
+        }
+        return points[0];
+    }
+
+    // Catmull-Rom spline through all the points.
+    fn spline(points: &[Vec2], u: f32) -> Vec2 {
+        if points.len() < 2 {
+            return points[0];
+        }
+
+        let steps = points.len() - 1;
+        let pos = u * steps as f32;
+        let i = (pos as usize).min(steps - 1);
+        let s = pos - i as f32;
+
+        let p1 = points[i];
+        let p2 = points[i + 1];
+        let p0 = if i == 0 { p1 * 2.0 - p2 } else { points[i - 1] };
+        let p3 = if i + 2 > steps {
+            p2 * 2.0 - p1
+        } else {
+            points[i + 2]
+        };
+
+        return 0.5
+            * (p1 * 2.0
+                + (p2 - p0) * s
+                + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * s * s
+                + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * s * s * s);
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Formation {
+    pub pattern: String,
+    pub count: usize,
+    #[serde(default)]
+    pub delay: f32,
+    #[serde(default)]
+    pub spacing: f32,
+}
+
+#[derive(Deserialize, TypeUuid)]
+#[uuid = "7c0a3a3e-6f5e-4a53-9d0c-2b1f7f3c9a41"]
+pub struct PatternLibrary {
+    patterns: BTreeMap<String, Pattern>,
+    #[serde(default)]
+    formations: BTreeMap<String, Formation>,
+}
+
+impl PatternLibrary {
+    pub fn pattern(&self, name: &str) -> Option<Pattern> {
+        return Pattern::Ref(name.to_string()).resolve(self, 0);
+    }
+
+    pub fn formation(&self, name: &str) -> Option<&Formation> {
+        let formation = self.formations.get(name);
+        if formation.is_none() {
+            warn!("unknown formation: {}", name);
+        }
+        return formation;
+    }
+
+    pub fn pattern_names(&self) -> Vec<&str> {
+        return self.patterns.keys().map(|s| s.as_str()).collect();
+    }
+
+    pub fn formation_names(&self) -> Vec<&str> {
+        return self.formations.keys().map(|s| s.as_str()).collect();
+    }
+
+    // Members of a formation follow the leader along the same path,
+    // each one starting later and/or shifted sideways.
+    pub fn formation_members(
+        &self,
+        formation: &Formation,
+        side: Side,
+        origin: Vec2,
+        win: &WindowDescriptor,
+    ) -> Vec<MovementPattern> {
+        let pattern = match self.pattern(&formation.pattern) {
+            Some(pattern) => pattern,
+            None => return Vec::new(),
+        };
+
+        // The whole formation is moved along the edge to fit on the screen,
+        // otherwise the members that start past a corner are destroyed right away.
+        let last = formation.count.saturating_sub(1) as f32;
+        let span = side.to_world(Vec2::new(0.0, formation.spacing * last));
+        let screen = Vec2::new(win.max_x(), win.max_y());
+        let low = origin.min(origin + span);
+        let high = origin.max(origin + span);
+        let fit = (-low).max(Vec2::ZERO) - (high - screen).max(Vec2::ZERO);
+        let origin = origin + fit * side.to_world(Vec2::Y).abs();
+
+        return (0..formation.count)
+            .map(|i| {
+                let shift = side.to_world(Vec2::new(0.0, formation.spacing * i as f32));
+                MovementPattern::new(pattern.clone(), side, origin + shift)
+                    .with_delay(formation.delay * i as f32)
+            })
+            .collect();
+    }
+}
+
+pub struct Patterns(pub Handle<PatternLibrary>);
+
+#[derive(Component, Clone)]
+pub struct MovementPattern {
+    pattern: Pattern,
+    side: Side,
+    origin: Vec2,
+    elapsed: f32,
+    aim: Option<Vec2>,
+}
+
+impl MovementPattern {
+    pub fn new(pattern: Pattern, side: Side, origin: Vec2) -> Self {
+        return Self {
+            pattern,
+            side,
+            origin,
+            elapsed: 0.0,
+            aim: None,
+        };
+    }
+
+    pub fn with_delay(mut self, delay: f32) -> Self {
+        self.elapsed = -delay;
+        return self;
+    }
+
+    pub fn side(&self) -> Side {
+        return self.side;
+    }
+
+    pub fn position(&self) -> Vec2 {
+        let t = self.elapsed.max(0.0);
+        let aim = self.aim.unwrap_or(Vec2::ZERO);
+        return self.origin + self.side.to_world(self.pattern.displacement(t, aim));
+    }
+}
+
+fn load_assets(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    mut loading: ResMut<AssetsLoading>,
+) {
+    let handle = assets.load("enemies.patterns.ron");
+    loading.push(handle.clone_untyped());
+    commands.insert_resource(Patterns(handle));
+}
+
+fn movement(
+    mut q: Query<(&mut MovementPattern, &mut Transform)>,
+    player_q: Query<&Transform, (With<Player>, Without<MovementPattern>)>,
+    time: Res<Time>,
+    difficulty: Res<Difficulty>,
+) {
+    let players: Vec<Vec2> = player_q.iter().map(|t| t.translation.truncate()).collect();
+
+    for (mut movement, mut transform) in &mut q {
+        movement.elapsed += time.delta_seconds() * difficulty.preset().enemy_speed;
+
+        if movement.aim.is_none()
+            && movement.elapsed >= 0.0
+            && movement.pattern.is_aiming(movement.elapsed)
+        {
+            let pos = transform.translation.truncate();
+            let player_pos = players
+                .iter()
+                .copied()
+                .min_by(|a, b| pos.distance(*a).total_cmp(&pos.distance(*b)));
+            let aim = match player_pos {
+                Some(player_pos) => movement.side.to_local(player_pos - pos),
+                None => Vec2::NEG_X,
+            };
+            movement.aim = Some(aim.normalize_or_zero());
+        }
+
+        let pos = movement.position();
+        transform.translation = pos.extend(transform.translation.z);
+    }
+}
@@ -1,14 +1,21 @@
 // SPDX-License-Identifier: GPL-3.0-only
 // 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
 
-use crate::anim::{AnimationTimer, AssetsLoading};
-use crate::bullet::{Bullet, BulletRes, BulletTimer, HitBox};
-use crate::collision::Screen;
-use crate::enemy::{Enemy, EnemyBullet};
-use crate::explosion::{Explosion, ExplosionAudio, ExplosionSheet};
+use crate::anim::{AnimationTimer, AssetsLoading, MainFont};
+use crate::boss::BossPart;
+use crate::bullet::{Bullet, BulletMotion, BulletRes, BulletTimer, HomingTarget};
+use crate::collision::{Collider, CollisionLayers, Screen};
+use crate::enemy::Enemy;
+use crate::grid::Layers;
+use crate::health::{self, Damage, DamageKind, Died, Health, OverTime};
+use crate::net::NetInput;
+use crate::pool::Pools;
+use crate::rank::RankEvent;
+use crate::score::Score;
+use crate::shape::Shape;
 use crate::{AnimPlugin, GameState};
 use bevy::prelude::*;
-use bevy::sprite::collide_aabb::collide;
+use std::f32::consts::FRAC_PI_4;
 
 #[cfg(feature = "inspector")]
 use bevy_inspector_egui::Inspectable;
@@ -18,15 +25,30 @@ pub struct PlayerPlugin;
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
         app.add_startup_system_to_stage(StartupStage::PreStartup, load_assets)
+            .add_startup_system(setup)
+            .insert_resource(PlayerConfig::default())
+            .insert_resource(Slots::default())
+            .insert_resource(TickInputs::default())
+            .add_system_set(SystemSet::on_enter(GameState::TitleFlyOut).with_system(reset_slots))
             .add_system_set(SystemSet::on_enter(GameState::PlayerSlideOut).with_system(spawn))
             .add_system_set(SystemSet::on_update(GameState::PlayerSlideOut).with_system(slide_out))
+            .add_system_set(SystemSet::on_enter(GameState::Game).with_system(show_slot_text))
+            .add_system_set(SystemSet::on_exit(GameState::Game).with_system(hide_slot_text))
             .add_system_set(
                 SystemSet::on_update(GameState::Game)
-                    .with_system(movement)
-                    .with_system(attack)
-                    .with_system(collision_with_enemy)
-                    .with_system(collision_with_bullet),
-            );
+                    .with_system(read_input)
+                    .with_system(apply_input.after(read_input))
+                    .with_system(movement.after(apply_input))
+                    .with_system(show_core.after(movement))
+                    .with_system(attack.after(movement))
+                    .with_system(aim_missiles)
+                    .with_system(blink)
+                    .with_system(join.after(apply_input))
+                    .with_system(respawn)
+                    .with_system(lose_life.after(health::die)),
+            )
+            .add_system_to_stage(CoreStage::First, end_run)
+            .add_system(update_slot_text);
     }
 }
 
@@ -36,20 +58,241 @@ const SIZE_X: f32 = 0.15;
 const SIZE_Y: f32 = SIZE_X * HEIGHT / WIDTH;
 const SPEED: f32 = 1.0;
 const SLIDE_OUT_SLOWDOWN: f32 = 5.0;
-const BULLET_SPEED: f32 = 2.0;
 const SPEED_CHANGE: f32 = 4.5;
+const MISSILE_COLOR: Color = Color::rgb(1.0, 0.6, 0.2);
+// Enough to take down any ordinary enemy along with the player.
+const RAM_DAMAGE: f32 = 100.0;
+
+// The core sits in the cockpit.
+const CORE_OFFSET: Vec2 = Vec2::new(-0.02, 0.01);
+const CORE_Z: f32 = 2.0;
+const CORE_COLOR: Color = Color::rgb(1.0, 1.0, 1.0);
+const CORE_GLOW_COLOR: Color = Color::rgba(1.0, 0.3, 0.3, 0.5);
+const CORE_GLOW_SCALE: f32 = 2.5;
+
+pub const MAX_PLAYERS: usize = 2;
+const TINTS: [Color; MAX_PLAYERS] = [Color::WHITE, Color::rgb(0.55, 0.8, 1.0)];
+const START_Y: [f32; MAX_PLAYERS] = [0.5 + SIZE_Y / 2.0, 0.3];
+const RESPAWN_DELAY: f32 = 1.5;
+const RESPAWN_INVULNERABILITY: f32 = 2.5;
+const BLINK_RATE: f32 = 10.0;
+const SLOT_TEXT_LINE: f32 = 0.03;
+const GHOST_ALPHA: f32 = 0.35;
+
+// A gamepad with the same index as the player works too.
+struct Controls {
+    up: KeyCode,
+    down: KeyCode,
+    left: KeyCode,
+    right: KeyCode,
+    fire: KeyCode,
+    missile: KeyCode,
+    focus: KeyCode,
+    // How the fire key is shown in the join prompt.
+    fire_title: &'static str,
+}
+
+const CONTROLS: [Controls; MAX_PLAYERS] = [
+    Controls {
+        up: KeyCode::W,
+        down: KeyCode::S,
+        left: KeyCode::A,
+        right: KeyCode::D,
+        fire: KeyCode::M,
+        missile: KeyCode::N,
+        focus: KeyCode::LShift,
+        fire_title: "M",
+    },
+    Controls {
+        up: KeyCode::Up,
+        down: KeyCode::Down,
+        left: KeyCode::Left,
+        right: KeyCode::Right,
+        fire: KeyCode::RControl,
+        missile: KeyCode::RAlt,
+        focus: KeyCode::RShift,
+        fire_title: "RIGHT CTRL",
+    },
+];
+
+impl Controls {
+    fn fire_pressed(
+        &self,
+        gamepad: Gamepad,
+        kbd: &Input<KeyCode>,
+        buttons: &Input<GamepadButton>,
+    ) -> bool {
+        return kbd.pressed(self.fire)
+            || buttons.pressed(GamepadButton::new(gamepad, GamepadButtonType::South));
+    }
+
+    fn read(
+        &self,
+        gamepad: Gamepad,
+        kbd: &Input<KeyCode>,
+        buttons: &Input<GamepadButton>,
+        axes: &Axis<GamepadAxis>,
+    ) -> PlayerInput {
+        let button = |button_type| buttons.pressed(GamepadButton::new(gamepad, button_type));
+        let axis = |axis_type| {
+            axes.get(GamepadAxis::new(gamepad, axis_type))
+                .unwrap_or(0.0)
+        };
+
+        let mut movement = Vec2::new(
+            axis(GamepadAxisType::LeftStickX),
+            axis(GamepadAxisType::LeftStickY),
+        );
+        for (key, pad, dir) in [
+            (self.left, GamepadButtonType::DPadLeft, Vec2::NEG_X),
+            (self.right, GamepadButtonType::DPadRight, Vec2::X),
+            (self.up, GamepadButtonType::DPadUp, Vec2::Y),
+            (self.down, GamepadButtonType::DPadDown, Vec2::NEG_Y),
+        ] {
+            if kbd.pressed(key) || button(pad) {
+                movement += dir;
+            }
+        }
+
+        return PlayerInput {
+            movement: movement.clamp_length_max(1.0),
+            fire: self.fire_pressed(gamepad, kbd, buttons),
+            missile: kbd.pressed(self.missile) || button(GamepadButtonType::West),
+            focus: kbd.pressed(self.focus) || button(GamepadButtonType::RightTrigger),
+        };
+    }
+}
+
+// What the player wants the ship to do this frame.
+#[derive(Component, Clone, Default)]
+pub struct PlayerInput {
+    pub movement: Vec2,
+    pub fire: bool,
+    pub missile: bool,
+    pub focus: bool,
+}
+
+impl PlayerInput {
+    // Stick movement is reduced to eight directions,
+    // so that the same inputs can be sent, recorded and played back exactly.
+    pub fn pack(&self) -> NetInput {
+        let mut bits = 0;
+        for (on, bit) in [
+            (self.movement.y > 0.5, NetInput::UP),
+            (self.movement.y < -0.5, NetInput::DOWN),
+            (self.movement.x < -0.5, NetInput::LEFT),
+            (self.movement.x > 0.5, NetInput::RIGHT),
+            (self.fire, NetInput::FIRE),
+            (self.focus, NetInput::FOCUS),
+            (self.missile, NetInput::MISSILE),
+        ] {
+            if on {
+                bits |= bit;
+            }
+        }
+        return NetInput(bits);
+    }
+
+    pub fn unpack(input: NetInput) -> Self {
+        let mut movement = Vec2::ZERO;
+        for (bit, dir) in [
+            (NetInput::UP, Vec2::Y),
+            (NetInput::DOWN, Vec2::NEG_Y),
+            (NetInput::LEFT, Vec2::NEG_X),
+            (NetInput::RIGHT, Vec2::X),
+        ] {
+            if input.has(bit) {
+                movement += dir;
+            }
+        }
+        return Self {
+            movement: movement.clamp_length_max(1.0),
+            fire: input.has(NetInput::FIRE),
+            missile: input.has(NetInput::MISSILE),
+            focus: input.has(NetInput::FOCUS),
+        };
+    }
+}
+
+// The inputs of every slot for the current tick, whether anyone plays in it or not.
+// They come from the local controls unless something else replaces them
+// between `read_input` and `apply_input`.
+#[derive(Default, Clone)]
+pub struct TickInputs(pub [NetInput; MAX_PLAYERS]);
+
+#[derive(Clone)]
+pub struct Slot {
+    pub joined: bool,
+    pub lives: u32,
+    respawn: Option<Timer>,
+}
+
+impl Slot {
+    // Has a ship on the field or will have one soon.
+    pub fn is_playing(&self) -> bool {
+        return self.joined && self.lives > 0;
+    }
+
+    pub fn leave(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn respawn_elapsed(&self) -> f32 {
+        return self
+            .respawn
+            .as_ref()
+            .map_or(0.0, |timer| timer.elapsed_secs());
+    }
+}
+
+impl Default for Slot {
+    fn default() -> Self {
+        return Self {
+            joined: false,
+            lives: 0,
+            respawn: None,
+        };
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct Slots(pub [Slot; MAX_PLAYERS]);
+
+#[derive(Component)]
+pub struct SlotText(usize);
 
 const EXHAUST_WIDTH: f32 = 75.0;
 const EXHAUST_HEIGHT: f32 = 25.0;
 const EXHAUST_SIZE_X: f32 = 0.1;
 const EXHAUST_SIZE_Y: f32 = EXHAUST_SIZE_X * EXHAUST_HEIGHT / EXHAUST_WIDTH;
 
-#[derive(Component)]
+pub struct PlayerConfig {
+    // The only part of the ship that can be hit.
+    pub core_radius: f32,
+    // Speed multiplier while focusing.
+    pub focus_speed: f32,
+    pub lives: u32,
+}
+
+impl Default for PlayerConfig {
+    fn default() -> Self {
+        return Self {
+            core_radius: 0.008,
+            focus_speed: 0.5,
+            lives: 3,
+        };
+    }
+}
+
+#[derive(Component, Clone)]
 #[cfg_attr(feature = "inspector", derive(Inspectable))]
 pub struct Player {
+    index: usize,
     cur_speed_vec: Vec2,
+    velocity: Vec2,
     heat: f32,
     heat_recovery: f32,
+    focused: bool,
 }
 
 impl Player {
@@ -58,19 +301,98 @@ impl Player {
     const MAX_HEAT_RECOVERY: f32 = 0.5;
     const HEAT_RECOVERY_INCREASE: f32 = 0.1;
 
-    fn new() -> Self {
+    fn new(index: usize) -> Self {
         return Self {
+            index,
             cur_speed_vec: Vec2::ZERO,
+            velocity: Vec2::ZERO,
             heat: 0.0,
             heat_recovery: 0.0,
+            focused: false,
         };
     }
 
+    pub fn index(&self) -> usize {
+        return self.index;
+    }
+
+    pub fn velocity(&self) -> Vec2 {
+        return self.velocity;
+    }
+
+    pub fn heat(&self) -> f32 {
+        return self.heat;
+    }
+
     fn increase_heat(&mut self) {
         self.heat += Self::BULLET_HEAT;
         self.heat_recovery = Self::MIN_HEAT_RECOVERY;
     }
 
+    pub fn relieve_heat(&mut self, amount: f32) {
+        self.heat = (self.heat - amount).max(0.0);
+    }
+
+    fn steer(
+        &mut self,
+        transform: &mut Transform,
+        input: &PlayerInput,
+        delta: f32,
+        bounds: &UiRect<f32>,
+        config: &PlayerConfig,
+    ) {
+        self.focused = input.focus;
+        let speed = if self.focused {
+            SPEED * config.focus_speed
+        } else {
+            SPEED
+        };
+
+        let target_speed_vector = input.movement;
+        let speed_change = target_speed_vector - self.cur_speed_vec;
+        let speed_change_norm = speed_change.normalize_or_zero();
+        if speed_change_norm == Vec2::ZERO {
+            self.cur_speed_vec = target_speed_vector;
+        } else {
+            let speed_change_dist = speed_change.length();
+            let frame_speed_change = SPEED_CHANGE * delta;
+            if speed_change_dist < frame_speed_change {
+                self.cur_speed_vec = target_speed_vector;
+            } else {
+                self.cur_speed_vec += speed_change_norm * frame_speed_change;
+            }
+        }
+
+        self.velocity = Vec2::ZERO;
+        if self.cur_speed_vec != Vec2::ZERO {
+            let old_pos = Vec2::new(transform.translation.x, transform.translation.y);
+            let pos_delta = speed * delta * self.cur_speed_vec;
+            let mut next_pos = old_pos + pos_delta;
+
+            if next_pos.x < bounds.left {
+                next_pos.x = bounds.left;
+                self.cur_speed_vec.x = 0.0;
+            } else if next_pos.x > bounds.right {
+                next_pos.x = bounds.right;
+                self.cur_speed_vec.x = 0.0;
+            }
+
+            if next_pos.y < bounds.bottom {
+                next_pos.y = bounds.bottom;
+                self.cur_speed_vec.y = 0.0;
+            } else if next_pos.y > bounds.top {
+                next_pos.y = bounds.top;
+                self.cur_speed_vec.y = 0.0;
+            }
+
+            transform.translation = next_pos.extend(transform.translation.z);
+            // A paused tick has no velocity to speak of.
+            if delta > 0.0 {
+                self.velocity = (next_pos - old_pos) / delta;
+            }
+        }
+    }
+
     fn cooldown(&mut self, delta: f32) {
         self.heat_recovery = (self.heat_recovery + Self::HEAT_RECOVERY_INCREASE * delta)
             .min(Self::MAX_HEAT_RECOVERY);
@@ -78,31 +400,222 @@ impl Player {
     }
 }
 
-#[derive(Component)]
-pub struct PlayerBullet;
+// How the shots of a single volley are laid out.
+#[derive(Clone, Copy)]
+struct Volley {
+    shots: usize,
+    // The angle between neighbouring shots.
+    spread: f32,
+}
+
+impl Volley {
+    const SINGLE: Self = Self {
+        shots: 1,
+        spread: 0.0,
+    };
+
+    // The direction of each shot.
+    fn shots(&self) -> impl Iterator<Item = Vec2> + '_ {
+        let middle = (self.shots - 1) as f32 / 2.0;
+        return (0..self.shots)
+            .map(move |index| Vec2::from_angle(self.spread * (index as f32 - middle)));
+    }
+}
+
+#[derive(Component, Clone)]
+pub struct Weapon {
+    period: f32,
+    speed: f32,
+    damage: Damage,
+    motion: Option<BulletMotion>,
+    volley: Volley,
+    // Narrower and concentrated on what is right ahead.
+    focused_volley: Volley,
+}
+
+impl Weapon {
+    const BLASTER: Self = Self {
+        period: 0.1,
+        speed: 2.0,
+        damage: Damage::new(1.0, DamageKind::Kinetic),
+        motion: None,
+        volley: Volley {
+            shots: 3,
+            spread: 0.12,
+        },
+        // As many shots as the normal fire, so focusing does not add damage.
+        focused_volley: Volley {
+            shots: 3,
+            spread: 0.03,
+        },
+    };
+
+    const MISSILE: Self = Self {
+        period: 0.8,
+        speed: 0.5,
+        damage: Damage {
+            amount: 2.0,
+            kind: DamageKind::Explosive,
+            over_time: Some(OverTime {
+                per_second: 0.5,
+                duration: 2.0,
+            }),
+        },
+        motion: Some(BulletMotion {
+            acceleration: 3.0,
+            min_speed: 0.0,
+            max_speed: 1.8,
+            angular_velocity: 0.0,
+            turn_rate: 4.0,
+            lifetime: 3.0,
+        }),
+        volley: Volley::SINGLE,
+        focused_volley: Volley::SINGLE,
+    };
+
+    // Returns the number of shots fired.
+    fn fire(
+        &self,
+        commands: &mut Commands,
+        pools: &mut Pools,
+        bullet_res: &BulletRes,
+        starting_point: Vec3,
+        color: Color,
+        player: &Player,
+    ) -> usize {
+        let volley = if player.focused {
+            &self.focused_volley
+        } else {
+            &self.volley
+        };
+        for dir in volley.shots() {
+            let entity = Bullet::spawn_silent(
+                commands,
+                pools,
+                &bullet_res.player,
+                starting_point,
+                starting_point + dir.extend(0.0),
+                self.speed,
+                color,
+            );
+            commands
+                .entity(entity)
+                .insert(PlayerBullet {
+                    player: player.index,
+                })
+                .insert(self.damage)
+                .insert(CollisionLayers::new(
+                    Layers::PLAYER_BULLET,
+                    Layers::ENEMY | Layers::ENEMY_BULLET,
+                ))
+                .insert(Name::new("PlayerBullet"));
+
+            if let Some(motion) = self.motion {
+                commands
+                    .entity(entity)
+                    .insert(motion)
+                    .insert(HomingTarget(None));
+            }
+        }
+        return volley.shots;
+    }
+}
+
+#[derive(Component, Clone)]
+pub struct MissileLauncher {
+    timer: BulletTimer,
+}
+
+impl MissileLauncher {
+    pub fn elapsed(&self) -> f32 {
+        return self.timer.elapsed();
+    }
+}
+
+#[derive(Component, Clone)]
+pub struct PlayerBullet {
+    // The index of the player who fired it.
+    pub player: usize,
+}
+
+// Shown while focusing.
+#[derive(Component, Clone)]
+pub struct CoreMarker;
 
 pub struct PlayerGraphics {
     ship_atlas: Handle<TextureAtlas>,
     exhaust_atlas: Handle<TextureAtlas>,
 }
 
-fn spawn(mut commands: Commands, player_graphics: Res<PlayerGraphics>) {
+fn setup(mut commands: Commands, font: Res<MainFont>, win: Res<WindowDescriptor>) {
+    for index in 0..MAX_PLAYERS {
+        let pos = Vec3::new(win.max_x() - 0.01, 1.0 - SLOT_TEXT_LINE * index as f32, 0.1);
+        let mut text = AnimPlugin::text_bundle(&font.0, "", 25.0, pos);
+        text.text.alignment.horizontal = HorizontalAlign::Right;
+        text.text.alignment.vertical = VerticalAlign::Top;
+        commands
+            .spawn_bundle(text)
+            .insert(SlotText(index))
+            .insert(Name::new(format!("Slot{}", index + 1)));
+    }
+}
+
+// A new game always starts with a single player.
+pub fn reset_slots(mut slots: ResMut<Slots>) {
+    *slots = Slots::default();
+    slots.0[0].joined = true;
+}
+
+fn spawn(
+    mut commands: Commands,
+    player_graphics: Res<PlayerGraphics>,
+    config: Res<PlayerConfig>,
+    mut slots: ResMut<Slots>,
+) {
+    for (index, slot) in slots.0.iter_mut().enumerate() {
+        if !slot.joined {
+            continue;
+        }
+        slot.lives = config.lives;
+        slot.respawn = None;
+        spawn_ship(&mut commands, &player_graphics, &config, index, -SIZE_X);
+    }
+}
+
+fn spawn_ship(
+    commands: &mut Commands,
+    player_graphics: &PlayerGraphics,
+    config: &PlayerConfig,
+    index: usize,
+    x: f32,
+) -> Entity {
     let mut sprite = TextureAtlasSprite::new(0);
     sprite.custom_size = Some(Vec2::new(SIZE_X, SIZE_Y));
+    sprite.color = TINTS[index];
     let player = commands
         .spawn_bundle(SpriteSheetBundle {
             sprite,
             texture_atlas: player_graphics.ship_atlas.clone(),
             transform: Transform {
-                translation: Vec3::new(-SIZE_X, 0.5 + SIZE_Y / 2.0, 200.0),
+                translation: Vec3::new(x, START_Y[index], 200.0),
                 ..default()
             },
             ..default()
         })
-        .insert(Player::new())
-        .insert(BulletTimer::new(0.1))
-        .insert(HitBox(Vec2::new(SIZE_X * 0.9, SIZE_Y * 0.9)))
-        .insert(Name::new("Player"))
+        .insert(Player::new(index))
+        .insert(PlayerInput::default())
+        .insert(BulletTimer::new(Weapon::BLASTER.period))
+        .insert(Weapon::BLASTER)
+        .insert(MissileLauncher {
+            timer: BulletTimer::new(Weapon::MISSILE.period),
+        })
+        .insert(Health::new(1.0))
+        .insert(Damage::new(RAM_DAMAGE, DamageKind::Ram))
+        .insert(CollisionLayers::new(
+            Layers::PLAYER,
+            Layers::ENEMY | Layers::ENEMY_BULLET | Layers::PICKUP,
+        ))
+        .insert(Name::new(format!("Player{}", index + 1)))
         .id();
 
     let mut sprite = TextureAtlasSprite::new(0);
@@ -122,6 +635,64 @@ fn spawn(mut commands: Commands, player_graphics: Res<PlayerGraphics>) {
         .id();
 
     commands.entity(player).add_child(exhaust);
+
+    let diameter = config.core_radius * 2.0;
+    let core = commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: CORE_COLOR,
+                custom_size: Some(Vec2::splat(diameter)),
+                ..default()
+            },
+            transform: Transform {
+                translation: CORE_OFFSET.extend(CORE_Z + 0.1),
+                rotation: Quat::from_rotation_z(FRAC_PI_4),
+                ..default()
+            },
+            visibility: Visibility { is_visible: false },
+            ..default()
+        })
+        .insert(Collider(Shape::Circle {
+            radius: config.core_radius,
+        }))
+        .insert(CoreMarker)
+        .insert(Name::new("PlayerCore"))
+        .id();
+    let glow = commands
+        .spawn_bundle(SpriteBundle {
+            sprite: Sprite {
+                color: CORE_GLOW_COLOR,
+                custom_size: Some(Vec2::splat(diameter * CORE_GLOW_SCALE)),
+                ..default()
+            },
+            transform: Transform {
+                translation: CORE_OFFSET.extend(CORE_Z),
+                rotation: Quat::from_rotation_z(FRAC_PI_4),
+                ..default()
+            },
+            visibility: Visibility { is_visible: false },
+            ..default()
+        })
+        .insert(CoreMarker)
+        .insert(Name::new("PlayerCoreGlow"))
+        .id();
+    commands.entity(player).push_children(&[core, glow]);
+    return player;
+}
+
+// A ship that is only there to be looked at.
+pub fn ghost_bundle(player_graphics: &PlayerGraphics, index: usize) -> SpriteSheetBundle {
+    let mut sprite = TextureAtlasSprite::new(0);
+    sprite.custom_size = Some(Vec2::new(SIZE_X, SIZE_Y));
+    sprite.color = TINTS[index];
+    sprite.color.set_a(GHOST_ALPHA);
+    return SpriteSheetBundle {
+        sprite,
+        texture_atlas: player_graphics.ship_atlas.clone(),
+        transform: Transform::from_xyz(0.0, 0.0, 199.0),
+        visibility: Visibility { is_visible: false },
+        ..default()
+    };
 }
 
 fn load_assets(
@@ -155,194 +726,244 @@ fn load_assets(
     });
 }
 
-fn movement(
-    mut q: Query<(&mut Transform, &mut Player)>,
+// What the keys and the gamepad of the given player are asking for.
+pub fn read_controls(
+    index: usize,
+    kbd: &Input<KeyCode>,
+    buttons: &Input<GamepadButton>,
+    axes: &Axis<GamepadAxis>,
+) -> PlayerInput {
+    return CONTROLS[index].read(Gamepad::new(index), kbd, buttons, axes);
+}
+
+pub fn read_input(
+    mut inputs: ResMut<TickInputs>,
     kbd: Res<Input<KeyCode>>,
-    time: Res<Time>,
-    win: Res<WindowDescriptor>,
+    buttons: Res<Input<GamepadButton>>,
+    axes: Res<Axis<GamepadAxis>>,
 ) {
-    let bounds = win.bounds_box_inside(Vec2::new(SIZE_X, SIZE_Y));
-
-    let speed = if kbd.pressed(KeyCode::LShift) {
-        SPEED / 2.0
-    } else {
-        SPEED
-    };
-
-    let mut target_speed_vector = Vec2::ZERO;
-
-    for (mut transform, mut player) in &mut q {
-        if kbd.pressed(KeyCode::A) {
-            target_speed_vector -= Vec2::X;
-        }
-        if kbd.pressed(KeyCode::D) {
-            target_speed_vector += Vec2::X;
-        }
-        if kbd.pressed(KeyCode::W) {
-            target_speed_vector += Vec2::Y;
-        }
-        if kbd.pressed(KeyCode::S) {
-            target_speed_vector -= Vec2::Y;
-        }
-
-        target_speed_vector = target_speed_vector.normalize_or_zero();
-        let speed_change = target_speed_vector - player.cur_speed_vec;
-        let speed_change_norm = speed_change.normalize_or_zero();
-        if speed_change_norm == Vec2::ZERO {
-            player.cur_speed_vec = target_speed_vector;
-        } else {
-            let speed_change_dist = speed_change.length();
-            let frame_speed_change = SPEED_CHANGE * time.delta_seconds();
-            if speed_change_dist < frame_speed_change {
-                player.cur_speed_vec = target_speed_vector;
-            } else {
-                player.cur_speed_vec += speed_change_norm * frame_speed_change;
-            }
-        }
-
-        if player.cur_speed_vec != Vec2::ZERO {
-            let old_pos = Vec2::new(transform.translation.x, transform.translation.y);
-            let pos_delta = speed * time.delta_seconds() * player.cur_speed_vec;
-            let mut next_pos = old_pos + pos_delta;
+    for (index, input) in inputs.0.iter_mut().enumerate() {
+        *input = read_controls(index, &kbd, &buttons, &axes).pack();
+    }
+}
 
-            if next_pos.x < bounds.left {
-                next_pos.x = bounds.left;
-                player.cur_speed_vec.x = 0.0;
-            } else if next_pos.x > bounds.right {
-                next_pos.x = bounds.right;
-                player.cur_speed_vec.x = 0.0;
-            }
+pub fn apply_input(mut q: Query<(&Player, &mut PlayerInput)>, inputs: Res<TickInputs>) {
+    for (player, mut input) in &mut q {
+        *input = PlayerInput::unpack(inputs.0[player.index]);
+    }
+}
 
-            if next_pos.y < bounds.bottom {
-                next_pos.y = bounds.bottom;
-                player.cur_speed_vec.y = 0.0;
-            } else if next_pos.y > bounds.top {
-                next_pos.y = bounds.top;
-                player.cur_speed_vec.y = 0.0;
-            }
+pub fn movement_bounds(win: &WindowDescriptor) -> UiRect<f32> {
+    return win.bounds_box_inside(Vec2::new(SIZE_X, SIZE_Y));
+}
 
-            transform.translation = next_pos.extend(transform.translation.z);
-        }
+pub fn movement(
+    mut q: Query<(&mut Transform, &mut Player, &PlayerInput)>,
+    time: Res<Time>,
+    win: Res<WindowDescriptor>,
+    config: Res<PlayerConfig>,
+) {
+    let bounds = movement_bounds(&win);
+    for (mut transform, mut player, input) in &mut q {
+        player.steer(
+            &mut transform,
+            input,
+            time.delta_seconds(),
+            &bounds,
+            &config,
+        );
     }
 }
 
-fn attack(
+pub fn attack(
     mut commands: Commands,
-    mut q: Query<(&Transform, &mut BulletTimer, &mut Player)>,
-    kbd: Res<Input<KeyCode>>,
+    mut q: Query<(
+        &Transform,
+        &mut BulletTimer,
+        &mut Player,
+        &Weapon,
+        &mut MissileLauncher,
+        &PlayerInput,
+    )>,
     bullet_res: Res<BulletRes>,
+    mut pools: ResMut<Pools>,
     audio: Res<Audio>,
     time: Res<Time>,
+    mut rank_events: EventWriter<RankEvent>,
 ) {
-    for (transform, mut bullet_timer, mut player) in &mut q {
+    for (transform, mut bullet_timer, mut player, weapon, mut launcher, input) in &mut q {
         bullet_timer.process(time.delta());
+        launcher.timer.process(time.delta());
 
-        if kbd.pressed(KeyCode::M) && bullet_timer.can_shoot && player.heat < 1.0 {
+        if input.missile && launcher.timer.can_shoot {
+            let starting_point = transform.translation + Vec3::new(0.0, -0.04, 1.0);
+            let shots = Weapon::MISSILE.fire(
+                &mut commands,
+                &mut pools,
+                &bullet_res,
+                starting_point,
+                MISSILE_COLOR,
+                &player,
+            );
+            Bullet::play_sound(&bullet_res.player, &audio);
+            launcher.timer.shoot();
+            for _ in 0..shots {
+                rank_events.send(RankEvent::Shot);
+            }
+        }
+
+        if input.fire && bullet_timer.can_shoot && player.heat < 1.0 {
             let starting_point = transform.translation + Vec3::new(0.03, -0.025, 1.0);
             let mut color = Color::WHITE;
             color.set_b(1.0 - player.heat);
-            let entity = Bullet::spawn(
+            let shots = weapon.fire(
                 &mut commands,
-                &bullet_res.player,
+                &mut pools,
+                &bullet_res,
                 starting_point,
-                starting_point + Vec3::X,
-                BULLET_SPEED,
                 color,
-                &audio,
+                &player,
             );
-            commands
-                .entity(entity)
-                .insert(PlayerBullet)
-                .insert(Name::new("PlayerBullet"));
+            Bullet::play_sound(&bullet_res.player, &audio);
             bullet_timer.shoot();
             player.increase_heat();
+            for _ in 0..shots {
+                rank_events.send(RankEvent::Shot);
+            }
         } else {
             player.cooldown(time.delta_seconds());
         }
     }
 }
 
-fn collision_with_enemy(
-    mut commands: Commands,
-    player_query: Query<(&Transform, &HitBox, Entity), With<Player>>,
-    enemy_query: Query<(&Transform, &HitBox, Entity), With<Enemy>>,
-    explosion_sheet: Res<ExplosionSheet>,
-    mut game_state: ResMut<State<GameState>>,
-    explosion_audio: Res<ExplosionAudio>,
-    audio: Res<Audio>,
+fn show_core(
+    players_q: Query<(&Player, &Children)>,
+    mut q: Query<&mut Visibility, With<CoreMarker>>,
 ) {
-    for (player_pos, player_box, player) in &player_query {
-        for (enemy_pos, enemy_box, enemy) in &enemy_query {
-            if collide(
-                player_pos.translation,
-                player_box.0,
-                enemy_pos.translation,
-                enemy_box.0,
-            )
-            .is_some()
-            {
-                commands.entity(player).despawn_recursive();
-                commands.entity(enemy).despawn_recursive();
-
-                Explosion::spawn(
-                    &mut commands,
-                    &explosion_sheet,
-                    player_pos.translation,
-                    0.05,
-                    &explosion_audio,
-                    &audio,
-                );
-                Explosion::spawn(
-                    &mut commands,
-                    &explosion_sheet,
-                    enemy_pos.translation,
-                    0.05,
-                    &explosion_audio,
-                    &audio,
-                );
-
-                game_state.set(GameState::GameOver).unwrap();
-                return;
+    for (player, children) in &players_q {
+        for child in children {
+            if let Ok(mut visibility) = q.get_mut(*child) {
+                visibility.is_visible = player.focused;
             }
         }
     }
 }
 
-fn collision_with_bullet(
+// The ship blinks while it cannot be hit.
+fn blink(mut q: Query<(&Health, &mut TextureAtlasSprite), With<Player>>, time: Res<Time>) {
+    let visible = ((time.seconds_since_startup() as f32 * BLINK_RATE) as u32).is_multiple_of(2);
+    for (health, mut sprite) in &mut q {
+        let alpha = if health.is_invulnerable() && !visible {
+            0.3
+        } else {
+            1.0
+        };
+        sprite.color.set_a(alpha);
+    }
+}
+
+// What missiles can home in on.
+type MissileTargets = (With<Collider>, Or<(With<Enemy>, With<BossPart>)>);
+
+fn aim_missiles(
+    mut missiles: Query<(&Transform, &mut HomingTarget), With<PlayerBullet>>,
+    targets: Query<(Entity, &GlobalTransform), MissileTargets>,
+) {
+    for (transform, mut homing) in &mut missiles {
+        if homing.0.is_some_and(|target| targets.contains(target)) {
+            continue;
+        }
+
+        let pos = transform.translation.truncate();
+        homing.0 = targets
+            .iter()
+            .map(|(entity, target)| (entity, target.translation().truncate()))
+            .filter(|(_, target)| target.x > pos.x)
+            .min_by(|(_, a), (_, b)| a.distance_squared(pos).total_cmp(&b.distance_squared(pos)))
+            .map(|(entity, _)| entity);
+    }
+}
+
+fn lose_life(
+    mut events: EventReader<Died>,
+    player_query: Query<&Player>,
+    mut slots: ResMut<Slots>,
+) {
+    for event in events.iter() {
+        let player = match player_query.get(event.entity) {
+            Ok(player) => player,
+            Err(_) => continue,
+        };
+        let slot = &mut slots.0[player.index];
+        slot.lives = slot.lives.saturating_sub(1);
+        if slot.lives > 0 {
+            slot.respawn = Some(Timer::from_seconds(RESPAWN_DELAY, false));
+        }
+    }
+}
+
+// The game is over when nobody has any lives left.
+// That is only looked at before the next tick, when netplay already knows
+// whether the tick before was simulated with the right inputs.
+fn end_run(slots: Res<Slots>, mut state: ResMut<State<GameState>>) {
+    if *state.current() == GameState::Game && !slots.0.iter().any(|slot| slot.is_playing()) {
+        state.set(GameState::GameOver).unwrap();
+    }
+}
+
+fn respawn(
     mut commands: Commands,
-    player_query: Query<(Entity, &Transform, &HitBox), With<Player>>,
-    enemy_bullet_query: Query<(Entity, &Transform, &HitBox), With<EnemyBullet>>,
-    explosion_sheet: Res<ExplosionSheet>,
-    explosion_audio: Res<ExplosionAudio>,
-    audio: Res<Audio>,
-    mut game_state: ResMut<State<GameState>>,
+    mut slots: ResMut<Slots>,
+    player_graphics: Res<PlayerGraphics>,
+    config: Res<PlayerConfig>,
+    time: Res<Time>,
 ) {
-    for (player, player_pos, enemy_hitbox) in &player_query {
-        for (bullet, bullet_pos, bullet_hitbox) in &enemy_bullet_query {
-            if collide(
-                player_pos.translation,
-                enemy_hitbox.0,
-                bullet_pos.translation,
-                bullet_hitbox.0,
-            )
-            .is_some()
-            {
-                commands.entity(player).despawn_recursive();
-                commands.entity(bullet).despawn_recursive();
-
-                Explosion::spawn(
-                    &mut commands,
-                    &explosion_sheet,
-                    player_pos.translation,
-                    0.05,
-                    &explosion_audio,
-                    &audio,
-                );
-
-                game_state.set(GameState::GameOver).unwrap();
-                break;
-            }
+    for (index, slot) in slots.0.iter_mut().enumerate() {
+        let timer = match &mut slot.respawn {
+            Some(timer) => timer,
+            None => continue,
+        };
+        if !timer.tick(time.delta()).finished() {
+            continue;
         }
+        slot.respawn = None;
+        let player = spawn_ship(
+            &mut commands,
+            &player_graphics,
+            &config,
+            index,
+            SIZE_X / 2.0,
+        );
+        let mut health = Health::new(1.0);
+        health.make_invulnerable(RESPAWN_INVULNERABILITY);
+        commands.entity(player).insert(health);
+    }
+}
+
+// Players who are not in the game yet can join any time by pressing fire.
+fn join(
+    mut commands: Commands,
+    mut slots: ResMut<Slots>,
+    inputs: Res<TickInputs>,
+    player_graphics: Res<PlayerGraphics>,
+    config: Res<PlayerConfig>,
+) {
+    for (index, slot) in slots.0.iter_mut().enumerate() {
+        if slot.joined || !inputs.0[index].has(NetInput::FIRE) {
+            continue;
+        }
+        slot.joined = true;
+        slot.lives = config.lives;
+        let player = spawn_ship(
+            &mut commands,
+            &player_graphics,
+            &config,
+            index,
+            SIZE_X / 2.0,
+        );
+        let mut health = Health::new(1.0);
+        health.make_invulnerable(RESPAWN_INVULNERABILITY);
+        commands.entity(player).insert(health);
     }
 }
 
@@ -351,10 +972,50 @@ fn slide_out(
     time: Res<Time>,
     mut state: ResMut<State<GameState>>,
 ) {
-    let mut player_pos = q.single_mut();
-    player_pos.translation.x += SPEED * time.delta_seconds() / SLIDE_OUT_SLOWDOWN;
+    let mut done = true;
+    for mut transform in &mut q {
+        transform.translation.x += SPEED * time.delta_seconds() / SLIDE_OUT_SLOWDOWN;
+        done &= transform.translation.x > SIZE_X / 2.0;
+    }
 
-    if player_pos.translation.x > SIZE_X / 2.0 {
+    if done {
         state.set(GameState::Game).unwrap();
     }
 }
+
+fn show_slot_text(mut q: Query<&mut Visibility, With<SlotText>>) {
+    for mut visibility in &mut q {
+        visibility.is_visible = true;
+    }
+}
+
+fn hide_slot_text(mut q: Query<&mut Visibility, With<SlotText>>) {
+    for mut visibility in &mut q {
+        visibility.is_visible = false;
+    }
+}
+
+fn update_slot_text(mut q: Query<(&mut Text, &SlotText)>, slots: Res<Slots>, score: Res<Score>) {
+    for (mut text, slot_text) in &mut q {
+        let index = slot_text.0;
+        let slot = &slots.0[index];
+        let value = if slot.joined {
+            format!(
+                "{}P SCORE: {} LIVES: {}",
+                index + 1,
+                score.players[index],
+                slot.lives
+            )
+        } else {
+            format!(
+                "{}P PRESS {} TO JOIN",
+                index + 1,
+                CONTROLS[index].fire_title
+            )
+        };
+        let section = text.sections.first_mut().unwrap();
+        if section.value != value {
+            section.value = value;
+        }
+    }
+}
@@ -0,0 +1,169 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+// Short-lived entities are hidden and kept for reuse instead of being despawned.
+// A released entity loses the components that make the game treat it as alive,
+// and gets them back along with a fresh sprite when it is acquired again.
+
+use crate::anim::AnimationTimer;
+use crate::bullet::{Bullet, BulletMotion, HomingTarget};
+use crate::collision::{CollisionLayers, DestroyOutsideScreen};
+use crate::enemy::EnemyBullet;
+use crate::explosion::Explosion;
+use crate::health::Damage;
+use crate::player::PlayerBullet;
+use bevy::ecs::system::{Command, EntityCommands};
+use bevy::ecs::world::EntityMut;
+use bevy::hierarchy::despawn_with_children_recursive;
+use bevy::prelude::*;
+
+pub struct PoolPlugin;
+
+impl Plugin for PoolPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Pools::default());
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolKind {
+    PlayerBullet,
+    EnemyBullet,
+    Explosion,
+    Spark,
+}
+
+impl PoolKind {
+    pub const ALL: [Self; 4] = [
+        Self::PlayerBullet,
+        Self::EnemyBullet,
+        Self::Explosion,
+        Self::Spark,
+    ];
+
+    pub fn title(&self) -> &'static str {
+        return match self {
+            Self::PlayerBullet => "PLAYER BULLETS",
+            Self::EnemyBullet => "ENEMY BULLETS",
+            Self::Explosion => "EXPLOSIONS",
+            Self::Spark => "SPARKS",
+        };
+    }
+
+    fn release(&self, entity: &mut EntityMut) {
+        match self {
+            Self::PlayerBullet | Self::EnemyBullet => {
+                entity.remove_bundle_intersection::<(
+                    Bullet,
+                    PlayerBullet,
+                    EnemyBullet,
+                    Damage,
+                    CollisionLayers,
+                    DestroyOutsideScreen,
+                    BulletMotion,
+                    HomingTarget,
+                )>();
+            }
+            Self::Explosion | Self::Spark => {
+                entity.remove_bundle_intersection::<(Explosion, AnimationTimer)>();
+            }
+        }
+    }
+}
+
+#[derive(Component, Clone)]
+pub struct Pooled {
+    pub kind: PoolKind,
+    in_use: bool,
+}
+
+impl Pooled {
+    pub fn new(kind: PoolKind) -> Self {
+        return Self { kind, in_use: true };
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct Pool {
+    free: Vec<Entity>,
+    pub reused: u32,
+    pub spawned: u32,
+}
+
+impl Pool {
+    pub fn free(&self) -> usize {
+        return self.free.len();
+    }
+
+    pub fn hit_rate(&self) -> f32 {
+        let total = self.reused + self.spawned;
+        if total == 0 {
+            return 0.0;
+        }
+        return self.reused as f32 / total as f32;
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct Pools([Pool; PoolKind::ALL.len()]);
+
+impl Pools {
+    pub fn get(&self, kind: PoolKind) -> &Pool {
+        return &self.0[kind as usize];
+    }
+
+    // The caller is expected to insert the whole bundle again,
+    // along with `Pooled`, since a reused entity keeps its old values.
+    pub fn acquire(&mut self, commands: &mut Commands, kind: PoolKind) -> Entity {
+        let pool = &mut self.0[kind as usize];
+        if let Some(entity) = pool.free.pop() {
+            pool.reused += 1;
+            return entity;
+        }
+        pool.spawned += 1;
+        return commands.spawn().id();
+    }
+}
+
+struct Release(Entity);
+
+impl Command for Release {
+    fn write(self, world: &mut World) {
+        let mut entity = match world.get_entity_mut(self.0) {
+            Some(entity) => entity,
+            None => return,
+        };
+        let kind = match entity.get_mut::<Pooled>() {
+            // Released more than once in the same frame.
+            Some(pooled) if !pooled.in_use => return,
+            Some(mut pooled) => {
+                pooled.in_use = false;
+                pooled.kind
+            }
+            None => {
+                despawn_with_children_recursive(world, self.0);
+                return;
+            }
+        };
+
+        kind.release(&mut entity);
+        if let Some(mut visibility) = entity.get_mut::<Visibility>() {
+            visibility.is_visible = false;
+        }
+        world.resource_mut::<Pools>().0[kind as usize]
+            .free
+            .push(self.0);
+    }
+}
+
+pub trait ReleaseExt {
+    // Puts a pooled entity back into its pool, or despawns any other entity.
+    fn release(&mut self);
+}
+
+impl<'w, 's, 'a> ReleaseExt for EntityCommands<'w, 's, 'a> {
+    fn release(&mut self) {
+        let entity = self.id();
+        self.commands().add(Release(entity));
+    }
+}
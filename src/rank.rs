@@ -0,0 +1,226 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+use crate::health::{self, Damaged};
+use crate::player::{self, PlayerBullet};
+use crate::score;
+use crate::state::GameState;
+use bevy::prelude::*;
+
+pub struct RankPlugin;
+
+impl Plugin for RankPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<RankEvent>()
+            .insert_resource(Rank::default())
+            .add_system_set(SystemSet::on_enter(GameState::TitleFlyOut).with_system(reset))
+            .add_system_set(SystemSet::on_enter(GameState::GameOver).with_system(record_death))
+            .add_system_set(SystemSet::on_update(GameState::Game).with_system(survive))
+            .add_system(count_hits.after(health::take_hits))
+            .add_system(
+                apply_events
+                    .after(count_hits)
+                    .after(player::attack)
+                    .after(score::award_points),
+            );
+    }
+}
+
+// Rank gained per second just for staying alive.
+const SURVIVAL_GAIN: f32 = 1.0 / 180.0;
+// Extra rank per second for every point of accuracy above par.
+const ACCURACY_GAIN: f32 = 1.0 / 60.0;
+const ACCURACY_PAR: f32 = 0.3;
+// Accuracy is not trusted until this many recent shots.
+const MIN_SHOTS: f32 = 5.0;
+// Older shots and hits count less and less.
+const STATS_HALF_LIFE: f32 = 10.0;
+const KILL_GAIN: f32 = 0.01;
+// Share of the rank kept after a death or a continue.
+const DEATH_KEEP: f32 = 0.6;
+const CONTINUE_KEEP: f32 = 0.75;
+const MAX_RANK: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RankEvent {
+    Shot,
+    Hit,
+    Kill,
+    Death,
+    Continue,
+}
+
+// A hidden measure of how well the player is doing.
+// It knows nothing about the ECS, so it can be driven by made-up events.
+#[derive(Default, Debug, Clone)]
+pub struct Rank {
+    value: f32,
+    shots: f32,
+    hits: f32,
+}
+
+impl Rank {
+    pub fn value(&self) -> f32 {
+        return self.value;
+    }
+
+    pub fn accuracy(&self) -> f32 {
+        if self.shots < MIN_SHOTS {
+            return ACCURACY_PAR;
+        }
+        return (self.hits / self.shots).min(1.0);
+    }
+
+    pub fn apply(&mut self, event: RankEvent) {
+        match event {
+            RankEvent::Shot => self.shots += 1.0,
+            RankEvent::Hit => self.hits += 1.0,
+            RankEvent::Kill => self.add(KILL_GAIN),
+            RankEvent::Death => self.value *= DEATH_KEEP,
+            RankEvent::Continue => self.value *= CONTINUE_KEEP,
+        }
+    }
+
+    pub fn update(&mut self, delta: f32) {
+        let decay = 0.5_f32.powf(delta / STATS_HALF_LIFE);
+        self.shots *= decay;
+        self.hits *= decay;
+
+        let skill = (self.accuracy() - ACCURACY_PAR) * ACCURACY_GAIN;
+        self.add((SURVIVAL_GAIN + skill) * delta);
+    }
+
+    fn add(&mut self, amount: f32) {
+        self.value = (self.value + amount).clamp(0.0, MAX_RANK);
+    }
+}
+
+fn reset(mut rank: ResMut<Rank>) {
+    *rank = Rank::default();
+}
+
+fn apply_events(mut rank: ResMut<Rank>, mut events: EventReader<RankEvent>) {
+    for event in events.iter() {
+        rank.apply(*event);
+    }
+}
+
+fn count_hits(
+    mut events: EventReader<Damaged>,
+    bullets_q: Query<(), With<PlayerBullet>>,
+    mut rank_events: EventWriter<RankEvent>,
+) {
+    for damaged in events.iter() {
+        if bullets_q.contains(damaged.source) {
+            rank_events.send(RankEvent::Hit);
+        }
+    }
+}
+
+fn survive(mut rank: ResMut<Rank>, time: Res<Time>) {
+    rank.update(time.delta_seconds());
+}
+
+fn record_death(mut rank: ResMut<Rank>) {
+    rank.apply(RankEvent::Death);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STEP: f32 = 1.0 / 60.0;
+
+    // Plays `seconds` of a run, firing a shot every `shot_every` steps
+    // and landing `hits` of every 10 shots.
+    fn play(rank: &mut Rank, seconds: f32, shot_every: usize, hits: usize) {
+        let mut shots = 0;
+        for step in 0..(seconds / STEP) as usize {
+            if step % shot_every == 0 {
+                rank.apply(RankEvent::Shot);
+                if shots % 10 < hits {
+                    rank.apply(RankEvent::Hit);
+                }
+                shots += 1;
+            }
+            rank.update(STEP);
+        }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        return (a - b).abs() < 1e-4;
+    }
+
+    #[test]
+    fn survival_raises_rank() {
+        let mut rank = Rank::default();
+        play(&mut rank, 60.0, usize::MAX, 0);
+        assert!(close(rank.value(), 60.0 * SURVIVAL_GAIN));
+    }
+
+    #[test]
+    fn accuracy_raises_rank_faster() {
+        let (mut sharp, mut par, mut sloppy) = (Rank::default(), Rank::default(), Rank::default());
+        play(&mut sharp, 60.0, 6, 9);
+        play(&mut par, 60.0, 6, 3);
+        play(&mut sloppy, 60.0, 6, 0);
+        assert!((par.value() / (60.0 * SURVIVAL_GAIN) - 1.0).abs() < 0.1);
+        assert!(sharp.value() > par.value());
+        assert!(sloppy.value() < par.value());
+    }
+
+    #[test]
+    fn accuracy_needs_enough_shots() {
+        let mut rank = Rank::default();
+        for _ in 0..4 {
+            rank.apply(RankEvent::Shot);
+        }
+        assert_eq!(rank.accuracy(), ACCURACY_PAR);
+        rank.apply(RankEvent::Shot);
+        assert_eq!(rank.accuracy(), 0.0);
+    }
+
+    #[test]
+    fn old_shots_fade() {
+        let mut rank = Rank::default();
+        for _ in 0..20 {
+            rank.apply(RankEvent::Shot);
+        }
+        rank.update(STATS_HALF_LIFE);
+        assert!(close(rank.shots, 10.0));
+        // Missing was long enough ago.
+        rank.update(STATS_HALF_LIFE * 2.0);
+        assert_eq!(rank.accuracy(), ACCURACY_PAR);
+    }
+
+    #[test]
+    fn kills_raise_rank() {
+        let mut rank = Rank::default();
+        for _ in 0..10 {
+            rank.apply(RankEvent::Kill);
+        }
+        assert!(close(rank.value(), 10.0 * KILL_GAIN));
+    }
+
+    #[test]
+    fn death_and_continue_lower_rank() {
+        let mut rank = Rank::default();
+        play(&mut rank, 120.0, 6, 9);
+        let before = rank.value();
+        rank.apply(RankEvent::Death);
+        assert!(close(rank.value(), before * DEATH_KEEP));
+        rank.apply(RankEvent::Continue);
+        assert!(close(rank.value(), before * DEATH_KEEP * CONTINUE_KEEP));
+    }
+
+    #[test]
+    fn rank_is_capped() {
+        let mut rank = Rank::default();
+        for _ in 0..1000 {
+            rank.apply(RankEvent::Kill);
+        }
+        assert_eq!(rank.value(), MAX_RANK);
+        play(&mut rank, 60.0, 6, 10);
+        assert_eq!(rank.value(), MAX_RANK);
+    }
+}
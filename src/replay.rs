@@ -0,0 +1,380 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+// Every run is recorded until the game is over: the seed, the settings,
+// and for every tick how long it lasted, the inputs of all slots and the checksum after it.
+// That is enough to play the run again and to tell whether it still plays out the same.
+// Where the ships were is recorded too, for showing a run without simulating it (see ghost.rs).
+// The best run for each combination of settings is kept separately.
+// A recorded run is played again by feeding its inputs to the simulation tick by tick,
+// with the ticks lasting as long as they did, see Playback.
+
+use crate::bullet::BulletCancel;
+use crate::checksum::{self, Checksums};
+use crate::difficulty::Difficulty;
+use crate::game_over::{self, FieldQueries};
+use crate::net::NetInput;
+use crate::player::{self, Player, TickInputs, MAX_PLAYERS};
+use crate::save;
+use crate::score::Score;
+use crate::sim::{self, NextSeed, Settings, SimClock, SimRng};
+use crate::stage::GameMode;
+use crate::GameState;
+use bevy::prelude::*;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Recorder::default())
+            .add_event::<RunRecorded>()
+            .add_system_set(
+                SystemSet::on_enter(GameState::TitleFlyOut).with_system(start.after(sim::reseed)),
+            )
+            .add_system_to_stage(CoreStage::PostUpdate, record.after(checksum::take))
+            .add_system_set(
+                SystemSet::on_update(GameState::Game).with_system(
+                    play_inputs
+                        .after(player::read_input)
+                        .before(player::apply_input),
+                ),
+            )
+            .add_system_to_stage(CoreStage::PostUpdate, verify.after(checksum::take))
+            .add_system_to_stage(CoreStage::Last, rewind)
+            .add_system_to_stage(CoreStage::Last, pace.after(rewind));
+    }
+}
+
+const DIR: &str = "replays";
+// Events live for two ticks, and reading the ones left from the run before may send more.
+const REWIND_TICKS: u32 = 4;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Replay {
+    // Other versions of the game may play the same inputs out differently.
+    pub version: String,
+    pub seed: u64,
+    pub settings: Settings,
+    pub score: u32,
+    // The tick the game was over on.
+    pub end_tick: u64,
+    pub ticks: Vec<ReplayTick>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct ReplayTick {
+    // In nanoseconds.
+    pub step: u64,
+    pub inputs: [u8; MAX_PLAYERS],
+    pub checksum: u64,
+    pub ships: [Option<(f32, f32)>; MAX_PLAYERS],
+}
+
+impl Replay {
+    pub fn load_file(path: &Path) -> Result<Self, String> {
+        let data = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        return ron::from_str(&data).map_err(|e| e.to_string());
+    }
+
+    pub fn load_best(settings: &Settings) -> Option<Self> {
+        return save::load(&best_name(settings));
+    }
+
+    // The names of the saved replays.
+    pub fn list() -> Vec<String> {
+        return save::list(DIR);
+    }
+
+    pub fn load(name: &str) -> Option<Self> {
+        return save::load(&format!("{}/{}", DIR, name));
+    }
+
+    // Seconds since the start of the run by the end of each tick.
+    pub fn tick_times(&self) -> Vec<f64> {
+        let mut time = 0.0;
+        return self
+            .ticks
+            .iter()
+            .map(|tick| {
+                time += tick.step as f64 / 1e9;
+                time
+            })
+            .collect();
+    }
+}
+
+fn best_name(settings: &Settings) -> String {
+    return format!("{}/best-{}.ron", DIR, settings.key());
+}
+
+// Sent with every run that was recorded to the end.
+pub struct RunRecorded(pub Replay);
+
+#[derive(Default)]
+pub struct Recorder {
+    replay: Option<Replay>,
+}
+
+impl Recorder {
+    // Forgets the ticks from the given one on, which are going to be simulated again.
+    pub fn truncate(&mut self, tick: u64) {
+        if let Some(replay) = &mut self.replay {
+            replay.ticks.truncate(tick as usize);
+        }
+    }
+}
+
+fn start(
+    mut recorder: ResMut<Recorder>,
+    rng: Res<SimRng>,
+    playback: Option<Res<Playback>>,
+    (mode, difficulty, cancel): (Res<GameMode>, Res<Difficulty>, Res<BulletCancel>),
+) {
+    if playback.is_some() {
+        recorder.replay = None;
+        return;
+    }
+    recorder.replay = Some(Replay {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        seed: rng.seed(),
+        settings: Settings {
+            mode: *mode,
+            difficulty: *difficulty,
+            cancel: *cancel,
+        },
+        score: 0,
+        end_tick: 0,
+        ticks: Vec::new(),
+    });
+}
+
+fn record(
+    mut recorder: ResMut<Recorder>,
+    (clock, time, state): (Res<SimClock>, Res<Time>, Res<State<GameState>>),
+    (inputs, checksums, score): (Res<TickInputs>, Res<Checksums>, Res<Score>),
+    q: Query<(&Player, &Transform)>,
+    mut recorded: EventWriter<RunRecorded>,
+) {
+    if clock.stalled {
+        return;
+    }
+    if let Some(replay) = &mut recorder.replay {
+        let mut ships = [None; MAX_PLAYERS];
+        for (player, transform) in &q {
+            ships[player.index()] = Some((transform.translation.x, transform.translation.y));
+        }
+        replay.ticks.push(ReplayTick {
+            step: time.delta().as_nanos() as u64,
+            inputs: inputs.0.map(|input| input.0),
+            checksum: checksums.get(clock.tick).unwrap_or_default(),
+            ships,
+        });
+    }
+    // Not on entering the state, so that the tick the game ended on is recorded too.
+    if *state.current() == GameState::GameOver {
+        if let Some(replay) = finish(&mut recorder, &score, clock.tick) {
+            recorded.send(RunRecorded(replay));
+        }
+    }
+}
+
+fn finish(recorder: &mut Recorder, score: &Score, end_tick: u64) -> Option<Replay> {
+    let mut replay = recorder.replay.take()?;
+    replay.score = score.score;
+    replay.end_tick = end_tick;
+
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    // Keeps the ticks on one line.
+    let config = PrettyConfig::default().depth_limit(1);
+    save::store_with(&format!("{}/{}.ron", DIR, time), &replay, config.clone());
+
+    let best = Replay::load_best(&replay.settings).map_or(0, |best| best.score);
+    if replay.score > best {
+        save::store_with(&best_name(&replay.settings), &replay, config);
+    }
+    return Some(replay);
+}
+
+enum Rewind {
+    // The run that was going on is cleared away first.
+    Clear,
+    Wait(u32),
+}
+
+// Plays a replay instead of the local controls. Whoever inserts it decides when to advance.
+pub struct Playback {
+    pub replay: Replay,
+    times: Vec<f64>,
+    // The next tick to simulate.
+    pub tick: usize,
+    // Whether to simulate it in the next frame.
+    pub advance: bool,
+    // The first tick that played out differently than recorded.
+    pub desync: Option<usize>,
+    rewind: Option<Rewind>,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        return Self {
+            times: replay.tick_times(),
+            replay,
+            tick: 0,
+            advance: false,
+            desync: None,
+            rewind: Some(Rewind::Clear),
+        };
+    }
+
+    // Starts the run over, for when there is no snapshot to go back to.
+    pub fn rewind(&mut self) {
+        self.tick = 0;
+        self.desync = None;
+        self.rewind = Some(Rewind::Clear);
+    }
+
+    // Goes on from the tick after a snapshot that is being restored, see rollback.rs.
+    pub fn restore(&mut self, tick: usize) {
+        self.tick = tick;
+        if self.desync.is_some_and(|desync| desync >= tick) {
+            self.desync = None;
+        }
+    }
+
+    pub fn is_rewinding(&self) -> bool {
+        return self.rewind.is_some();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        return self.tick >= self.replay.ticks.len();
+    }
+
+    // Seconds of the run simulated so far.
+    pub fn time(&self) -> f64 {
+        return self.time_at(self.tick);
+    }
+
+    // Seconds of the run it takes to simulate the ticks before the given one.
+    pub fn time_at(&self, tick: usize) -> f64 {
+        return match tick {
+            0 => 0.0,
+            tick => self.times[tick - 1],
+        };
+    }
+
+    pub fn duration(&self) -> f64 {
+        return self.times.last().copied().unwrap_or_default();
+    }
+
+    // How many ticks it takes to get to the time.
+    pub fn ticks_until(&self, time: f64) -> usize {
+        return self.times.partition_point(|t| *t <= time);
+    }
+
+    // Of the tick simulated last.
+    pub fn inputs(&self) -> [NetInput; MAX_PLAYERS] {
+        return match self.tick {
+            0 => [0; MAX_PLAYERS],
+            tick => self.replay.ticks[tick - 1].inputs,
+        }
+        .map(NetInput);
+    }
+}
+
+fn play_inputs(playback: Option<Res<Playback>>, mut inputs: ResMut<TickInputs>) {
+    let playback = match playback {
+        Some(playback) => playback,
+        None => return,
+    };
+    if let Some(tick) = playback.replay.ticks.get(playback.tick) {
+        inputs.0 = tick.inputs.map(NetInput);
+    }
+}
+
+fn verify(playback: Option<ResMut<Playback>>, clock: Res<SimClock>, checksums: Res<Checksums>) {
+    let mut playback = match playback {
+        Some(playback) if !playback.is_rewinding() => playback,
+        _ => return,
+    };
+    if clock.stalled {
+        return;
+    }
+    let recorded = match playback.replay.ticks.get(playback.tick) {
+        Some(tick) => tick.checksum,
+        None => return,
+    };
+
+    if playback.desync.is_none() && checksums.get(clock.tick) != Some(recorded) {
+        warn!("replay: tick {} played out differently", clock.tick);
+        checksums.dump(clock.tick, "replay");
+        playback.desync = Some(playback.tick);
+    }
+    playback.tick += 1;
+}
+
+pub fn rewind(
+    mut commands: Commands,
+    playback: Option<ResMut<Playback>>,
+    (mut state, mut clock): (ResMut<State<GameState>>, ResMut<SimClock>),
+    (mut next_seed, mut score): (ResMut<NextSeed>, ResMut<Score>),
+    (mut mode, mut difficulty, mut cancel): (
+        ResMut<GameMode>,
+        ResMut<Difficulty>,
+        ResMut<BulletCancel>,
+    ),
+    field: FieldQueries,
+) {
+    let mut playback = match playback {
+        Some(playback) => playback,
+        None => return,
+    };
+
+    match playback.rewind {
+        None => {}
+        Some(Rewind::Clear) => {
+            game_over::clear_field(&mut commands, &field);
+            if *state.current() != GameState::ReplayRewind {
+                state.set(GameState::ReplayRewind).unwrap();
+            }
+            clock.unfix();
+            clock.stalled = false;
+            playback.rewind = Some(Rewind::Wait(REWIND_TICKS));
+        }
+        Some(Rewind::Wait(ticks)) if ticks > 1 => {
+            playback.rewind = Some(Rewind::Wait(ticks - 1));
+        }
+        Some(Rewind::Wait(_)) => {
+            let settings = playback.replay.settings;
+            *mode = settings.mode;
+            *difficulty = settings.difficulty;
+            *cancel = settings.cancel;
+            score.clear();
+            next_seed.0 = Some(playback.replay.seed);
+            clock.fix(SimClock::STEP);
+            state.set(GameState::TitleFlyOut).unwrap();
+            playback.rewind = None;
+        }
+    }
+}
+
+pub fn pace(playback: Option<Res<Playback>>, mut clock: ResMut<SimClock>) {
+    let playback = match playback {
+        Some(playback) if !playback.is_rewinding() => playback,
+        _ => return,
+    };
+    match playback.replay.ticks.get(playback.tick) {
+        Some(tick) if playback.advance => {
+            clock.set_step(Duration::from_nanos(tick.step));
+            clock.stalled = false;
+        }
+        _ => clock.stalled = true,
+    }
+}
@@ -0,0 +1,376 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+// Copies of the whole state of a run, taken after some ticks, to go back to later:
+// netplay goes back to the last tick it guessed right and simulates the rest again,
+// and the replay viewer seeks by simulating from the closest one.
+//
+// A copy holds every entity on the field with all of its components, and the resources of the run.
+// Whatever a run depends on has to be registered below, or it is not put back.
+// Queries list the entities in the order they were stored in, so they are put back in that order too.
+
+use crate::anim::AnimationTimer;
+use crate::background::Background;
+use crate::boss::{Boss, BossDeath, BossPart, BossSchedule};
+use crate::bullet::{Bullet, BulletMotion, BulletTimer, HomingTarget};
+use crate::checksum::Checksums;
+use crate::collision::{Collider, CollisionLayers, DestroyOutsideScreen};
+use crate::difficulty::{Levels, RunProgress};
+use crate::emitter::Emitters;
+use crate::enemy::{Enemy, EnemyBullet, EnemySpawn};
+use crate::explosion::Explosion;
+use crate::graze::{GrazeZone, Grazes, Grazing};
+use crate::health::{Damage, Health, KeepOnDeath, Resistances};
+use crate::hit::HitFlash;
+use crate::pattern::MovementPattern;
+use crate::player::{
+    CoreMarker, MissileLauncher, Player, PlayerBullet, PlayerInput, Slots, TickInputs, Weapon,
+};
+use crate::pool::{Pooled, Pools};
+use crate::rank::Rank;
+use crate::replay::Recorder;
+use crate::score::{Points, Score};
+use crate::sim::{SimClock, SimRng};
+use crate::stage::StageRunner;
+use crate::state::GameState;
+use bevy::ecs::component::ComponentId;
+use bevy::ecs::world::{EntityMut, EntityRef};
+use bevy::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+pub struct RollbackPlugin;
+
+impl Plugin for RollbackPlugin {
+    fn build(&self, app: &mut App) {
+        let mut registry = Registry::default();
+        registry
+            .component::<Transform>(app)
+            .component::<GlobalTransform>(app)
+            .component::<Visibility>(app)
+            .component::<ComputedVisibility>(app)
+            .component::<Sprite>(app)
+            .component::<TextureAtlasSprite>(app)
+            .component::<Handle<TextureAtlas>>(app)
+            .component::<Handle<Image>>(app)
+            .component::<Name>(app)
+            .component::<AnimationTimer>(app)
+            .component::<Background>(app)
+            .component::<Boss>(app)
+            .component::<BossPart>(app)
+            .component::<BossDeath>(app)
+            .component::<Bullet>(app)
+            .component::<BulletMotion>(app)
+            .component::<BulletTimer>(app)
+            .component::<HomingTarget>(app)
+            .component::<DestroyOutsideScreen>(app)
+            .component::<CollisionLayers>(app)
+            .component::<Collider>(app)
+            .component::<Emitters>(app)
+            .component::<Enemy>(app)
+            .component::<EnemySpawn>(app)
+            .component::<EnemyBullet>(app)
+            .component::<Explosion>(app)
+            .component::<GrazeZone>(app)
+            .component::<Health>(app)
+            .component::<Damage>(app)
+            .component::<Resistances>(app)
+            .component::<KeepOnDeath>(app)
+            .component::<HitFlash>(app)
+            .component::<MovementPattern>(app)
+            .component::<PlayerInput>(app)
+            .component::<Player>(app)
+            .component::<Weapon>(app)
+            .component::<MissileLauncher>(app)
+            .component::<PlayerBullet>(app)
+            .component::<CoreMarker>(app)
+            .component::<Pooled>(app)
+            .component::<Points>(app)
+            .resource::<SimRng>()
+            .resource::<Score>()
+            .resource::<Slots>()
+            .resource::<TickInputs>()
+            .resource::<Rank>()
+            .resource::<Levels>()
+            .resource::<RunProgress>()
+            .resource::<StageRunner>()
+            .resource::<BossSchedule>()
+            .resource::<Pools>()
+            .resource::<Grazes>()
+            .resource::<Grazing>();
+        // Rebuilt when the entities are put back.
+        registry.skip::<Parent>(app).skip::<Children>(app);
+
+        app.insert_resource(Snapshots::new(registry))
+            .add_system_to_stage(CoreStage::Last, save.exclusive_system().at_start())
+            .add_system_to_stage(CoreStage::Last, restore.exclusive_system().at_end());
+    }
+}
+
+// The entities of a run are these and everything attached to them.
+type Roots = Or<(
+    With<Pooled>,
+    With<Player>,
+    With<Enemy>,
+    With<EnemySpawn>,
+    With<Boss>,
+    With<Background>,
+)>;
+
+trait SavedComponent: Send + Sync {
+    fn put_on(&self, entity: &mut EntityMut);
+}
+
+impl<T: Component + Clone> SavedComponent for T {
+    fn put_on(&self, entity: &mut EntityMut) {
+        entity.insert(self.clone());
+    }
+}
+
+trait SavedResource: Send + Sync {
+    fn put_into(&self, world: &mut World);
+}
+
+impl<T: Clone + Send + Sync + 'static> SavedResource for T {
+    fn put_into(&self, world: &mut World) {
+        world.insert_resource(self.clone());
+    }
+}
+
+type SaveComponent = fn(&EntityRef) -> Option<Box<dyn SavedComponent>>;
+type SaveResource = fn(&World) -> Box<dyn SavedResource>;
+
+#[derive(Default)]
+struct Registry {
+    // `None` for the ones that are not to be saved.
+    components: HashMap<ComponentId, Option<SaveComponent>>,
+    resources: Vec<SaveResource>,
+    // Reported only once each.
+    unknown: HashSet<ComponentId>,
+}
+
+impl Registry {
+    fn component<T: Component + Clone>(&mut self, app: &mut App) -> &mut Self {
+        let id = app.world.init_component::<T>();
+        self.components.insert(
+            id,
+            Some(|entity| {
+                let component = entity.get::<T>()?.clone();
+                return Some(Box::new(component));
+            }),
+        );
+        return self;
+    }
+
+    fn skip<T: Component>(&mut self, app: &mut App) -> &mut Self {
+        let id = app.world.init_component::<T>();
+        self.components.insert(id, None);
+        return self;
+    }
+
+    fn resource<T: Clone + Send + Sync + 'static>(&mut self) -> &mut Self {
+        self.resources
+            .push(|world| Box::new(world.resource::<T>().clone()));
+        return self;
+    }
+}
+
+struct SavedEntity {
+    entity: Entity,
+    components: Vec<Box<dyn SavedComponent>>,
+    children: Vec<Entity>,
+}
+
+struct Snapshot {
+    clock: SimClock,
+    entities: Vec<SavedEntity>,
+    resources: Vec<Box<dyn SavedResource>>,
+}
+
+// Put on the entities while they are being put back, so that they never get
+// into the same tables as the entities of the run in the meantime.
+#[derive(Component)]
+struct Restoring;
+
+pub struct Snapshots {
+    registry: Registry,
+    // Every this many ticks, or never.
+    period: Option<u64>,
+    // The most snapshots to keep, the oldest ones are dropped.
+    limit: Option<usize>,
+    // By the tick they were taken after.
+    snapshots: BTreeMap<u64, Snapshot>,
+    restore: Option<u64>,
+}
+
+impl Snapshots {
+    fn new(registry: Registry) -> Self {
+        return Self {
+            registry,
+            period: None,
+            limit: None,
+            snapshots: BTreeMap::new(),
+            restore: None,
+        };
+    }
+
+    pub fn start(&mut self, period: u64, limit: Option<usize>) {
+        self.stop();
+        self.period = Some(period);
+        self.limit = limit;
+    }
+
+    pub fn stop(&mut self) {
+        self.period = None;
+        self.snapshots.clear();
+        self.restore = None;
+    }
+
+    // The last tick up to the given one that there is a snapshot of.
+    pub fn latest(&self, until: u64) -> Option<u64> {
+        return self
+            .snapshots
+            .range(..=until)
+            .next_back()
+            .map(|(tick, _)| *tick);
+    }
+
+    // Goes back to the state after the tick at the end of the frame.
+    pub fn restore(&mut self, tick: u64) {
+        self.restore = Some(tick);
+    }
+}
+
+// The entities of the run, in the order they are stored in.
+fn entities(world: &mut World) -> Vec<Entity> {
+    let mut found = HashSet::new();
+    let mut next: Vec<Entity> = world
+        .query_filtered::<Entity, Roots>()
+        .iter(world)
+        .collect();
+    while let Some(entity) = next.pop() {
+        if found.insert(entity) {
+            if let Some(children) = world.get::<Children>(entity) {
+                next.extend(children.iter().copied());
+            }
+        }
+    }
+
+    return world
+        .archetypes()
+        .iter()
+        .flat_map(|archetype| archetype.entities().iter().copied())
+        .filter(|entity| found.contains(entity))
+        .collect();
+}
+
+fn save(world: &mut World) {
+    let clock = world.resource::<SimClock>().clone();
+    let state = *world.resource::<State<GameState>>().current();
+    match world.resource::<Snapshots>().period {
+        Some(period)
+            if !clock.stalled && state == GameState::Game && clock.tick.is_multiple_of(period) => {}
+        _ => return,
+    }
+
+    let entities = entities(world);
+    world.resource_scope(|world, mut snapshots: Mut<Snapshots>| {
+        let registry = &mut snapshots.registry;
+        let mut saved = Vec::with_capacity(entities.len());
+        for entity in entities {
+            let entity = world.entity(entity);
+            let mut components = Vec::new();
+            for id in entity.archetype().components() {
+                match registry.components.get(&id) {
+                    Some(Some(save)) => components.extend(save(&entity)),
+                    Some(None) => {}
+                    None => {
+                        if registry.unknown.insert(id) {
+                            let name = world.components().get_info(id).map(|info| info.name());
+                            warn!("rollback: {} is not saved", name.unwrap_or("?"));
+                        }
+                    }
+                }
+            }
+            let children = entity
+                .get::<Children>()
+                .map(|children| children.to_vec())
+                .unwrap_or_default();
+            saved.push(SavedEntity {
+                entity: entity.id(),
+                components,
+                children,
+            });
+        }
+        let resources = registry.resources.iter().map(|save| save(world)).collect();
+
+        snapshots.snapshots.insert(
+            clock.tick,
+            Snapshot {
+                clock,
+                entities: saved,
+                resources,
+            },
+        );
+        if let Some(limit) = snapshots.limit {
+            while snapshots.snapshots.len() > limit {
+                let oldest = *snapshots.snapshots.keys().next().unwrap();
+                snapshots.snapshots.remove(&oldest);
+            }
+        }
+    });
+}
+
+fn restore(world: &mut World) {
+    let mut snapshots = world.resource_mut::<Snapshots>();
+    let tick = match snapshots.restore.take() {
+        Some(tick) if snapshots.snapshots.contains_key(&tick) => tick,
+        Some(tick) => {
+            error!("rollback: nothing saved after tick {}", tick);
+            return;
+        }
+        None => return,
+    };
+
+    for entity in entities(world) {
+        world.despawn(entity);
+    }
+
+    world.resource_scope(|world, snapshots: Mut<Snapshots>| {
+        let snapshot = &snapshots.snapshots[&tick];
+        for saved in &snapshot.entities {
+            let mut entity = match world.get_or_spawn(saved.entity) {
+                Some(entity) => entity,
+                None => {
+                    error!("rollback: {:?} is taken", saved.entity);
+                    continue;
+                }
+            };
+            entity.insert(Restoring);
+            for component in &saved.components {
+                component.put_on(&mut entity);
+            }
+        }
+        for saved in &snapshot.entities {
+            if !saved.children.is_empty() {
+                world
+                    .entity_mut(saved.entity)
+                    .push_children(&saved.children);
+            }
+        }
+        // Each one goes to the end of its table.
+        for saved in &snapshot.entities {
+            if let Some(mut entity) = world.get_entity_mut(saved.entity) {
+                entity.remove::<Restoring>();
+            }
+        }
+
+        for resource in &snapshot.resources {
+            resource.as_ref().put_into(world);
+        }
+        world.resource_mut::<SimClock>().rewind(&snapshot.clock);
+    });
+
+    // What comes after the tick is going to be simulated again.
+    world.resource_mut::<Checksums>().truncate(tick + 1);
+    world.resource_mut::<Recorder>().truncate(tick + 1);
+}
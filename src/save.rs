@@ -0,0 +1,86 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+use bevy::prelude::*;
+use ron::ser::PrettyConfig;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::path::PathBuf;
+
+const APP_DIR: &str = "fly-and-shoot";
+
+fn path(name: &str) -> Option<PathBuf> {
+    return dirs::data_dir().map(|dir| dir.join(APP_DIR).join(name));
+}
+
+// A missing file is not an error: nothing was saved yet.
+pub fn load<T: DeserializeOwned>(name: &str) -> Option<T> {
+    let path = path(name)?;
+    let data = fs::read_to_string(&path).ok()?;
+    return match ron::from_str(&data) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            warn!("cannot parse {}: {}", path.display(), e);
+            None
+        }
+    };
+}
+
+pub fn load_text(name: &str) -> Option<String> {
+    return fs::read_to_string(path(name)?).ok();
+}
+
+pub fn store<T: Serialize>(name: &str, value: &T) {
+    store_with(name, value, PrettyConfig::default());
+}
+
+pub fn store_with<T: Serialize>(name: &str, value: &T, config: PrettyConfig) {
+    let data = match ron::ser::to_string_pretty(value, config) {
+        Ok(data) => data,
+        Err(e) => {
+            warn!("cannot serialize {}: {}", name, e);
+            return;
+        }
+    };
+    store_text(name, &data);
+}
+
+// The names of the files in a directory, sorted.
+pub fn list(dir: &str) -> Vec<String> {
+    let entries = match path(dir).map(fs::read_dir) {
+        Some(Ok(entries)) => entries,
+        _ => return Vec::new(),
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .collect();
+    names.sort();
+    return names;
+}
+
+// Returns where the file went.
+pub fn store_text(name: &str, data: &str) -> Option<PathBuf> {
+    let path = match path(name) {
+        Some(path) => path,
+        None => {
+            warn!("cannot find the data directory to save {}", name);
+            return None;
+        }
+    };
+
+    let result = fs::create_dir_all(path.parent().unwrap()).and_then(|_| fs::write(&path, data));
+    if let Err(e) = result {
+        warn!("cannot save {}: {}", path.display(), e);
+        return None;
+    }
+    return Some(path);
+}
+
+pub fn remove(name: &str) {
+    if let Some(path) = path(name) {
+        if let Err(e) = fs::remove_file(&path) {
+            warn!("cannot remove {}: {}", path.display(), e);
+        }
+    }
+}
@@ -2,9 +2,17 @@
 // 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
 
 use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 use crate::{
     anim::{AnimPlugin, MainFont},
+    difficulty::Difficulty,
+    health::{self, Died},
+    player::MAX_PLAYERS,
+    rank::RankEvent,
+    replay::Playback,
+    save,
     state::GameState,
 };
 
@@ -19,7 +27,8 @@ impl Plugin for ScorePlugin {
             )
             .add_system_set(
                 SystemSet::on_enter(GameState::GameOver)
-                    .with_system(setup_score_text_for_game_over),
+                    .with_system(setup_score_text_for_game_over)
+                    .with_system(record_high_score),
             )
             .add_system_set(
                 SystemSet::on_update(GameState::GameOver).with_system(blink_text_for_game_over),
@@ -27,55 +36,71 @@ impl Plugin for ScorePlugin {
             .add_system_set(
                 SystemSet::on_exit(GameState::GameOver).with_system(setup_score_text_for_game),
             )
-            .add_system(update_score_text);
+            .add_system(award_points.after(health::die))
+            .add_system(update_score_text.after(award_points));
     }
 }
 
 #[derive(Component)]
 pub struct ScoreText;
 
+// What destroying the entity is worth.
+#[derive(Component, Clone)]
+pub struct Points(pub u32);
+
+// The score is shared, but each player's part of it is tracked too.
+#[derive(Clone)]
 pub struct Score {
     pub score: u32,
+    pub players: [u32; MAX_PLAYERS],
+    // Bullets cancelled in this run, see bullet::CANCELS_PER_POINT.
+    pub cancels: u32,
 }
 
 impl Score {
-    const ZERO: Self = Self { score: 0 };
-    pub const CONTINUE_COST: u32 = 50;
+    const ZERO: Self = Self {
+        score: 0,
+        players: [0; MAX_PLAYERS],
+        cancels: 0,
+    };
+
+    pub fn add(&mut self, points: u32) {
+        self.score += points;
+    }
 
-    pub fn inc(&mut self) {
-        self.score += 1;
+    pub fn add_for(&mut self, player: usize, points: u32) {
+        self.add(points);
+        self.players[player] += points;
     }
 
     pub fn clear(&mut self) {
-        self.score = 0;
+        *self = Self::ZERO;
     }
 
-    pub fn can_continue(&self) -> bool {
-        return self.score >= Self::CONTINUE_COST;
+    pub fn can_continue(&self, cost: u32) -> bool {
+        return self.score >= cost;
     }
 
-    pub fn buy_continue(&mut self) {
-        self.score = self
-            .score
-            .checked_sub(Self::CONTINUE_COST)
-            .or(Some(0))
-            .unwrap();
+    pub fn buy_continue(&mut self, cost: u32) {
+        self.score = self.score.checked_sub(cost).or(Some(0)).unwrap();
     }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(transparent)]
+pub struct HighScores(BTreeMap<Difficulty, u32>);
 
-    pub fn interp(&self, start_val: f32, end_val: f32, max_score: u32) -> f32 {
-        let diff = end_val - start_val;
-        let raw_result = start_val + (self.score as f32) / (max_score as f32) * diff;
-        let clamped_result = if start_val < end_val {
-            raw_result.clamp(start_val, end_val)
-        } else {
-            raw_result.clamp(end_val, start_val)
-        };
-        return clamped_result;
+impl HighScores {
+    const FILE: &'static str = "highscores.ron";
+
+    pub fn get(&self, difficulty: Difficulty) -> u32 {
+        return self.0.get(&difficulty).copied().unwrap_or(0);
     }
 }
 
 fn setup(mut commands: Commands, font: Res<MainFont>) {
     commands.insert_resource(Score::ZERO);
+    commands.insert_resource(save::load::<HighScores>(HighScores::FILE).unwrap_or_default());
 
     let text =
         AnimPlugin::text_bundle(&font.0, "SCORE: 0123456789", 25.0, Vec3::new(0.0, 0.0, 0.1));
@@ -106,6 +131,23 @@ fn setup_score_text_for_game(
     section.style.color = Color::WHITE;
 }
 
+pub fn award_points(
+    mut events: EventReader<Died>,
+    points_q: Query<&Points>,
+    mut score: ResMut<Score>,
+    mut rank_events: EventWriter<RankEvent>,
+) {
+    for died in events.iter() {
+        if let Ok(points) = points_q.get(died.entity) {
+            match died.player {
+                Some(player) => score.add_for(player, points.0),
+                None => score.add(points.0),
+            }
+            rank_events.send(RankEvent::Kill);
+        }
+    }
+}
+
 fn update_score_text(mut q: Query<&mut Text, With<ScoreText>>, score: Res<Score>) {
     if score.is_changed() {
         for mut text in &mut q {
@@ -137,3 +179,19 @@ fn blink_text_for_game_over(mut q: Query<&mut Text, With<ScoreText>>, time: Res<
     let color_value = t.sin().abs();
     section.style.color = Color::rgb(1.0, color_value as f32, color_value as f32);
 }
+
+fn record_high_score(
+    mut high_scores: ResMut<HighScores>,
+    score: Res<Score>,
+    difficulty: Res<Difficulty>,
+    playback: Option<Res<Playback>>,
+) {
+    // A replay has already scored what it did.
+    if playback.is_some() {
+        return;
+    }
+    if score.score > high_scores.get(*difficulty) {
+        high_scores.0.insert(*difficulty, score.score);
+        save::store(HighScores::FILE, &*high_scores);
+    }
+}
@@ -0,0 +1,381 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+// Collider shapes and the overlap tests between them.
+// Like the grid, this does not depend on the ECS.
+
+use bevy::math::{Affine2, Vec2};
+use serde::Deserialize;
+
+// Sizes are in the local space of the entity, before its scale is applied.
+#[derive(Deserialize, Clone, Debug)]
+pub enum Shape {
+    Circle { radius: f32 },
+    // A rectangle along the X axis with half-circles on both ends.
+    // The length does not include the ends.
+    Capsule { radius: f32, length: f32 },
+    Box { size: Vec2 },
+    Compound(Vec<Part>),
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Part {
+    #[serde(default)]
+    pub offset: Vec2,
+    #[serde(default)]
+    pub angle: f32,
+    pub shape: Shape,
+}
+
+// A shape in world space.
+// Circles and capsules are both a segment with a radius around it.
+#[derive(Clone, Copy, Debug)]
+pub enum Placed {
+    Round {
+        a: Vec2,
+        b: Vec2,
+        radius: f32,
+    },
+    Box {
+        center: Vec2,
+        axes: [Vec2; 2],
+        half: Vec2,
+    },
+}
+
+impl Shape {
+    pub fn place(&self, transform: Affine2, out: &mut Vec<Placed>) {
+        let x_axis = transform.transform_vector2(Vec2::X);
+        let y_axis = transform.transform_vector2(Vec2::Y);
+        // Round shapes cannot be stretched, so they take the larger scale.
+        let scale = x_axis.length().max(y_axis.length());
+
+        match self {
+            Shape::Circle { radius } => {
+                let center = transform.transform_point2(Vec2::ZERO);
+                out.push(Placed::Round {
+                    a: center,
+                    b: center,
+                    radius: radius * scale,
+                });
+            }
+            Shape::Capsule { radius, length } => {
+                let end = Vec2::new(length / 2.0, 0.0);
+                out.push(Placed::Round {
+                    a: transform.transform_point2(-end),
+                    b: transform.transform_point2(end),
+                    radius: radius * scale,
+                });
+            }
+            Shape::Box { size } => {
+                out.push(Placed::Box {
+                    center: transform.transform_point2(Vec2::ZERO),
+                    axes: [x_axis.normalize_or_zero(), y_axis.normalize_or_zero()],
+                    half: *size / 2.0 * Vec2::new(x_axis.length(), y_axis.length()),
+                });
+            }
+            Shape::Compound(parts) => {
+                for part in parts {
+                    let local = Affine2::from_angle_translation(part.angle, part.offset);
+                    part.shape.place(transform * local, out);
+                }
+            }
+        }
+    }
+}
+
+impl Placed {
+    pub fn bounds(&self) -> (Vec2, Vec2) {
+        return match self {
+            Placed::Round { a, b, radius } => (a.min(*b) - *radius, a.max(*b) + *radius),
+            Placed::Box { center, axes, half } => {
+                let extent = axes[0].abs() * half.x + axes[1].abs() * half.y;
+                (*center - extent, *center + extent)
+            }
+        };
+    }
+
+    pub fn overlaps(&self, other: &Placed) -> bool {
+        return match (self, other) {
+            (
+                Placed::Round { a, b, radius },
+                Placed::Round {
+                    a: other_a,
+                    b: other_b,
+                    radius: other_radius,
+                },
+            ) => segment_distance(*a, *b, *other_a, *other_b) < radius + other_radius,
+            (Placed::Round { a, b, radius }, Placed::Box { center, axes, half })
+            | (Placed::Box { center, axes, half }, Placed::Round { a, b, radius }) => {
+                let local =
+                    |p: Vec2| Vec2::new((p - *center).dot(axes[0]), (p - *center).dot(axes[1]));
+                segment_box_distance(local(*a), local(*b), *half) < *radius
+            }
+            (
+                Placed::Box { center, axes, half },
+                Placed::Box {
+                    center: other_center,
+                    axes: other_axes,
+                    half: other_half,
+                },
+            ) => {
+                let offset = *other_center - *center;
+                let extent = |axes: &[Vec2; 2], half: Vec2, n: Vec2| {
+                    half.x * axes[0].dot(n).abs() + half.y * axes[1].dot(n).abs()
+                };
+                axes.iter().chain(other_axes).all(|n| {
+                    offset.dot(*n).abs()
+                        < extent(axes, *half, *n) + extent(other_axes, *other_half, *n)
+                })
+            }
+        };
+    }
+}
+
+fn point_segment_distance(p: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let t = if ab.length_squared() > 0.0 {
+        ((p - a).dot(ab) / ab.length_squared()).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    return p.distance(a + ab * t);
+}
+
+fn segments_cross(a: Vec2, b: Vec2, c: Vec2, d: Vec2) -> bool {
+    let side = |p: Vec2, q: Vec2, r: Vec2| (q - p).perp_dot(r - p);
+    return side(c, d, a) * side(c, d, b) < 0.0 && side(a, b, c) * side(a, b, d) < 0.0;
+}
+
+fn segment_distance(a: Vec2, b: Vec2, c: Vec2, d: Vec2) -> f32 {
+    if segments_cross(a, b, c, d) {
+        return 0.0;
+    }
+    return point_segment_distance(a, c, d)
+        .min(point_segment_distance(b, c, d))
+        .min(point_segment_distance(c, a, b))
+        .min(point_segment_distance(d, a, b));
+}
+
+// The box is centered at the origin and aligned with the axes.
+fn segment_box_distance(a: Vec2, b: Vec2, half: Vec2) -> f32 {
+    // Clip the segment against both slabs; anything left is inside the box.
+    let dir = b - a;
+    let (mut t0, mut t1) = (0.0_f32, 1.0_f32);
+    let mut inside = true;
+    for axis in 0..2 {
+        if dir[axis].abs() < f32::EPSILON {
+            if a[axis].abs() > half[axis] {
+                inside = false;
+            }
+            continue;
+        }
+        let near = (-half[axis] - a[axis]) / dir[axis];
+        let far = (half[axis] - a[axis]) / dir[axis];
+        t0 = t0.max(near.min(far));
+        t1 = t1.min(near.max(far));
+    }
+    if inside && t0 <= t1 {
+        return 0.0;
+    }
+
+    let point_box = |p: Vec2| (p.abs() - half).max(Vec2::ZERO).length();
+    let corners = [
+        Vec2::new(half.x, half.y),
+        Vec2::new(-half.x, half.y),
+        Vec2::new(half.x, -half.y),
+        Vec2::new(-half.x, -half.y),
+    ];
+    return corners
+        .into_iter()
+        .map(|corner| point_segment_distance(corner, a, b))
+        .fold(point_box(a).min(point_box(b)), f32::min);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::{FRAC_1_SQRT_2, FRAC_PI_4};
+
+    fn place(shape: &Shape, x: f32, y: f32, angle: f32) -> Vec<Placed> {
+        let mut out = Vec::new();
+        shape.place(
+            Affine2::from_angle_translation(angle, Vec2::new(x, y)),
+            &mut out,
+        );
+        return out;
+    }
+
+    // Checked both ways, since each pair of kinds has a single implementation.
+    fn overlap(a: &[Placed], b: &[Placed]) -> bool {
+        let there = a.iter().any(|a| b.iter().any(|b| a.overlaps(b)));
+        let back = b.iter().any(|b| a.iter().any(|a| b.overlaps(a)));
+        assert_eq!(there, back);
+        return there;
+    }
+
+    fn circle(radius: f32) -> Shape {
+        return Shape::Circle { radius };
+    }
+
+    fn capsule(radius: f32, length: f32) -> Shape {
+        return Shape::Capsule { radius, length };
+    }
+
+    fn square(size: f32) -> Shape {
+        return Shape::Box {
+            size: Vec2::splat(size),
+        };
+    }
+
+    #[test]
+    fn circle_circle() {
+        let a = place(&circle(1.0), 0.0, 0.0, 0.0);
+        assert!(overlap(&a, &place(&circle(1.0), 1.9, 0.0, 0.0)));
+        assert!(!overlap(&a, &place(&circle(1.0), 2.1, 0.0, 0.0)));
+        assert!(!overlap(&a, &place(&circle(0.5), 1.1, 1.1, 0.0)));
+    }
+
+    #[test]
+    fn circle_capsule() {
+        let a = place(&capsule(0.5, 4.0), 0.0, 0.0, 0.0);
+        // Next to the middle, and then past the rounded end.
+        assert!(overlap(&a, &place(&circle(0.5), 1.5, 0.9, 0.0)));
+        assert!(!overlap(&a, &place(&circle(0.5), 1.5, 1.1, 0.0)));
+        assert!(overlap(&a, &place(&circle(0.5), 2.9, 0.0, 0.0)));
+        assert!(!overlap(&a, &place(&circle(0.5), 2.8, 0.8, 0.0)));
+
+        let rotated = place(&capsule(0.5, 4.0), 0.0, 0.0, FRAC_PI_4 * 2.0);
+        assert!(overlap(&rotated, &place(&circle(0.5), 0.0, 2.9, 0.0)));
+        assert!(!overlap(&rotated, &place(&circle(0.5), 2.9, 0.0, 0.0)));
+    }
+
+    #[test]
+    fn capsule_capsule() {
+        let a = place(&capsule(0.1, 4.0), 0.0, 0.0, 0.0);
+        // Crossing in the middle, far from the ends of both.
+        assert!(overlap(&a, &place(&capsule(0.1, 4.0), 0.0, 0.0, FRAC_PI_4)));
+        assert!(overlap(&a, &place(&capsule(0.1, 4.0), 0.0, 0.19, 0.0)));
+        assert!(!overlap(&a, &place(&capsule(0.1, 4.0), 0.0, 0.21, 0.0)));
+        assert!(!overlap(
+            &a,
+            &place(&capsule(0.1, 1.0), 2.0, 0.8, 2.0 * FRAC_PI_4)
+        ));
+    }
+
+    #[test]
+    fn circle_box() {
+        let a = place(&square(2.0), 0.0, 0.0, 0.0);
+        assert!(overlap(&a, &place(&circle(0.5), 1.4, 0.0, 0.0)));
+        assert!(!overlap(&a, &place(&circle(0.5), 1.6, 0.0, 0.0)));
+        assert!(overlap(&a, &place(&circle(0.1), 0.0, 0.0, 0.0)));
+        // Inside the bounds of the box, but off its corner.
+        assert!(!overlap(&a, &place(&circle(0.5), 1.4, 1.4, 0.0)));
+        assert!(overlap(&a, &place(&circle(0.5), 1.3, 1.3, 0.0)));
+    }
+
+    #[test]
+    fn circle_rotated_box() {
+        // A diamond with its corners on the axes at about 1.41.
+        let a = place(&square(2.0), 0.0, 0.0, FRAC_PI_4);
+        assert!(overlap(&a, &place(&circle(0.1), 1.5, 0.0, 0.0)));
+        assert!(!overlap(&a, &place(&circle(0.1), 1.6, 0.0, 0.0)));
+        // Where the corner of an unrotated box would be.
+        assert!(!overlap(&a, &place(&circle(0.1), 0.9, 0.9, 0.0)));
+        assert!(overlap(&a, &place(&circle(0.1), 0.75, 0.75, 0.0)));
+    }
+
+    #[test]
+    fn capsule_box() {
+        let a = place(&square(2.0), 0.0, 0.0, 0.0);
+        // Goes right through with both ends outside.
+        assert!(overlap(&a, &place(&capsule(0.01, 6.0), 0.0, 0.0, 0.0)));
+        assert!(overlap(
+            &a,
+            &place(&capsule(0.01, 6.0), 0.0, 0.0, FRAC_PI_4)
+        ));
+        assert!(overlap(&a, &place(&capsule(0.2, 6.0), 0.0, 1.1, 0.0)));
+        assert!(!overlap(&a, &place(&capsule(0.2, 6.0), 0.0, 1.3, 0.0)));
+        // Along the diagonal, just clear of the corner.
+        let clear = 2.0_f32.sqrt() + 0.3;
+        let rod = capsule(0.2, 4.0);
+        assert!(!overlap(
+            &a,
+            &place(
+                &rod,
+                clear * FRAC_1_SQRT_2,
+                clear * FRAC_1_SQRT_2,
+                -FRAC_PI_4
+            )
+        ));
+    }
+
+    #[test]
+    fn box_box() {
+        let a = place(&square(2.0), 0.0, 0.0, 0.0);
+        assert!(overlap(&a, &place(&square(2.0), 1.9, 1.9, 0.0)));
+        assert!(!overlap(&a, &place(&square(2.0), 2.1, 0.0, 0.0)));
+        assert!(!overlap(&a, &place(&square(2.0), 0.0, -2.1, 0.0)));
+    }
+
+    #[test]
+    fn rotated_box_box() {
+        let a = place(&square(2.0), 0.0, 0.0, 0.0);
+        // The bounds overlap, but the diamond's side separates them.
+        let diamond = place(&square(2.0), 2.3, 2.3, FRAC_PI_4);
+        assert!(!overlap(&a, &diamond));
+        assert!(overlap(&a, &place(&square(2.0), 2.3, 0.0, FRAC_PI_4)));
+        assert!(!overlap(&a, &place(&square(2.0), 2.5, 0.0, FRAC_PI_4)));
+
+        // Two long thin boxes crossing, with no corner inside the other one.
+        let bar = Shape::Box {
+            size: Vec2::new(6.0, 0.2),
+        };
+        let b = place(&bar, 0.0, 0.0, FRAC_PI_4);
+        assert!(overlap(&b, &place(&bar, 0.0, 0.0, -FRAC_PI_4)));
+        assert!(!overlap(&b, &place(&bar, 1.0, -1.0, FRAC_PI_4)));
+    }
+
+    #[test]
+    fn compound() {
+        let dumbbell = Shape::Compound(vec![
+            Part {
+                offset: Vec2::new(-2.0, 0.0),
+                angle: 0.0,
+                shape: circle(1.0),
+            },
+            Part {
+                offset: Vec2::new(2.0, 0.0),
+                angle: FRAC_PI_4,
+                shape: square(1.0),
+            },
+        ]);
+        let a = place(&dumbbell, 0.0, 0.0, 0.0);
+        assert_eq!(a.len(), 2);
+        assert!(overlap(&a, &place(&circle(0.5), -0.6, 0.0, 0.0)));
+        // In the gap between the parts.
+        assert!(!overlap(&a, &place(&circle(0.3), 0.0, 0.0, 0.0)));
+        // The part is a diamond, so its corner reaches out further along the axis.
+        assert!(overlap(&a, &place(&circle(0.1), 2.75, 0.0, 0.0)));
+        assert!(!overlap(&a, &place(&circle(0.1), 2.55, 0.55, 0.0)));
+
+        // The parts turn around the origin of the whole shape.
+        let turned = place(&dumbbell, 0.0, 0.0, 2.0 * FRAC_PI_4);
+        assert!(overlap(&turned, &place(&circle(0.5), 0.0, -0.6, 0.0)));
+        assert!(!overlap(&turned, &place(&circle(0.5), -0.6, 0.0, 0.0)));
+        assert!(!overlap(&a, &place(&dumbbell, 0.0, 3.1, 0.0)));
+        assert!(overlap(&a, &place(&dumbbell, 0.0, 1.9, 0.0)));
+    }
+
+    #[test]
+    fn scaled() {
+        let mut out = Vec::new();
+        let transform = Affine2::from_scale(Vec2::new(2.0, 1.0));
+        square(1.0).place(transform, &mut out);
+        circle(1.0).place(transform, &mut out);
+        // The box is stretched, the circle takes the larger scale.
+        assert!(overlap(&out[..1], &place(&circle(0.1), 1.05, 0.0, 0.0)));
+        assert!(!overlap(&out[..1], &place(&circle(0.1), 0.0, 0.65, 0.0)));
+        assert!(overlap(&out[1..], &place(&circle(0.1), 0.0, 2.05, 0.0)));
+    }
+}
@@ -0,0 +1,257 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+// Whatever decides how a run plays out goes through here,
+// so that two machines given the same seed and the same inputs play the same run.
+// Randomness that is only for looks may still use the thread RNG.
+
+use crate::bullet::BulletCancel;
+use crate::difficulty::Difficulty;
+use crate::stage::GameMode;
+use crate::state::GameState;
+use bevy::audio::play_queued_audio_system;
+use bevy::ecs::schedule::{ParallelSystemContainer, ParallelSystemExecutor, ShouldRun};
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+pub struct SimPlugin;
+
+impl Plugin for SimPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(NextSeed(None))
+            .insert_resource(SimRng::new(0))
+            .insert_resource(SimClock::default())
+            .add_system_set(SystemSet::on_enter(GameState::TitleFlyOut).with_system(reseed))
+            .add_system_to_stage(CoreStage::First, advance)
+            .add_system_to_stage(
+                CoreStage::PostUpdate,
+                hush.before(play_queued_audio_system::<AudioSource>),
+            );
+
+        app.schedule.set_run_criteria(frame);
+        app.stage(CoreStage::Update, |stage: &mut SystemStage| {
+            stage.set_executor(Box::new(OrderedExecutor::default()));
+            return stage.set_run_criteria(not_stalled);
+        });
+        // Skipping this too keeps the events sent in the last tick around until the next one.
+        app.stage(CoreStage::First, |stage: &mut SystemStage| {
+            return stage.set_run_criteria(not_stalled);
+        });
+    }
+}
+
+// The seed for the next run. A random one is picked if there is none.
+pub struct NextSeed(pub Option<u64>);
+
+// Everything besides the seed and the inputs that a run depends on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Settings {
+    pub mode: GameMode,
+    pub difficulty: Difficulty,
+    pub cancel: BulletCancel,
+}
+
+impl Settings {
+    // E.g. "stage-normal-off".
+    pub fn key(&self) -> String {
+        let key = format!("{:?}-{:?}-{:?}", self.mode, self.difficulty, self.cancel);
+        return key.to_lowercase();
+    }
+}
+
+#[derive(Clone)]
+pub struct SimRng {
+    rng: StdRng,
+    seed: u64,
+}
+
+impl SimRng {
+    fn new(seed: u64) -> Self {
+        return Self {
+            rng: StdRng::seed_from_u64(seed),
+            seed,
+        };
+    }
+
+    // The seed the current run was started with.
+    pub fn seed(&self) -> u64 {
+        return self.seed;
+    }
+
+    // Tells apart the states of two generators without changing them.
+    pub fn fingerprint(&self) -> u64 {
+        return self.rng.clone().next_u64();
+    }
+}
+
+impl RngCore for SimRng {
+    fn next_u32(&mut self) -> u32 {
+        return self.rng.next_u32();
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        return self.rng.next_u64();
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest);
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        return self.rng.try_fill_bytes(dest);
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct SimClock {
+    // Every tick lasts exactly this long when set. Real time is used otherwise.
+    step: Option<Duration>,
+    time: Option<Time>,
+    // Ticks since the run started.
+    pub tick: u64,
+    // Nothing is simulated while stalled, e.g. when waiting for the other player's inputs.
+    pub stalled: bool,
+    // Ticks to simulate in this frame after the current one, before the frame is drawn.
+    pub catch_up: u32,
+    // Whether the current tick is one of those.
+    pub catching_up: bool,
+    // Whether those make no sound, e.g. because they were already heard before a rollback.
+    pub quiet: bool,
+}
+
+impl SimClock {
+    pub const STEP: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+    pub fn fix(&mut self, step: Duration) {
+        *self = Self {
+            step: Some(step),
+            ..default()
+        };
+    }
+
+    // Unlike `fix`, keeps counting the ticks of the current run.
+    pub fn set_step(&mut self, step: Duration) {
+        self.step = Some(step);
+    }
+
+    pub fn unfix(&mut self) {
+        *self = Self {
+            tick: self.tick,
+            ..default()
+        };
+    }
+
+    // Goes back to the tick and the time of a clock saved earlier, but keeps how it is driven.
+    pub fn rewind(&mut self, to: &SimClock) {
+        self.tick = to.tick;
+        self.time = to.time.clone();
+    }
+}
+
+// Runs the systems one at a time, in an order that only depends on their names
+// and on how they are ordered explicitly, and applies their commands in that order too.
+// Bevy orders the systems that are not ordered explicitly differently in every process.
+#[derive(Default)]
+struct OrderedExecutor {
+    order: Vec<usize>,
+}
+
+impl ParallelSystemExecutor for OrderedExecutor {
+    fn rebuild_cached_data(&mut self, systems: &[ParallelSystemContainer]) {
+        let mut waiting: Vec<usize> = systems.iter().map(|s| s.dependencies().len()).collect();
+        let mut dependants = vec![Vec::new(); systems.len()];
+        for (index, system) in systems.iter().enumerate() {
+            for &dependency in system.dependencies() {
+                dependants[dependency].push(index);
+            }
+        }
+
+        let mut ready: BTreeSet<(Cow<str>, usize)> = systems
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| waiting[*index] == 0)
+            .map(|(index, system)| (system.name(), index))
+            .collect();
+        self.order.clear();
+        while let Some(next) = ready.iter().next().cloned() {
+            ready.remove(&next);
+            let index = next.1;
+            self.order.push(index);
+            for &dependant in &dependants[index] {
+                waiting[dependant] -= 1;
+                if waiting[dependant] == 0 {
+                    ready.insert((systems[dependant].name(), dependant));
+                }
+            }
+        }
+    }
+
+    fn run_systems(&mut self, systems: &mut [ParallelSystemContainer], world: &mut World) {
+        for &index in &self.order {
+            if systems[index].should_run() {
+                systems[index].system_mut().run((), world);
+            }
+        }
+        // The stage would apply them in its own order otherwise.
+        for &index in &self.order {
+            if systems[index].should_run() {
+                systems[index].system_mut().apply_buffers(world);
+            }
+        }
+    }
+}
+
+pub fn reseed(mut rng: ResMut<SimRng>, next_seed: Res<NextSeed>, mut clock: ResMut<SimClock>) {
+    let seed = next_seed.0.unwrap_or_else(rand::random);
+    *rng = SimRng::new(seed);
+    clock.tick = 0;
+}
+
+fn advance(mut time: ResMut<Time>, mut clock: ResMut<SimClock>) {
+    clock.tick += 1;
+
+    let step = match clock.step {
+        Some(step) => step,
+        None => return,
+    };
+    let fixed = clock.time.get_or_insert_with(|| time.clone());
+    let last_update = fixed.last_update().unwrap_or_else(|| fixed.startup());
+    fixed.update_with_instant(last_update + step);
+    *time = fixed.clone();
+}
+
+// The whole schedule runs once more for every tick to catch up on.
+fn frame(mut clock: ResMut<SimClock>, mut started: Local<bool>) -> ShouldRun {
+    if !*started {
+        *started = true;
+        return ShouldRun::YesAndCheckAgain;
+    }
+    if clock.catch_up > 0 {
+        clock.catch_up -= 1;
+        clock.catching_up = true;
+        return ShouldRun::YesAndCheckAgain;
+    }
+    *started = false;
+    clock.catching_up = false;
+    clock.quiet = false;
+    return ShouldRun::No;
+}
+
+// Drops the sounds of the ticks that are not to be heard before they get played.
+fn hush(clock: Res<SimClock>, mut audio: ResMut<Audio>) {
+    if clock.catching_up && clock.quiet {
+        *audio = Audio::default();
+    }
+}
+
+fn not_stalled(clock: Res<SimClock>) -> ShouldRun {
+    if clock.stalled {
+        return ShouldRun::No;
+    }
+    return ShouldRun::Yes;
+}
@@ -0,0 +1,242 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+use crate::anim::AssetsLoading;
+use crate::background::MusicCue;
+use crate::boss::{self, Boss, BossLibrary, Bosses};
+use crate::data::RonLoader;
+use crate::difficulty::{Difficulty, Levels};
+use crate::enemy::{self, Archetypes, Enemy, EnemyArchetypes, EnemyGraphics};
+use crate::pattern::{MovementPattern, PatternLibrary, Patterns, Side};
+use crate::sim::SimRng;
+use crate::state::GameState;
+use bevy::prelude::*;
+use bevy::reflect::TypeUuid;
+use serde::{Deserialize, Serialize};
+
+pub struct StagePlugin;
+
+impl Plugin for StagePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<Stage>()
+            .add_asset_loader(RonLoader::<Stage>::new(&["stage.ron"]))
+            .add_startup_system_to_stage(StartupStage::PreStartup, load_assets)
+            .insert_resource(GameMode::Stage)
+            .insert_resource(StageRunner::new(GameMode::Stage, 1.0))
+            .add_system_set(SystemSet::on_enter(GameState::TitleFlyOut).with_system(reset))
+            .add_system_set(SystemSet::on_update(GameState::Game).with_system(run));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GameMode {
+    Stage,
+    Endless,
+}
+
+impl GameMode {
+    pub fn toggle(&mut self) {
+        *self = match self {
+            GameMode::Stage => GameMode::Endless,
+            GameMode::Endless => GameMode::Stage,
+        };
+    }
+
+    pub fn title(&self) -> &'static str {
+        return match self {
+            GameMode::Stage => "STAGE",
+            GameMode::Endless => "ENDLESS",
+        };
+    }
+}
+
+#[derive(Deserialize)]
+pub enum StageEvent {
+    Spawn {
+        archetype: String,
+        pattern: String,
+        side: Side,
+        pos: f32,
+    },
+    Formation {
+        archetype: String,
+        formation: String,
+        side: Side,
+        pos: f32,
+    },
+    Wait(f32),
+    WaitClear,
+    Boss(String),
+    Music(String),
+    StopMusic,
+}
+
+#[derive(Deserialize, TypeUuid)]
+#[uuid = "b5e2c7a4-1d3f-4a6b-9c8e-0f7a2d4b6e13"]
+pub struct Stage {
+    events: Vec<StageEvent>,
+}
+
+pub struct CurrentStage(pub Handle<Stage>);
+
+#[derive(Clone)]
+pub struct StageRunner {
+    mode: GameMode,
+    cursor: usize,
+    wait: f32,
+    wait_scale: f32,
+    waiting_for_clear: bool,
+    finished: bool,
+}
+
+impl StageRunner {
+    fn new(mode: GameMode, wait_scale: f32) -> Self {
+        return Self {
+            mode,
+            cursor: 0,
+            wait: 0.0,
+            wait_scale,
+            waiting_for_clear: false,
+            finished: false,
+        };
+    }
+
+    // How long until the next stage event.
+    pub fn wait(&self) -> f32 {
+        return self.wait;
+    }
+
+    // The random spawner takes over when the stage script runs out.
+    pub fn is_endless(&self) -> bool {
+        return self.mode == GameMode::Endless || self.finished;
+    }
+}
+
+fn load_assets(
+    mut commands: Commands,
+    assets: Res<AssetServer>,
+    mut loading: ResMut<AssetsLoading>,
+) {
+    let handle = assets.load("stages/1.stage.ron");
+    loading.push(handle.clone_untyped());
+    commands.insert_resource(CurrentStage(handle));
+}
+
+// What the stage waits for when it waits for the field to be cleared.
+type Foes = Or<(With<Enemy>, With<Boss>)>;
+
+fn reset(mut runner: ResMut<StageRunner>, mode: Res<GameMode>, difficulty: Res<Difficulty>) {
+    *runner = StageRunner::new(*mode, difficulty.preset().spawn_interval);
+}
+
+pub fn run(
+    mut commands: Commands,
+    (mut runner, time): (ResMut<StageRunner>, Res<Time>),
+    (stage, stages): (Res<CurrentStage>, Res<Assets<Stage>>),
+    (patterns, pattern_libraries): (Res<Patterns>, Res<Assets<PatternLibrary>>),
+    (archetypes, archetype_sheets, graphics): (
+        Res<Archetypes>,
+        Res<Assets<EnemyArchetypes>>,
+        Res<EnemyGraphics>,
+    ),
+    (bosses, boss_libraries, enemies): (Res<Bosses>, Res<Assets<BossLibrary>>, Query<(), Foes>),
+    (levels, mut rng, win, mut music): (
+        Res<Levels>,
+        ResMut<SimRng>,
+        Res<WindowDescriptor>,
+        EventWriter<MusicCue>,
+    ),
+) {
+    if runner.is_endless() {
+        return;
+    }
+
+    let stage = stages.get(&stage.0).unwrap();
+    let library = pattern_libraries.get(&patterns.0).unwrap();
+    let archetypes = archetype_sheets.get(&archetypes.0).unwrap();
+    let bosses = boss_libraries.get(&bosses.0).unwrap();
+
+    runner.wait -= time.delta_seconds();
+
+    loop {
+        if runner.wait > 0.0 {
+            return;
+        }
+
+        if runner.waiting_for_clear {
+            if !enemies.is_empty() {
+                return;
+            }
+            runner.waiting_for_clear = false;
+            runner.wait = 0.0;
+        }
+
+        let event = match stage.events.get(runner.cursor) {
+            Some(event) => event,
+            None => {
+                runner.finished = true;
+                return;
+            }
+        };
+        runner.cursor += 1;
+
+        match event {
+            StageEvent::Spawn {
+                archetype,
+                pattern,
+                side,
+                pos,
+            } => {
+                let archetype = archetypes.get(archetype);
+                if let Some(pattern) = library.pattern(pattern) {
+                    let origin = side.entry_point(*pos, archetype.size(), &win);
+                    let movement = MovementPattern::new(pattern, *side, origin);
+                    enemy::spawn(
+                        &mut commands,
+                        &graphics,
+                        &archetype,
+                        movement,
+                        &levels,
+                        &mut *rng,
+                    );
+                    // Spawned enemies are not in the query until the next frame.
+                    return;
+                }
+            }
+            StageEvent::Formation {
+                archetype,
+                formation,
+                side,
+                pos,
+            } => {
+                let archetype = archetypes.get(archetype);
+                if let Some(formation) = library.formation(formation) {
+                    let origin = side.entry_point(*pos, archetype.size(), &win);
+                    for movement in library.formation_members(formation, *side, origin, &win) {
+                        enemy::spawn(
+                            &mut commands,
+                            &graphics,
+                            &archetype,
+                            movement,
+                            &levels,
+                            &mut *rng,
+                        );
+                    }
+                    return;
+                }
+            }
+            StageEvent::Wait(secs) => runner.wait += secs * runner.wait_scale,
+            StageEvent::WaitClear => runner.waiting_for_clear = true,
+            StageEvent::Boss(name) => {
+                if let Some(def) = bosses.get(name) {
+                    boss::spawn(&mut commands, &graphics, def, library, &win);
+                    runner.waiting_for_clear = true;
+                    // Otherwise the boss is not there yet and the wait ends right away.
+                    return;
+                }
+            }
+            StageEvent::Music(name) => music.send(MusicCue::Play(name.clone())),
+            StageEvent::StopMusic => music.send(MusicCue::Stop),
+        }
+    }
+}
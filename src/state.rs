@@ -8,6 +8,8 @@ pub enum GameState {
     TitleFlyIn,
     TitleInstructionsFlyIn,
     Title,
+    Lobby,
+    Replays,
     TitleFlyOut,
 
     PlayerSlideOut,
@@ -16,4 +18,6 @@ pub enum GameState {
     GameOver,
     GameOverWaitingForEmptyField,
     GameOverWaitingForTimer,
+
+    ReplayRewind,
 }
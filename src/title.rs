@@ -2,10 +2,15 @@
 // 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
 
 use crate::audio::AudioTrack;
+use crate::bullet::BulletCancel;
 use crate::collision::Screen;
+use crate::difficulty::Difficulty;
+use crate::ghost::Ghost;
+use crate::netplay::Netplay;
+use crate::stage::GameMode;
 use crate::{
     anim::{AnimPlugin, AssetsLoading, MainFont},
-    score::Score,
+    score::{HighScores, Score},
     state::GameState,
 };
 use bevy::{audio::AudioSink, prelude::*};
@@ -28,12 +33,34 @@ impl Plugin for TitlePlugin {
                     .with_system(instructions_fly_in)
                     .with_system(animate_title),
             )
+            .add_system_set(SystemSet::on_enter(GameState::Lobby).with_system(hide_action_text))
+            .add_system_set(
+                SystemSet::on_enter(GameState::Replays)
+                    .with_system(hide_action_text)
+                    .with_system(hide_settings)
+                    .with_system(hide_instructions),
+            )
+            .add_system_set(
+                SystemSet::on_exit(GameState::Replays).with_system(restore_instructions),
+            )
+            .add_system_set(
+                SystemSet::on_enter(GameState::TitleFlyOut)
+                    .with_system(hide_settings)
+                    .with_system(reset_timer)
+                    .with_system(play_start_audio),
+            )
             .add_system_set(SystemSet::on_update(GameState::TitleFlyOut).with_system(fly_out))
-            .add_system_set(SystemSet::on_enter(GameState::Title).with_system(show_action_text))
+            .add_system_set(
+                SystemSet::on_enter(GameState::Title)
+                    .with_system(show_action_text)
+                    .with_system(show_settings),
+            )
             .add_system_set(
                 SystemSet::on_update(GameState::Title)
                     .with_system(animate_title)
                     .with_system(animate_action_text)
+                    .with_system(change_settings)
+                    .with_system(update_settings_text.after(change_settings))
                     .with_system(wait_for_enter),
             );
     }
@@ -44,6 +71,7 @@ const SHADOW_RADIUS: f32 = 0.0035;
 const SHADOW_ALPHA: f32 = 0.5;
 const SHADOW_SPEED: f32 = 10.0;
 const SHADOW_OUTER_RADIUS: f32 = 1.5;
+const SETTINGS_ROWS: usize = 4;
 
 #[derive(Component)]
 pub struct TitleShadow {
@@ -99,6 +127,20 @@ pub struct TitleInstructions;
 #[derive(Component)]
 pub struct TitleActionText;
 
+#[derive(Component)]
+pub struct TitleSettings {
+    selected: usize,
+}
+
+impl TitleSettings {
+    fn row(&self, index: usize, label: &str, value: &str) -> String {
+        if index == self.selected {
+            return format!("{}: < {} >", label, value);
+        }
+        return format!("{}: {}", label, value);
+    }
+}
+
 #[derive(Component, Deref, DerefMut)]
 pub struct TitleTimer(pub Timer);
 
@@ -129,7 +171,7 @@ fn setup(
 
     let instructions = AnimPlugin::text_bundle(
         &font.0,
-        "WASD - MOVEMENT\nM - ATTACK",
+        "WASD - MOVEMENT\nM - ATTACK\nN - MISSILE\nSHIFT - FOCUS\nPLAYER 2: ARROWS, RIGHT CTRL, RIGHT ALT, RIGHT SHIFT",
         50.0,
         Vec3::new(win.middle_x(), win.middle_y() - 0.25, 0.1),
     );
@@ -149,6 +191,17 @@ fn setup(
         .insert(TitleActionText)
         .insert(Name::new("ActionText"));
 
+    let settings_text = AnimPlugin::text_bundle(
+        &font.0,
+        "",
+        40.0,
+        Vec3::new(win.middle_x(), win.middle_y() + 0.05, 0.1),
+    );
+    commands
+        .spawn_bundle(settings_text)
+        .insert(TitleSettings { selected: 0 })
+        .insert(Name::new("Settings"));
+
     let action_audio = assets.load("start.ogg");
     loading.push(action_audio.clone_untyped());
     commands.insert_resource(TitleActionAudio(action_audio));
@@ -179,11 +232,37 @@ fn show_instructions(mut q: Query<(&mut Text, &mut Visibility), With<TitleInstru
     text.sections.first_mut().unwrap().style.color.set_a(0.0);
 }
 
+fn hide_instructions(mut q: Query<&mut Visibility, With<TitleInstructions>>) {
+    q.single_mut().is_visible = false;
+}
+
+// Unlike show_instructions, without flying them in again.
+fn restore_instructions(mut q: Query<&mut Visibility, With<TitleInstructions>>) {
+    q.single_mut().is_visible = true;
+}
+
 fn show_action_text(mut q: Query<&mut Visibility, With<TitleActionText>>) {
     let mut visibility = q.single_mut();
     visibility.is_visible = true;
 }
 
+fn show_settings(mut q: Query<&mut Visibility, With<TitleSettings>>) {
+    q.single_mut().is_visible = true;
+}
+
+fn hide_settings(mut q: Query<&mut Visibility, With<TitleSettings>>) {
+    q.single_mut().is_visible = false;
+}
+
+fn hide_action_text(mut q: Query<&mut Visibility, With<TitleActionText>>) {
+    q.single_mut().is_visible = false;
+}
+
+// The fly-out must take the same number of ticks every time, see SimClock.
+fn reset_timer(mut q: Query<&mut TitleTimer>) {
+    q.single_mut().reset();
+}
+
 fn start_audio(
     mut bg_audio: ResMut<TitleAudio>,
     audio: Res<Audio>,
@@ -316,22 +395,82 @@ fn animate_action_text(mut q: Query<&mut Text, With<TitleActionText>>, time: Res
         .set_g(1.0 - r as f32);
 }
 
+pub fn change_settings(
+    kbd: Res<Input<KeyCode>>,
+    mut q: Query<&mut TitleSettings>,
+    mut mode: ResMut<GameMode>,
+    mut difficulty: ResMut<Difficulty>,
+    mut cancel: ResMut<BulletCancel>,
+    mut ghost: ResMut<Ghost>,
+) {
+    let mut settings = q.single_mut();
+
+    if kbd.just_pressed(KeyCode::Up) {
+        settings.selected = (settings.selected + SETTINGS_ROWS - 1) % SETTINGS_ROWS;
+    }
+    if kbd.just_pressed(KeyCode::Down) {
+        settings.selected = (settings.selected + 1) % SETTINGS_ROWS;
+    }
+
+    let left = kbd.just_pressed(KeyCode::Left);
+    let right = kbd.just_pressed(KeyCode::Right);
+    match settings.selected {
+        0 if left || right => mode.toggle(),
+        1 if left => difficulty.prev(),
+        1 if right => difficulty.next(),
+        2 if left || right => cancel.toggle(),
+        3 if left || right => ghost.toggle(),
+        _ => {}
+    }
+}
+
+pub fn update_settings_text(
+    mut q: Query<(&TitleSettings, &mut Text)>,
+    mode: Res<GameMode>,
+    difficulty: Res<Difficulty>,
+    cancel: Res<BulletCancel>,
+    ghost: Res<Ghost>,
+    high_scores: Res<HighScores>,
+) {
+    let (settings, mut text) = q.single_mut();
+
+    let value = [
+        settings.row(0, "MODE", mode.title()),
+        settings.row(1, "DIFFICULTY", difficulty.title()),
+        settings.row(2, "BULLET CANCEL", cancel.title()),
+        settings.row(3, "GHOST", ghost.title()),
+        format!("HIGH SCORE: {}", high_scores.get(*difficulty)),
+    ]
+    .join("\n");
+
+    if text.sections.first().unwrap().value != value {
+        text.sections.first_mut().unwrap().value = value;
+    }
+}
+
 fn wait_for_enter(
     mut kbd: ResMut<Input<KeyCode>>,
     mut state: ResMut<State<GameState>>,
-    action_audio: Res<TitleActionAudio>,
-    audio: Res<Audio>,
-    audio_sinks: Res<Assets<AudioSink>>,
-    bg_audio: Res<TitleAudio>,
     mut score: ResMut<Score>,
+    netplay: Option<Res<Netplay>>,
 ) {
     if kbd.just_pressed(KeyCode::Return) {
-        bg_audio.stop(&audio_sinks);
-
-        audio.play_with_settings(action_audio.0.clone(), PlaybackSettings { ..default() });
-
         score.clear();
-        state.set(GameState::TitleFlyOut).unwrap();
+        if netplay.is_some() {
+            state.set(GameState::Lobby).unwrap();
+        } else {
+            state.set(GameState::TitleFlyOut).unwrap();
+        }
         kbd.clear();
     }
 }
+
+fn play_start_audio(
+    action_audio: Res<TitleActionAudio>,
+    audio: Res<Audio>,
+    audio_sinks: Res<Assets<AudioSink>>,
+    bg_audio: Res<TitleAudio>,
+) {
+    bg_audio.stop(&audio_sinks);
+    audio.play_with_settings(action_audio.0.clone(), PlaybackSettings { ..default() });
+}
@@ -0,0 +1,162 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+// "--verify-replay FILE" plays the replay without a window or sound, as fast as it goes,
+// and tells whether it still ends the way it was recorded:
+// with the same score, on the same tick, and with the same checksums along the way.
+// The verdict is printed as a single line of RON. The exit code is 0 if everything matches,
+// 1 if something does not, and 2 if the replay cannot be played at all.
+
+use crate::replay::{self, Playback, Replay};
+use crate::score::Score;
+use crate::sim::SimClock;
+use crate::GameState;
+use bevy::asset::AssetPlugin;
+use bevy::audio::{AudioLoader, AudioSink};
+use bevy::core::CorePlugin;
+use bevy::hierarchy::HierarchyPlugin;
+use bevy::input::InputPlugin;
+use bevy::prelude::*;
+use bevy::render::texture::ImagePlugin;
+use bevy::text::FontLoader;
+use bevy::time::TimePlugin;
+use bevy::transform::TransformPlugin;
+use bevy::window::Windows;
+use serde::Serialize;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const LOAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Default)]
+struct Verdict {
+    file: String,
+    ok: bool,
+    // Why the replay could not be played.
+    error: Option<String>,
+    version: String,
+    score: u32,
+    recorded_score: u32,
+    // The tick the game was over on.
+    end_tick: Option<u64>,
+    recorded_end_tick: u64,
+    // The first tick that played out differently than recorded.
+    desync_tick: Option<u64>,
+}
+
+struct Verification {
+    // Until the playback takes it.
+    replay: Option<Replay>,
+    end_tick: Option<u64>,
+    done: bool,
+}
+
+// Returns the exit code.
+pub fn run(path: &Path) -> i32 {
+    let mut verdict = Verdict {
+        file: path.display().to_string(),
+        ..default()
+    };
+    let replay = match Replay::load_file(path) {
+        Ok(replay) => replay,
+        Err(e) => {
+            verdict.error = Some(e);
+            return report(&verdict);
+        }
+    };
+    verdict.version = replay.version.clone();
+    verdict.recorded_score = replay.score;
+    verdict.recorded_end_tick = replay.end_tick;
+
+    let mut app = headless_app();
+    app.insert_resource(Verification {
+        replay: Some(replay),
+        end_tick: None,
+        done: false,
+    })
+    .add_system_to_stage(CoreStage::Last, drive.before(replay::pace));
+
+    let started = Instant::now();
+    loop {
+        app.update();
+        let verification = app.world.resource::<Verification>();
+        if verification.done {
+            break;
+        }
+        if verification.replay.is_some() && started.elapsed() > LOAD_TIMEOUT {
+            verdict.error = Some("cannot load the assets".to_string());
+            return report(&verdict);
+        }
+    }
+
+    let playback = app.world.resource::<Playback>();
+    verdict.desync_tick = playback.desync.map(|tick| tick as u64);
+    verdict.end_tick = app.world.resource::<Verification>().end_tick;
+    verdict.score = app.world.resource::<Score>().score;
+    verdict.ok = verdict.desync_tick.is_none()
+        && verdict.end_tick == Some(verdict.recorded_end_tick)
+        && verdict.score == verdict.recorded_score;
+    return report(&verdict);
+}
+
+// The whole game without a window or sound.
+pub fn headless_app() -> App {
+    let mut app = App::new();
+    app.insert_resource(crate::window())
+        .insert_resource(Windows::default())
+        .add_plugin(CorePlugin)
+        .add_plugin(TimePlugin)
+        .add_plugin(TransformPlugin)
+        .add_plugin(HierarchyPlugin)
+        .add_plugin(InputPlugin)
+        .add_plugin(AssetPlugin)
+        .add_plugin(ImagePlugin)
+        .add_asset::<TextureAtlas>()
+        .add_asset::<Font>()
+        .init_asset_loader::<FontLoader>()
+        .add_asset::<AudioSource>()
+        .init_asset_loader::<AudioLoader>()
+        .add_asset::<AudioSink>()
+        .init_resource::<Audio>();
+    crate::add_game(&mut app);
+    return app;
+}
+
+fn report(verdict: &Verdict) -> i32 {
+    println!("{}", ron::to_string(verdict).unwrap());
+    if verdict.ok {
+        return 0;
+    }
+    if verdict.error.is_some() {
+        return 2;
+    }
+    return 1;
+}
+
+fn drive(
+    mut commands: Commands,
+    mut verification: ResMut<Verification>,
+    playback: Option<ResMut<Playback>>,
+    state: Res<State<GameState>>,
+    clock: Res<SimClock>,
+) {
+    let mut playback = match playback {
+        Some(playback) => playback,
+        None => {
+            if *state.current() != GameState::Loading {
+                if let Some(replay) = verification.replay.take() {
+                    commands.insert_resource(Playback::new(replay));
+                }
+            }
+            return;
+        }
+    };
+
+    let simulated = !playback.is_rewinding() && !clock.stalled;
+    if simulated && *state.current() == GameState::GameOver && verification.end_tick.is_none() {
+        verification.end_tick = Some(clock.tick);
+    }
+    // The run may also go on for longer than recorded, or end sooner.
+    verification.done = verification.end_tick.is_some() || playback.is_finished();
+    playback.advance = true;
+}
@@ -0,0 +1,625 @@
+// SPDX-License-Identifier: GPL-3.0-only
+// 🄯 2022, Alexey Parfenov <zxed@alkatrazstudio.net>
+
+// Watching the saved replays, opened from the title.
+// The replay is simulated again from the start (see Playback), as many ticks per frame as it takes.
+// The run is saved every now and then while watching (see rollback.rs), so seeking back
+// goes on from the closest snapshot before the time and fast-forwards the rest without sound.
+// Only with no snapshot to go back to does the replay start over.
+
+use crate::anim::{AnimPlugin, MainFont};
+use crate::collision::Screen;
+use crate::game_over::{self, FieldQueries};
+use crate::net::NetInput;
+use crate::netplay::Netplay;
+use crate::player::MAX_PLAYERS;
+use crate::replay::{self, Playback, Replay};
+use crate::rollback::Snapshots;
+use crate::sim::{NextSeed, SimClock};
+use crate::GameState;
+use bevy::prelude::*;
+use bevy::window::PresentMode;
+use std::time::Instant;
+
+pub struct ViewerPlugin;
+
+impl Plugin for ViewerPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(ReplayList::default())
+            .insert_resource(Viewer::default())
+            .add_startup_system(setup)
+            .add_system_set(SystemSet::on_enter(GameState::Title).with_system(show_hint))
+            .add_system_set(SystemSet::on_update(GameState::Title).with_system(open))
+            .add_system_set(SystemSet::on_exit(GameState::Title).with_system(hide_hint))
+            .add_system_set(SystemSet::on_enter(GameState::Replays).with_system(show_list))
+            .add_system_set(SystemSet::on_update(GameState::Replays).with_system(choose))
+            .add_system_set(SystemSet::on_exit(GameState::Replays).with_system(hide_list))
+            .add_system_to_stage(CoreStage::Last, control.before(replay::pace))
+            .add_system_to_stage(CoreStage::Last, exit.after(replay::pace))
+            .add_system_to_stage(CoreStage::Last, update_hud.after(exit))
+            .add_system_to_stage(CoreStage::Last, update_keys.after(exit));
+    }
+}
+
+const LIST_ROWS: usize = 7;
+const SPEEDS: [(KeyCode, f64); 4] = [
+    (KeyCode::Key1, 0.5),
+    (KeyCode::Key2, 1.0),
+    (KeyCode::Key3, 2.0),
+    (KeyCode::Key4, 4.0),
+];
+const SEEK_STEP: f64 = 10.0;
+const SEEK_LONG_STEP: f64 = 60.0;
+// Beyond that the playback just gets slower instead of catching up.
+const MAX_OWED: f64 = 0.1;
+// The most ticks simulated in one frame, so that seeking far still shows how it goes.
+const MAX_TICKS_PER_FRAME: usize = 300;
+// The run is saved after every this many ticks, i.e. every 10 seconds.
+const SNAPSHOT_PERIOD: u64 = 600;
+const KEYS: [(u8, &str); 7] = [
+    (NetInput::UP, "UP"),
+    (NetInput::DOWN, "DOWN"),
+    (NetInput::LEFT, "LEFT"),
+    (NetInput::RIGHT, "RIGHT"),
+    (NetInput::FIRE, "FIRE"),
+    (NetInput::FOCUS, "FOCUS"),
+    (NetInput::MISSILE, "MISSILE"),
+];
+const KEY_UP_ALPHA: f32 = 0.2;
+const HUD_TEXT_SIZE: f32 = 25.0;
+
+#[derive(Component)]
+struct HintText;
+
+#[derive(Component)]
+struct ListText;
+
+#[derive(Component)]
+struct HudText;
+
+#[derive(Component)]
+struct KeysText;
+
+#[derive(Default)]
+struct ReplayList {
+    names: Vec<String>,
+    selected: usize,
+}
+
+impl ReplayList {
+    fn load() -> Self {
+        let mut names = Replay::list();
+        // The best runs first, then the newest ones.
+        names.sort_by_key(|name| (!name.starts_with("best-"), std::cmp::Reverse(name.clone())));
+        return Self { names, selected: 0 };
+    }
+
+    fn text(&self) -> String {
+        if self.names.is_empty() {
+            return "NO REPLAYS YET\n\nESC - BACK".to_string();
+        }
+        let first = self
+            .selected
+            .saturating_sub(LIST_ROWS / 2)
+            .min(self.names.len().saturating_sub(LIST_ROWS));
+        let rows: Vec<String> = (first..self.names.len().min(first + LIST_ROWS))
+            .map(|index| {
+                let title = title(&self.names[index]);
+                if index == self.selected {
+                    return format!("< {} >", title);
+                }
+                return title;
+            })
+            .collect();
+        return format!(
+            "REPLAYS\n\n{}\n\nENTER - WATCH\nESC - BACK",
+            rows.join("\n")
+        );
+    }
+}
+
+// E.g. "1792368120.ron" is "2026-10-19 14:02 UTC",
+// and "best-stages-normal-off.ron" is "BEST: STAGES NORMAL OFF".
+fn title(name: &str) -> String {
+    let name = name.trim_end_matches(".ron");
+    if let Some(settings) = name.strip_prefix("best-") {
+        return format!("BEST: {}", settings.replace('-', " ").to_uppercase());
+    }
+    return match name.parse::<u64>() {
+        Ok(secs) => date(secs),
+        Err(_) => name.to_uppercase(),
+    };
+}
+
+// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn date(secs: u64) -> String {
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    return format!(
+        "{}-{:02}-{:02} {:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        secs % 86400 / 3600,
+        secs % 3600 / 60
+    );
+}
+
+fn clock_time(secs: f64) -> String {
+    return format!("{}:{:04.1}", (secs / 60.0) as u32, secs % 60.0);
+}
+
+// How the replay is being watched.
+struct Viewer {
+    speed: f64,
+    paused: bool,
+    // The tick to fast-forward to.
+    seek: Option<usize>,
+    // Real time not played yet.
+    owed: f64,
+    last_frame: Option<Instant>,
+}
+
+impl Default for Viewer {
+    fn default() -> Self {
+        return Self {
+            speed: 1.0,
+            paused: false,
+            seek: None,
+            owed: 0.0,
+            last_frame: None,
+        };
+    }
+}
+
+fn setup(mut commands: Commands, font: Res<MainFont>, win: Res<WindowDescriptor>) {
+    let hint = AnimPlugin::text_bundle(
+        &font.0,
+        "R - REPLAYS",
+        30.0,
+        Vec3::new(win.middle_x(), 0.05, 0.1),
+    );
+    commands
+        .spawn_bundle(hint)
+        .insert(HintText)
+        .insert(Name::new("ReplaysHint"));
+
+    let list = AnimPlugin::text_bundle(&font.0, "", 40.0, win.middle_with_z(0.1));
+    commands
+        .spawn_bundle(list)
+        .insert(ListText)
+        .insert(Name::new("ReplayList"));
+
+    let mut hud = AnimPlugin::text_bundle(
+        &font.0,
+        "",
+        HUD_TEXT_SIZE,
+        Vec3::new(win.middle_x(), 0.01, 0.1),
+    );
+    hud.text.alignment.vertical = VerticalAlign::Bottom;
+    commands
+        .spawn_bundle(hud)
+        .insert(HudText)
+        .insert(Name::new("ReplayHud"));
+
+    let mut keys = AnimPlugin::text_bundle(&font.0, "", HUD_TEXT_SIZE, Vec3::new(0.01, 0.01, 0.1));
+    keys.text.alignment.horizontal = HorizontalAlign::Left;
+    keys.text.alignment.vertical = VerticalAlign::Bottom;
+    let style = keys.text.sections[0].style.clone();
+    keys.text.sections.clear();
+    for index in 0..MAX_PLAYERS {
+        let label = match index {
+            0 => format!("P{} ", index + 1),
+            _ => format!("\nP{} ", index + 1),
+        };
+        keys.text
+            .sections
+            .push(TextSection::new(label, style.clone()));
+        for (_, name) in KEYS {
+            keys.text
+                .sections
+                .push(TextSection::new(format!("{} ", name), style.clone()));
+        }
+    }
+    commands
+        .spawn_bundle(keys)
+        .insert(KeysText)
+        .insert(Name::new("ReplayKeys"));
+}
+
+fn show_hint(mut q: Query<&mut Visibility, With<HintText>>, netplay: Option<Res<Netplay>>) {
+    q.single_mut().is_visible = netplay.is_none();
+}
+
+fn hide_hint(mut q: Query<&mut Visibility, With<HintText>>) {
+    q.single_mut().is_visible = false;
+}
+
+fn open(
+    mut kbd: ResMut<Input<KeyCode>>,
+    mut state: ResMut<State<GameState>>,
+    netplay: Option<Res<Netplay>>,
+) {
+    if netplay.is_none() && kbd.just_pressed(KeyCode::R) {
+        state.set(GameState::Replays).unwrap();
+        kbd.clear();
+    }
+}
+
+fn show_list(
+    mut list: ResMut<ReplayList>,
+    mut q: Query<(&mut Visibility, &mut Text), With<ListText>>,
+) {
+    *list = ReplayList::load();
+    let (mut visibility, mut text) = q.single_mut();
+    visibility.is_visible = true;
+    text.sections.first_mut().unwrap().value = list.text();
+}
+
+fn hide_list(mut q: Query<&mut Visibility, With<ListText>>) {
+    q.single_mut().is_visible = false;
+}
+
+fn choose(
+    mut commands: Commands,
+    mut kbd: ResMut<Input<KeyCode>>,
+    mut state: ResMut<State<GameState>>,
+    mut list: ResMut<ReplayList>,
+    (mut viewer, mut snapshots): (ResMut<Viewer>, ResMut<Snapshots>),
+    mut windows: ResMut<Windows>,
+    mut q: Query<&mut Text, With<ListText>>,
+) {
+    if kbd.just_pressed(KeyCode::Escape) {
+        state.set(GameState::Title).unwrap();
+        kbd.clear();
+        return;
+    }
+
+    let count = list.names.len();
+    if count > 0 && kbd.just_pressed(KeyCode::Up) {
+        list.selected = (list.selected + count - 1) % count;
+    }
+    if count > 0 && kbd.just_pressed(KeyCode::Down) {
+        list.selected = (list.selected + 1) % count;
+    }
+    let value = list.text();
+    if q.single().sections.first().unwrap().value != value {
+        q.single_mut().sections.first_mut().unwrap().value = value;
+    }
+
+    if count == 0 || !kbd.just_pressed(KeyCode::Return) {
+        return;
+    }
+    let name = &list.names[list.selected];
+    let replay = match Replay::load(name) {
+        Some(replay) => replay,
+        None => {
+            error!("replay: cannot load {}", name);
+            return;
+        }
+    };
+    if replay.version != env!("CARGO_PKG_VERSION") {
+        warn!(
+            "replay: {} was recorded with version {}, it may play out differently",
+            name, replay.version
+        );
+    }
+    commands.insert_resource(Playback::new(replay));
+    *viewer = Viewer::default();
+    snapshots.start(SNAPSHOT_PERIOD, None);
+    // Faster than 1x takes more frames than the display shows.
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_present_mode(PresentMode::AutoNoVsync);
+    }
+    kbd.clear();
+}
+
+fn control(
+    kbd: Res<Input<KeyCode>>,
+    playback: Option<ResMut<Playback>>,
+    (mut viewer, mut snapshots): (ResMut<Viewer>, ResMut<Snapshots>),
+    (mut clock, mut state): (ResMut<SimClock>, ResMut<State<GameState>>),
+) {
+    let mut playback = match playback {
+        Some(playback) => playback,
+        None => return,
+    };
+    // How many ticks the frame takes is decided when it starts.
+    if clock.catching_up {
+        playback.advance = clock.catch_up > 0;
+        return;
+    }
+
+    let now = Instant::now();
+    let real = viewer
+        .last_frame
+        .replace(now)
+        .map_or(0.0, |last| (now - last).as_secs_f64());
+
+    if kbd.just_pressed(KeyCode::Space) {
+        viewer.paused = !viewer.paused;
+    }
+    for (key, speed) in SPEEDS {
+        if kbd.just_pressed(key) {
+            viewer.speed = speed;
+        }
+    }
+
+    let seek_by = if kbd.just_pressed(KeyCode::Left) {
+        Some(-SEEK_STEP)
+    } else if kbd.just_pressed(KeyCode::Right) {
+        Some(SEEK_STEP)
+    } else if kbd.just_pressed(KeyCode::PageDown) {
+        Some(-SEEK_LONG_STEP)
+    } else if kbd.just_pressed(KeyCode::PageUp) {
+        Some(SEEK_LONG_STEP)
+    } else if kbd.just_pressed(KeyCode::Home) {
+        Some(f64::NEG_INFINITY)
+    } else {
+        None
+    };
+    if let Some(seek_by) = seek_by {
+        // Seeking again before getting there goes on from where it was headed.
+        let from = match viewer.seek {
+            Some(tick) => playback.time_at(tick),
+            None => playback.time(),
+        };
+        let tick = playback.ticks_until((from + seek_by).max(0.0));
+        seek(&mut playback, &mut snapshots, &mut state, tick);
+        viewer.seek = Some(tick);
+    }
+
+    let ticks = match viewer.seek {
+        _ if playback.is_rewinding() => 0,
+        Some(tick) if playback.tick < tick => {
+            clock.quiet = true;
+            tick - playback.tick
+        }
+        _ if viewer.paused || playback.is_finished() => {
+            viewer.seek = None;
+            viewer.owed = 0.0;
+            kbd.just_pressed(KeyCode::Period) as usize
+        }
+        _ => {
+            viewer.seek = None;
+            viewer.owed = (viewer.owed + real * viewer.speed).min(MAX_OWED);
+            let mut ticks = 0;
+            while let Some(tick) = playback.replay.ticks.get(playback.tick + ticks) {
+                let step = tick.step as f64 / 1e9;
+                if viewer.owed < step {
+                    break;
+                }
+                viewer.owed -= step;
+                ticks += 1;
+            }
+            ticks
+        }
+    };
+    // The ticks are simulated in the rest of the frame, see SimClock.
+    clock.catch_up = ticks.min(MAX_TICKS_PER_FRAME) as u32;
+    playback.advance = clock.catch_up > 0;
+}
+
+// Goes on from the closest snapshot before the tick, when it saves simulating from where the run is.
+// Going back without one starts the run over.
+fn seek(
+    playback: &mut Playback,
+    snapshots: &mut Snapshots,
+    state: &mut State<GameState>,
+    tick: usize,
+) {
+    let back = tick < playback.tick;
+    let last = match state.current() {
+        _ if playback.is_rewinding() => None,
+        GameState::Game => tick.checked_sub(1),
+        // Getting back into the game from the end takes a tick.
+        GameState::GameOver => tick.checked_sub(2),
+        _ => None,
+    };
+    let snapshot = last
+        .and_then(|last| snapshots.latest(last as u64))
+        .filter(|snapshot| back || *snapshot as usize >= playback.tick);
+    match snapshot {
+        Some(snapshot) => {
+            snapshots.restore(snapshot);
+            playback.restore(snapshot as usize + 1);
+            if *state.current() == GameState::GameOver {
+                state.set(GameState::Game).unwrap();
+            }
+        }
+        None if back => playback.rewind(),
+        None => {}
+    }
+}
+
+fn exit(
+    mut commands: Commands,
+    mut kbd: ResMut<Input<KeyCode>>,
+    playback: Option<Res<Playback>>,
+    (mut state, mut clock, mut next_seed): (
+        ResMut<State<GameState>>,
+        ResMut<SimClock>,
+        ResMut<NextSeed>,
+    ),
+    (mut windows, win): (ResMut<Windows>, Res<WindowDescriptor>),
+    (field, mut snapshots): (FieldQueries, ResMut<Snapshots>),
+) {
+    if playback.is_none() || !kbd.just_pressed(KeyCode::Escape) {
+        return;
+    }
+
+    game_over::clear_field(&mut commands, &field);
+    commands.remove_resource::<Playback>();
+    snapshots.stop();
+    clock.unfix();
+    clock.stalled = false;
+    clock.catch_up = 0;
+    next_seed.0 = None;
+    // Replaces whatever the playback has set this frame.
+    state.overwrite_set(GameState::TitleFlyIn).unwrap();
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_present_mode(win.present_mode);
+    }
+    kbd.clear();
+}
+
+fn update_hud(
+    playback: Option<Res<Playback>>,
+    viewer: Res<Viewer>,
+    mut q: Query<(&mut Visibility, &mut Text), With<HudText>>,
+) {
+    let (mut visibility, mut hud) = q.single_mut();
+    visibility.is_visible = playback.is_some();
+    let playback = match playback {
+        Some(playback) => playback,
+        None => return,
+    };
+
+    let status = if playback.is_rewinding() || viewer.seek.is_some() {
+        "SEEKING".to_string()
+    } else if playback.is_finished() {
+        "END".to_string()
+    } else if viewer.paused {
+        "PAUSED".to_string()
+    } else {
+        format!("{}X", viewer.speed)
+    };
+    let mut value = format!(
+        "REPLAY {} / {}  {}",
+        clock_time(playback.time()),
+        clock_time(playback.duration()),
+        status
+    );
+    if let Some(tick) = playback.desync {
+        value.push_str(&format!(
+            "  OUT OF SYNC SINCE {}",
+            clock_time(playback.time_at(tick))
+        ));
+    }
+    value.push_str(
+        "\nSPACE - PAUSE  1-4 - SPEED  . - STEP  LEFT/RIGHT, PGUP/PGDN - SEEK  HOME - RESTART  ESC - EXIT",
+    );
+    if hud.sections.first().unwrap().value != value {
+        hud.sections.first_mut().unwrap().value = value;
+    }
+}
+
+fn update_keys(
+    playback: Option<Res<Playback>>,
+    state: Res<State<GameState>>,
+    mut q: Query<(&mut Visibility, &mut Text), With<KeysText>>,
+) {
+    let (mut visibility, mut keys) = q.single_mut();
+    visibility.is_visible = playback.is_some();
+    let playback = match playback {
+        Some(playback) => playback,
+        None => return,
+    };
+
+    // The inputs only mean something while the ships are controlled.
+    let inputs = match state.current() {
+        GameState::Game if !playback.is_rewinding() => playback.inputs(),
+        _ => [NetInput(0); MAX_PLAYERS],
+    };
+    let mut sections = keys.sections.iter_mut();
+    for input in inputs {
+        sections.next();
+        for (bit, _) in KEYS {
+            let alpha = if input.has(bit) { 1.0 } else { KEY_UP_ALPHA };
+            sections.next().unwrap().style.color.set_a(alpha);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::score::Score;
+    use crate::verify;
+    use bevy::input::keyboard::KeyboardInput;
+    use bevy::input::ButtonState;
+    use std::path::Path;
+
+    const REPLAY: &str = "corpus/stage-normal-seed-7.ron";
+    const MAX_FRAMES: u32 = 10000;
+
+    fn tap(app: &mut App, key: KeyCode) {
+        for state in [ButtonState::Pressed, ButtonState::Released] {
+            app.world
+                .resource_mut::<Events<KeyboardInput>>()
+                .send(KeyboardInput {
+                    scan_code: 0,
+                    key_code: Some(key),
+                    state,
+                });
+            app.update();
+        }
+    }
+
+    fn playback(app: &App) -> &Playback {
+        return app.world.resource::<Playback>();
+    }
+
+    // Returns how many frames it took.
+    fn play_to_end(app: &mut App) -> u32 {
+        let mut frames = 0;
+        while !playback(app).is_finished() {
+            assert!(frames < MAX_FRAMES, "the replay does not go on");
+            app.update();
+            frames += 1;
+        }
+        return frames;
+    }
+
+    #[test]
+    fn seeking_back_plays_the_same() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(REPLAY);
+        let replay = Replay::load_file(&path).unwrap();
+        let score = replay.score;
+
+        let mut app = verify::headless_app();
+        app.add_plugin(ViewerPlugin);
+        let mut frames = 0;
+        while *app.world.resource::<State<GameState>>().current() == GameState::Loading {
+            assert!(frames < MAX_FRAMES, "the assets are not loaded");
+            app.update();
+            frames += 1;
+        }
+        app.world.insert_resource(Playback::new(replay));
+        app.world
+            .resource_mut::<Snapshots>()
+            .start(SNAPSHOT_PERIOD, None);
+
+        // Much further than the replay goes.
+        tap(&mut app, KeyCode::PageUp);
+        play_to_end(&mut app);
+        assert_eq!(playback(&app).desync, None);
+        assert_eq!(app.world.resource::<Score>().score, score);
+        let end = playback(&app).tick;
+
+        // Once from the end and once more from the game.
+        tap(&mut app, KeyCode::Left);
+        tap(&mut app, KeyCode::Left);
+        assert!(!playback(&app).is_rewinding());
+        assert!(playback(&app).tick > SNAPSHOT_PERIOD as usize);
+        assert!(playback(&app).tick < end);
+        tap(&mut app, KeyCode::PageUp);
+        let frames = play_to_end(&mut app);
+        assert!(frames < 10, "the ticks are not simulated together");
+
+        assert_eq!(playback(&app).desync, None);
+        assert_eq!(playback(&app).tick, end);
+        assert_eq!(app.world.resource::<Score>().score, score);
+        assert_eq!(
+            *app.world.resource::<State<GameState>>().current(),
+            GameState::GameOver
+        );
+    }
+}
@@ -0,0 +1,13 @@
+#!/usr/bin/env bash
+# Checks that the replays in the given directory (the corpus by default) still play out the same.
+cd "$(dirname -- "${BASH_SOURCE[0]}")"
+
+DIR="${1:-corpus}"
+cargo build --release || exit 2
+
+RESULT=0
+for FILE in "$DIR"/*.ron
+do
+    CARGO_MANIFEST_DIR="$PWD" target/release/flyandshoot --verify-replay "$FILE" || RESULT=1
+done
+exit $RESULT